@@ -0,0 +1,416 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use crypto::hmac::Hmac;
+use crypto::pbkdf2::pbkdf2;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use iron::prelude::*;
+use iron::method::Method;
+use iron::status;
+use iron::typemap::Key;
+use iron::BeforeMiddleware;
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::hex::{FromHex, ToHex};
+use config::AuthConfig;
+
+// GET routes that change state or name operators and their calls, and therefore need the operator role
+const OPERATOR_GET_PATHS: &'static [&'static str] = &["start/group", "stop/group", "audit"];
+// POST endpoints that don't change anything
+const READ_ONLY_POST_PATHS: &'static [&'static str] = &["simulate"];
+// endpoints reachable without credentials, e.g. for load balancer health checks
+const PUBLIC_PATHS: &'static [&'static str] = &["admin/ping"];
+// length of the derived key stored in the users file
+const PASSWORD_HASH_LENGTH: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Role {
+    ReadOnly,
+    Operator,
+}
+
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Request extension holding the authenticated caller.
+pub struct AuthIdentity;
+
+impl Key for AuthIdentity {
+    type Value = Identity;
+}
+
+struct User {
+    iterations: u32,
+    salt: String,
+    password_hash: Vec<u8>,
+    role: Role,
+}
+
+enum Credentials {
+    Tokens(HashMap<String, Identity>),
+    Users(HashMap<String, User>),
+}
+
+pub struct AuthMiddleware {
+    credentials: Credentials,
+}
+
+#[derive(Debug)]
+struct AuthError(String);
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for AuthError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Role {
+    fn from_str(role: &str) -> Option<Role> {
+        match role {
+            "read-only" | "readonly" | "read_only" => Some(Role::ReadOnly),
+            "operator" => Some(Role::Operator),
+            _ => None,
+        }
+    }
+}
+
+/// Hash stored in the users file for basic authentication, hex encoded pbkdf2-hmac-sha256 of the
+/// password with the given salt and number of iterations.
+pub fn hash_password(salt: &str, password: &str, iterations: u32) -> String {
+    derive_key(salt, password, iterations).to_hex()
+}
+
+fn derive_key(salt: &str, password: &str, iterations: u32) -> Vec<u8> {
+    let mut mac = Hmac::new(Sha256::new(), password.as_bytes());
+    let mut result = vec![0; PASSWORD_HASH_LENGTH];
+    pbkdf2(&mut mac, salt.as_bytes(), iterations, &mut result);
+    result
+}
+
+impl AuthMiddleware {
+    /// Builds the middleware from the `api.auth` config section, `None` if
+    /// authentication is disabled. Fails if the tokens or users file can't be read.
+    pub fn from_config(auth: &AuthConfig) -> Result<Option<AuthMiddleware>, String> {
+        let credentials = match &*auth.auth_type {
+            "token" => Credentials::Tokens(try!(read_tokens_file(&auth.tokens_file))),
+            "basic" => Credentials::Users(try!(read_users_file(&auth.users_file))),
+            // anything else was rejected when the config got loaded
+            _ => return Ok(None),
+        };
+
+        Ok(Some(AuthMiddleware { credentials: credentials }))
+    }
+
+    fn authenticate(&self, header: &str) -> Option<Identity> {
+        match self.credentials {
+            Credentials::Tokens(ref tokens) => {
+                if !header.starts_with("Bearer ") {
+                    return None;
+                }
+                let token = header["Bearer ".len()..].trim();
+                tokens.iter()
+                    .find(|&(known, _)| fixed_time_eq(known.as_bytes(), token.as_bytes()))
+                    .map(|(_, identity)| identity.clone())
+            }
+            Credentials::Users(ref users) => {
+                if !header.starts_with("Basic ") {
+                    return None;
+                }
+                let decoded = match header["Basic ".len()..].trim().from_base64() {
+                    Ok(decoded) => String::from_utf8_lossy(&decoded).into_owned(),
+                    Err(_) => return None,
+                };
+                let mut elmts = decoded.splitn(2, ':');
+                let name = elmts.next().unwrap_or("");
+                let password = elmts.next().unwrap_or("");
+
+                match users.get(name) {
+                    Some(user) => {
+                        let hash = derive_key(&user.salt, password, user.iterations);
+                        match fixed_time_eq(&hash, &user.password_hash) {
+                            true => {
+                                Some(Identity {
+                                    name: name.to_string(),
+                                    role: user.role,
+                                })
+                            }
+                            false => None,
+                        }
+                    }
+                    None => None,
+                }
+            }
+        }
+    }
+
+    fn challenge(&self) -> &'static str {
+        match self.credentials {
+            Credentials::Tokens(_) => "Bearer realm=\"torc\"",
+            Credentials::Users(_) => "Basic realm=\"torc\"",
+        }
+    }
+}
+
+impl BeforeMiddleware for AuthMiddleware {
+    fn before(&self, request: &mut Request) -> IronResult<()> {
+        let path = request.url.path.join("/").trim_right_matches('/').to_string();
+
+        if is_exempt(&request.method, &path) {
+            return Ok(());
+        }
+
+        let header = match request.headers.get_raw("Authorization") {
+            Some(values) if values.len() > 0 => String::from_utf8_lossy(&values[0]).into_owned(),
+            _ => String::new(),
+        };
+        let identity = match self.authenticate(&header) {
+            Some(identity) => identity,
+            None => {
+                let mut error = IronError::new(AuthError("authentication required".to_string()),
+                                               (status::Unauthorized, "authentication required"));
+                error.response.headers.set_raw("WWW-Authenticate", vec![self.challenge().as_bytes().to_vec()]);
+                return Err(error);
+            }
+        };
+
+        if identity.role < required_role(&request.method, &path) {
+            log_warn!("request denied"; "identity" => identity.name, "path" => path);
+            return Err(IronError::new(AuthError(format!("{} is not allowed to access {}", identity.name, path)),
+                                      (status::Forbidden, "operator role required")));
+        }
+
+        request.extensions.insert::<AuthIdentity>(identity);
+        Ok(())
+    }
+}
+
+// CORS preflight requests never carry credentials
+fn is_exempt(method: &Method, path: &String) -> bool {
+    *method == Method::Options || PUBLIC_PATHS.iter().any(|public| path == *public)
+}
+
+fn required_role(method: &Method, path: &String) -> Role {
+    match *method {
+        Method::Get | Method::Head => {
+            match OPERATOR_GET_PATHS.iter().any(|operator_path| path == *operator_path) {
+                true => Role::Operator,
+                false => Role::ReadOnly,
+            }
+        }
+        Method::Post if READ_ONLY_POST_PATHS.iter().any(|read_only_path| path == *read_only_path) => Role::ReadOnly,
+        _ => Role::Operator,
+    }
+}
+
+fn read_lines(path: &String) -> Result<Vec<String>, String> {
+    let file = try!(File::open(path).map_err(|err| format!("can't open {}: {}", path, err)));
+
+    let mut result = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = try!(line.map_err(|err| format!("can't read {}: {}", path, err)));
+        let line = line.trim().to_string();
+        if !line.is_empty() && !line.starts_with('#') {
+            result.push(line);
+        }
+    }
+
+    Ok(result)
+}
+
+fn read_tokens_file(path: &String) -> Result<HashMap<String, Identity>, String> {
+    let mut result = HashMap::new();
+
+    for line in try!(read_lines(path)) {
+        let elmts: Vec<&str> = line.split_whitespace().collect();
+        if elmts.len() < 2 {
+            return Err(format!("invalid entry in {}, expected \"<token> <role> <identity>\"", path));
+        }
+        let role = try!(Role::from_str(elmts[1]).ok_or(format!("unknown role {} in {}", elmts[1], path)));
+        let name = elmts.get(2).map(|name| name.to_string()).unwrap_or(format!("token-{}", result.len()));
+
+        result.insert(elmts[0].to_string(),
+                      Identity {
+                          name: name,
+                          role: role,
+                      });
+    }
+
+    Ok(result)
+}
+
+fn read_users_file(path: &String) -> Result<HashMap<String, User>, String> {
+    let mut result = HashMap::new();
+
+    for line in try!(read_lines(path)) {
+        let elmts: Vec<&str> = line.split(':').collect();
+        if elmts.len() != 5 {
+            return Err(format!("invalid entry in {}, expected \"<user>:<iterations>:<salt>:<hash>:<role>\"", path));
+        }
+        let iterations = match elmts[1].parse::<u32>() {
+            Ok(iterations) if iterations > 0 => iterations,
+            _ => return Err(format!("invalid number of iterations {} for {} in {}", elmts[1], elmts[0], path)),
+        };
+        let password_hash = match elmts[3].from_hex() {
+            Ok(ref hash) if hash.len() == PASSWORD_HASH_LENGTH => hash.clone(),
+            _ => return Err(format!("invalid password hash for {} in {}", elmts[0], path)),
+        };
+        let role = try!(Role::from_str(elmts[4]).ok_or(format!("unknown role {} in {}", elmts[4], path)));
+
+        result.insert(elmts[0].to_string(),
+                      User {
+                          iterations: iterations,
+                          salt: elmts[2].to_string(),
+                          password_hash: password_hash,
+                          role: role,
+                      });
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use iron::method::Method;
+    use config::fixtures::write_files;
+    use super::{AuthMiddleware, Credentials, Role, hash_password, is_exempt, read_tokens_file, read_users_file,
+                required_role};
+
+    fn write_file(test: &str, lines: &[&str]) -> String {
+        let text = lines.join("\n");
+        let directory = write_files(test, &[("credentials", &text)]);
+        directory.join("credentials").to_string_lossy().into_owned()
+    }
+
+    fn role(method: Method, path: &str) -> Role {
+        required_role(&method, &path.to_string())
+    }
+
+    #[test]
+    fn reads_need_the_read_only_role() {
+        assert_eq!(role(Method::Get, "services/running"), Role::ReadOnly);
+        assert_eq!(role(Method::Head, "nodes"), Role::ReadOnly);
+        assert_eq!(role(Method::Get, "events"), Role::ReadOnly);
+        assert_eq!(role(Method::Post, "simulate"), Role::ReadOnly);
+        // only exact routes are exempt
+        assert_eq!(role(Method::Get, "start/group/extra"), Role::ReadOnly);
+    }
+
+    #[test]
+    fn changes_and_the_audit_log_need_the_operator_role() {
+        assert_eq!(role(Method::Get, "start/group"), Role::Operator);
+        assert_eq!(role(Method::Get, "stop/group"), Role::Operator);
+        assert_eq!(role(Method::Get, "audit"), Role::Operator);
+        assert_eq!(role(Method::Post, "service"), Role::Operator);
+        assert_eq!(role(Method::Post, "simulate/extra"), Role::Operator);
+        assert_eq!(role(Method::Put, "service"), Role::Operator);
+        assert_eq!(role(Method::Delete, "service"), Role::Operator);
+    }
+
+    #[test]
+    fn preflight_requests_and_ping_need_no_credentials() {
+        assert!(is_exempt(&Method::Options, &"service".to_string()));
+        assert!(is_exempt(&Method::Get, &"admin/ping".to_string()));
+        assert!(!is_exempt(&Method::Get, &"admin/ping/extra".to_string()));
+        assert!(!is_exempt(&Method::Get, &"admin/config".to_string()));
+        assert!(!is_exempt(&Method::Get, &"".to_string()));
+    }
+
+    #[test]
+    fn password_hashes_are_pbkdf2_hmac_sha256() {
+        assert_eq!(hash_password("salt", "password", 1),
+                   "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b");
+        assert_eq!(hash_password("salt", "password", 4096),
+                   "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a");
+    }
+
+    #[test]
+    fn basic_authentication_checks_the_password() {
+        let hash = hash_password("salt", "password", 1);
+        let line = format!("alice:1:salt:{}:operator", hash);
+        let path = write_file("auth-users", &["# user:iterations:salt:hash:role", "", &line[..]]);
+        let auth = AuthMiddleware { credentials: Credentials::Users(read_users_file(&path).unwrap()) };
+
+        // alice:password
+        let identity = auth.authenticate("Basic YWxpY2U6cGFzc3dvcmQ=").unwrap();
+        assert_eq!(identity.name, "alice");
+        assert_eq!(identity.role, Role::Operator);
+        // alice:wrong and bob:password
+        assert!(auth.authenticate("Basic YWxpY2U6d3Jvbmc=").is_none());
+        assert!(auth.authenticate("Basic Ym9iOnBhc3N3b3Jk").is_none());
+        assert!(auth.authenticate("Bearer YWxpY2U6cGFzc3dvcmQ=").is_none());
+        assert!(auth.authenticate("").is_none());
+    }
+
+    #[test]
+    fn malformed_users_are_rejected() {
+        let hash = hash_password("salt", "password", 1);
+        let entries = [format!("alice:1:salt:{}", hash),
+                       format!("alice:0:salt:{}:operator", hash),
+                       format!("alice:many:salt:{}:operator", hash),
+                       "alice:1:salt:abcd:operator".to_string(),
+                       "alice:1:salt:not-hex:operator".to_string(),
+                       format!("alice:1:salt:{}:admin", hash)];
+
+        for (idx, entry) in entries.iter().enumerate() {
+            let path = write_file(&format!("auth-bad-user-{}", idx), &[&entry[..]]);
+            assert!(read_users_file(&path).is_err(), "{} was accepted", entry);
+        }
+        assert!(read_users_file(&"/nonexistent/users".to_string()).is_err());
+    }
+
+    #[test]
+    fn token_authentication_looks_up_the_token() {
+        let path = write_file("auth-tokens", &["# token role identity", "t0k3n operator deploy", "r34d read-only"]);
+        let auth = AuthMiddleware { credentials: Credentials::Tokens(read_tokens_file(&path).unwrap()) };
+
+        let identity = auth.authenticate("Bearer t0k3n").unwrap();
+        assert_eq!(identity.name, "deploy");
+        assert_eq!(identity.role, Role::Operator);
+        let identity = auth.authenticate("Bearer r34d").unwrap();
+        assert_eq!(identity.name, "token-1");
+        assert_eq!(identity.role, Role::ReadOnly);
+        assert!(auth.authenticate("Bearer unknown").is_none());
+        assert!(auth.authenticate("Basic t0k3n").is_none());
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let path = write_file("auth-bad-token-role", &["t0k3n admin deploy"]);
+        assert!(read_tokens_file(&path).is_err());
+        let path = write_file("auth-bad-token-fields", &["t0k3n"]);
+        assert!(read_tokens_file(&path).is_err());
+    }
+}
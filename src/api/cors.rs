@@ -0,0 +1,158 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use iron::prelude::*;
+use iron::method::Method;
+use iron::status;
+use iron::AfterMiddleware;
+use config::CorsConfig;
+
+// without an api.cors section only these routes answer cross origin requests, as they always did
+const LEGACY_PATHS: &'static [&'static str] = &["service"];
+const LEGACY_METHODS: &'static [&'static str] = &["GET"];
+
+#[derive(Clone, Debug)]
+pub struct CorsMiddleware {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<String>,
+    allowed_headers: Vec<String>,
+    max_age_in_seconds: u64,
+    // routes the headers are added to, all of them if empty
+    paths: Vec<String>,
+}
+
+impl CorsMiddleware {
+    /// Without an `api.cors` section any origin is allowed to `GET /service`
+    /// only, which is what it always did. With one, only the listed origins are
+    /// allowed, on every route.
+    pub fn from_config(cors: &Option<CorsConfig>) -> CorsMiddleware {
+        match *cors {
+            Some(ref cors) => {
+                CorsMiddleware {
+                    allowed_origins: cors.allowed_origins.clone(),
+                    allowed_methods: cors.allowed_methods.clone(),
+                    allowed_headers: cors.allowed_headers.clone(),
+                    max_age_in_seconds: cors.max_age_in_seconds,
+                    paths: vec![],
+                }
+            }
+            None => {
+                CorsMiddleware {
+                    allowed_origins: vec!["*".to_string()],
+                    allowed_methods: to_strings(LEGACY_METHODS),
+                    allowed_headers: vec![],
+                    max_age_in_seconds: 0,
+                    paths: to_strings(LEGACY_PATHS),
+                }
+            }
+        }
+    }
+
+    fn applies_to(&self, request: &Request) -> bool {
+        let path = request.url.path.join("/");
+        self.paths.is_empty() || self.paths.iter().any(|allowed| *allowed == path)
+    }
+
+    // e.g. a `DELETE /service` doesn't get to be read cross origin just because `GET` may be
+    fn allows_method(&self, request: &Request) -> bool {
+        let method = request.method.to_string();
+        self.allowed_methods.iter().any(|allowed| allowed.to_uppercase() == method)
+    }
+
+    fn allowed_origin(&self, request: &Request) -> Option<String> {
+        if self.allowed_origins.iter().any(|origin| origin == "*") {
+            return Some("*".to_string());
+        }
+
+        let origin = match request.headers.get_raw("Origin") {
+            Some(values) if values.len() > 0 => String::from_utf8_lossy(&values[0]).into_owned(),
+            _ => return None,
+        };
+
+        match self.allowed_origins.iter().any(|allowed| *allowed == origin) {
+            true => Some(origin),
+            false => None,
+        }
+    }
+
+    fn add_headers(&self, request: &Request, response: &mut Response) {
+        if let Some(origin) = self.allowed_origin(request) {
+            if origin != "*" {
+                response.headers.set_raw("Vary", vec![b"Origin".to_vec()]);
+            }
+            response.headers.set_raw("Access-Control-Allow-Origin", vec![origin.into_bytes()]);
+        }
+    }
+
+    fn preflight(&self, request: &Request) -> Response {
+        let mut response = Response::with(status::NoContent);
+        let methods = self.allowed_methods.join(", ");
+
+        response.headers.set_raw("Allow", vec![methods.clone().into_bytes()]);
+        if self.allowed_origin(request).is_some() {
+            response.headers.set_raw("Access-Control-Allow-Methods", vec![methods.into_bytes()]);
+            response.headers.set_raw("Access-Control-Allow-Headers",
+                                     vec![self.allowed_headers.join(", ").into_bytes()]);
+            response.headers.set_raw("Access-Control-Max-Age",
+                                     vec![self.max_age_in_seconds.to_string().into_bytes()]);
+        }
+        self.add_headers(request, &mut response);
+
+        response
+    }
+}
+
+impl AfterMiddleware for CorsMiddleware {
+    fn after(&self, request: &mut Request, mut response: Response) -> IronResult<Response> {
+        if !self.applies_to(request) {
+            return Ok(response);
+        }
+        if request.method == Method::Options {
+            return Ok(self.preflight(request));
+        }
+
+        if self.allows_method(request) {
+            self.add_headers(request, &mut response);
+        }
+        Ok(response)
+    }
+
+    // also runs for requests rejected earlier in the chain, e.g. by authentication,
+    // so browsers get to see those errors too
+    fn catch(&self, request: &mut Request, mut error: IronError) -> IronResult<Response> {
+        if !self.applies_to(request) {
+            return Err(error);
+        }
+        if request.method == Method::Options {
+            return Ok(self.preflight(request));
+        }
+
+        if self.allows_method(request) {
+            self.add_headers(request, &mut error.response);
+        }
+        Err(error)
+    }
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
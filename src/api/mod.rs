@@ -21,5 +21,9 @@
 // THE SOFTWARE.
 
 pub use self::run_api::run_api;
+pub use self::auth::{Identity, Role, hash_password};
 
 mod run_api;
+mod auth;
+mod cors;
+mod tls;
@@ -20,20 +20,56 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use state::StateManager;
-use iron::mime::{Mime, SubLevel, TopLevel};
-use hyper::header::AccessControlAllowOrigin;
+use state::{self, Event, EventFilter, StateManager, TaskState};
+use iron::mime::{Attr, Mime, SubLevel, TopLevel, Value};
+use iron::response::{ResponseBody, WriteBody};
+use hyper::header::{CacheControl, CacheDirective};
 use iron::prelude::*;
 use iron::status;
 use router::Router;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::process;
 use std::sync::Mutex;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::UTC;
 use rustc_serialize::json;
-use utils::read_task;
+use utils::Task;
+use config::{Config, ConfigChanges, DependencyGraph, load_service, load_workflow, start_order, start_tiers};
+use cron::describe_schedules;
+use scheduler::simulate_placement;
+use workflow::{define_workflow, get_run, list_runs, list_workflows, remove_workflow, retry_step, start_workflow};
+use metrics;
+use audit::{AuditQuery, query_audit_log, record_audit_entry, set_audit_log};
+use super::auth::{AuthIdentity, AuthMiddleware};
+use super::tls::{SERVER_THREADS, serve_http, serve_https};
+use super::cors::CorsMiddleware;
+
+const EVENTS_KEEP_ALIVE_IN_SECONDS: u64 = 15;
+const STOP_TIER_TIMEOUT_IN_SECONDS: u64 = 60;
+// every /events client holds a server thread for as long as it is connected,
+// keep half of the pool free for the other requests
+const MAX_EVENT_SUBSCRIBERS: usize = SERVER_THREADS / 2;
+
+static EVENT_SUBSCRIBERS: AtomicUsize = ATOMIC_USIZE_INIT;
 
 pub fn run_api(state_manager: &StateManager) {
-    println!("api starting");
+    log_info!("api starting");
     state_manager.send_ping();
 
+    let api_config = state_manager.get_config().api;
+    let audit_config = &api_config.audit;
+    if audit_config.path.is_empty() {
+        log_warn!("audit log disabled, no api.audit.path configured");
+    } else {
+        set_audit_log(audit_config.path.clone(),
+                      Some(audit_config.max_size_in_bytes),
+                      Some(audit_config.max_files));
+    }
+
     let mut router = Router::new();
     router.get("/admin/ping", handle_ping);
 
@@ -41,6 +77,10 @@ pub fn run_api(state_manager: &StateManager) {
     router.get("/nodes",
                move |_r: &mut Request| handle_nodes(&nodes_state_manager));
 
+    let node_by_name_state_manager = Mutex::new(state_manager.clone());
+    router.get("/nodes/:name",
+               move |request: &mut Request| handle_node_by_name(&node_by_name_state_manager, request));
+
     let services_metered_state_manager = Mutex::new(state_manager.clone());
     router.get("/services/metered",
                move |_r: &mut Request| handle_services_metered(&services_metered_state_manager));
@@ -49,6 +89,10 @@ pub fn run_api(state_manager: &StateManager) {
     router.get("/services/running",
                move |_r: &mut Request| handle_services_running(&services_running_state_manager));
 
+    let service_by_name_state_manager = Mutex::new(state_manager.clone());
+    router.get("/services/:name",
+               move |request: &mut Request| handle_service_by_name(&service_by_name_state_manager, request));
+
     let service_state_manager = Mutex::new(state_manager.clone());
     router.get("/service",
                move |request: &mut Request| handle_service(&service_state_manager, request));
@@ -61,8 +105,106 @@ pub fn run_api(state_manager: &StateManager) {
     router.get("/start/group",
                move |request: &mut Request| handle_start_service_group(&start_service_group_state_manager, request));
 
-    println!("API Server listening at: 3005");
-    Iron::new(router).http("0.0.0.0:3005").unwrap();
+    let events_state_manager = Mutex::new(state_manager.clone());
+    router.get("/events",
+               move |request: &mut Request| handle_events(&events_state_manager, request));
+
+    let stop_service_group_state_manager = Mutex::new(state_manager.clone());
+    router.get("/stop/group",
+               move |request: &mut Request| handle_stop_service_group(&stop_service_group_state_manager, request));
+
+    router.get("/audit", handle_audit);
+
+    let reload_state_manager = Mutex::new(state_manager.clone());
+    router.post("/config/reload",
+                move |request: &mut Request| handle_config_reload(&reload_state_manager, request));
+
+    let simulate_state_manager = Mutex::new(state_manager.clone());
+    router.get("/simulate",
+               move |request: &mut Request| handle_simulate(&simulate_state_manager, request));
+
+    let simulate_spec_state_manager = Mutex::new(state_manager.clone());
+    router.post("/simulate",
+                move |request: &mut Request| handle_simulate_spec(&simulate_spec_state_manager, request));
+
+    let jobs_state_manager = Mutex::new(state_manager.clone());
+    router.get("/jobs",
+               move |request: &mut Request| handle_jobs(&jobs_state_manager, request));
+
+    let schedules_state_manager = Mutex::new(state_manager.clone());
+    router.get("/schedules",
+               move |request: &mut Request| handle_schedules(&schedules_state_manager, request));
+
+    let schedule_by_name_state_manager = Mutex::new(state_manager.clone());
+    router.get("/schedules/:name",
+               move |request: &mut Request| handle_schedule_by_name(&schedule_by_name_state_manager, request));
+
+    let workflows_state_manager = Mutex::new(state_manager.clone());
+    router.get("/workflows",
+               move |_r: &mut Request| handle_workflows(&workflows_state_manager));
+
+    let define_workflow_state_manager = Mutex::new(state_manager.clone());
+    router.post("/workflows",
+                move |request: &mut Request| handle_define_workflow(&define_workflow_state_manager, request));
+
+    router.delete("/workflows/:name", handle_remove_workflow);
+
+    let start_workflow_state_manager = Mutex::new(state_manager.clone());
+    router.post("/workflows/:name/runs",
+                move |request: &mut Request| handle_start_workflow(&start_workflow_state_manager, request));
+
+    router.get("/workflow-runs", handle_workflow_runs);
+    router.get("/workflow-runs/:id", handle_workflow_run);
+
+    let retry_step_state_manager = Mutex::new(state_manager.clone());
+    router.post("/workflow-runs/:id/steps/:step/retry",
+                move |request: &mut Request| handle_retry_step(&retry_step_state_manager, request));
+
+    let graph_state_manager = Mutex::new(state_manager.clone());
+    router.get("/graph",
+               move |request: &mut Request| handle_graph(&graph_state_manager, request));
+
+    let metrics_state_manager = Mutex::new(state_manager.clone());
+    router.get("/metrics",
+               move |_r: &mut Request| handle_metrics(&metrics_state_manager));
+
+    let mut chain = Chain::new(router);
+    match AuthMiddleware::from_config(&api_config.auth) {
+        Ok(Some(auth)) => {
+            chain.link_before(auth);
+        }
+        Ok(None) => log_warn!("api authentication disabled, no api.auth configured"),
+        Err(error_msg) => {
+            log_error!("invalid api.auth"; "error" => error_msg);
+            exit_without_api();
+        }
+    }
+    chain.link_after(CorsMiddleware::from_config(&api_config.cors));
+
+    let address = format!("{}:{}", api_config.listen_address, api_config.port);
+
+    match api_config.tls {
+        Some(tls_config) => {
+            log_info!("API server listening"; "address" => address, "tls" => true,
+                      "client_certificates" => !tls_config.client_ca.is_empty());
+            if let Err(error_msg) = serve_https(chain, &address, tls_config) {
+                log_error!("can't serve the api"; "address" => address, "error" => error_msg);
+                exit_without_api();
+            }
+        }
+        None => {
+            log_info!("API server listening"; "address" => address, "tls" => false);
+            if let Err(error_msg) = serve_http(chain, &address) {
+                log_error!("can't serve the api"; "address" => address, "error" => error_msg);
+                exit_without_api();
+            }
+        }
+    }
+}
+
+// the api runs in a thread of its own, the scheduler can't be managed without it
+fn exit_without_api() -> ! {
+    process::exit(1);
 }
 
 
@@ -71,6 +213,65 @@ struct SimpleResponse {
     result: String,
 }
 
+#[derive(Clone, Debug, RustcEncodable)]
+struct ReloadResponse {
+    result: String,
+    changes: Option<ConfigChanges>,
+    errors: Vec<String>,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+struct ErrorsResponse {
+    result: String,
+    errors: Vec<String>,
+}
+
+struct EventStream {
+    events: Receiver<Event>,
+    _slot: SubscriberSlot,
+}
+
+// a reserved /events subscriber slot, given back when the stream is dropped
+struct SubscriberSlot;
+
+impl SubscriberSlot {
+    fn reserve() -> Option<SubscriberSlot> {
+        match EVENT_SUBSCRIBERS.fetch_add(1, Ordering::SeqCst) < MAX_EVENT_SUBSCRIBERS {
+            true => Some(SubscriberSlot),
+            false => {
+                EVENT_SUBSCRIBERS.fetch_sub(1, Ordering::SeqCst);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for SubscriberSlot {
+    fn drop(&mut self) {
+        EVENT_SUBSCRIBERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WriteBody for EventStream {
+    fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+        try!(res.write_all(b": connected\n\n"));
+        try!(res.flush());
+
+        loop {
+            match self.events.recv_timeout(Duration::from_secs(EVENTS_KEEP_ALIVE_IN_SECONDS)) {
+                Ok(event) => {
+                    let data = json::encode(&event).unwrap();
+                    try!(write!(res, "id: {}\nevent: {}\ndata: {}\n\n", event.id, event.kind.name(), data));
+                }
+                // comment lines keep proxies from closing the stream and let us notice gone clients
+                Err(RecvTimeoutError::Timeout) => try!(res.write_all(b": keep-alive\n\n")),
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            try!(res.flush());
+        }
+    }
+}
+
 fn handle_ping(_request: &mut Request) -> IronResult<Response> {
     Ok(Response::with((status::Ok, "pong")))
 }
@@ -89,6 +290,19 @@ fn handle_nodes(state_manager: &Mutex<StateManager>) -> IronResult<Response> {
     Ok(Response::with((content_type, status::Ok, json::encode(&result).unwrap())))
 }
 
+fn handle_node_by_name(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let name = request.extensions.get::<Router>().unwrap().find("name").unwrap_or("").to_string();
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+
+    match state_manager.lock().unwrap().request_node(name) {
+        Some(node) => Ok(Response::with((content_type, status::Ok, json::encode(&node).unwrap()))),
+        None => {
+            let response = SimpleResponse { result: "unknown_node".to_string() };
+            Ok(Response::with((content_type, status::NotFound, json::encode(&response).unwrap())))
+        }
+    }
+}
+
 fn handle_services_metered(state_manager: &Mutex<StateManager>) -> IronResult<Response> {
     let tasks = state_manager.lock().unwrap().request_list_running_tasks();
     let mut result = vec![];
@@ -117,97 +331,600 @@ fn handle_services_running(state_manager: &Mutex<StateManager>) -> IronResult<Re
     Ok(Response::with((content_type, status::Ok, json::encode(&result).unwrap())))
 }
 
+// the task with the reasons it hasn't been placed yet while it's Requested
+fn handle_service_by_name(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let name = request.extensions.get::<Router>().unwrap().find("name").unwrap_or("").to_string();
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+
+    match state_manager.lock().unwrap().request_task(name) {
+        Some(task) => Ok(Response::with((content_type, status::Ok, json::encode(&task).unwrap()))),
+        None => {
+            let response = SimpleResponse { result: "unknown_service".to_string() };
+            Ok(Response::with((content_type, status::NotFound, json::encode(&response).unwrap())))
+        }
+    }
+}
+
 fn handle_service(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
-    let url = request.url.clone().into_generic_url();
     let mut result = "".to_string();
-    let query: String = match url.query {
-        Some(q) => q.clone(),
-        None => "".to_string(),
-    };
 
-    if !query.is_empty() && query.starts_with("id=") {
-        let (_, id) = query.split_at(3);
-        if !id.is_empty() {
-            result = state_manager.lock().unwrap().request_task_name_by_id(id.to_string()).clone();
-        }
+    if let Some(id) = read_query_params(request).get("id") {
+        result = state_manager.lock().unwrap().request_task_name_by_id(id.to_string()).clone();
     }
 
     let response = SimpleResponse { result: result };
     let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
 
-    let mut res = Response::with((content_type, status::Ok, json::encode(&response).unwrap()));
-    res.headers.set(AccessControlAllowOrigin::Any);
-    Ok(res)
+    Ok(Response::with((content_type, status::Ok, json::encode(&response).unwrap())))
 }
 
 fn handle_service_delete(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
-    let url = request.url.clone().into_generic_url();
-    let query: String = match url.query {
-        Some(q) => q.clone(),
-        None => "".to_string(),
-    };
+    let params = read_query_params(request);
 
-    if !query.is_empty() && query.starts_with("name=") {
-        let (_, name) = query.split_at(5);
-        if !name.is_empty() {
-            state_manager.lock().unwrap().send_kill_task_by_name(name.to_string());
-        }
+    let mut outcome = "rejected";
+    let mut target = "".to_string();
+
+    if let Some(name) = params.get("name") {
+        state_manager.lock().unwrap().send_kill_task_by_name(name.to_string());
+        outcome = "requested";
+        target = name.to_string();
     }
 
+    audit(request, "kill_service", target, outcome);
+
     let response = SimpleResponse { result: "done".to_string() };
     let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
     Ok(Response::with((content_type, status::Ok, json::encode(&response).unwrap())))
 }
 
 fn handle_start_service_group(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
-    let url = request.url.clone().into_generic_url();
-    let query: String = match url.query {
-        Some(q) => q.clone(),
-        None => "".to_string(),
-    };
-
-    if !query.is_empty() && query.starts_with("name=") {
-        let (_, name) = query.split_at(5);
-        if !name.is_empty() {
-            let config = state_manager.lock().unwrap().get_yaml();
-
-            let service_groups = config["api"]["service-groups"].as_vec().unwrap();
-            for service_group in service_groups {
-                if service_group["name"].as_str().unwrap().to_string() == name {
-                    let services = service_group["services"].as_vec().unwrap();
-                    for service in services {
-                        let task = read_task(service);
-                        for cnt in 0..task.number_of_instances {
-                            let mut task_name = task.name.clone();
-                            if task.number_of_instances > 1 {
-                                task_name = format!("{}-{}", task_name, cnt);
-                            }
-
-                            state_manager.lock().unwrap().send_start_task(&task_name,
-                                                                          &task.image,
-                                                                          &task.node_name,
-                                                                          &task.node_type,
-                                                                          &task.node_function,
-                                                                          &task.dependent_service,
-                                                                          &task.arguments,
-                                                                          &task.parameters,
-                                                                          &task.memory,
-                                                                          &task.cpu,
-                                                                          &task.volumes,
-                                                                          &task.privileged,
-                                                                          &task.sla,
-                                                                          &task.is_metered,
-                                                                          &false,
-                                                                          &task.is_job,
-                                                                          &task.network_type)
-                        }
-                    }
+    let params = read_query_params(request);
+
+    let mut outcome = "rejected";
+    let mut target = "".to_string();
+
+    if let Some(name) = params.get("name") {
+        outcome = "unknown_group";
+        target = name.to_string();
+        let config = state_manager.lock().unwrap().get_config();
+
+        if let Some(service_group) = config.get_service_group(name) {
+            outcome = "ok";
+            // dependencies first, tasks still wait for them when offers come in
+            for task in &start_order(&service_group.services) {
+                // scheduled jobs run when their schedule says so
+                if task.schedule.is_some() {
+                    continue;
+                }
+                for (instance, task_name) in instance_names(task).into_iter().enumerate() {
+                    state_manager.lock().unwrap().send_start_task(&task_name,
+                                                                  &(instance as i64),
+                                                                  &task.image,
+                                                                  &task.node_name,
+                                                                  &task.node_type,
+                                                                  &task.node_function,
+                                                                  &task.dependent_service,
+                                                                  &task.dependencies,
+                                                                  &task.health_check,
+                                                                  &task.arguments,
+                                                                  &task.parameters,
+                                                                  &task.memory,
+                                                                  &task.cpu,
+                                                                  &task.volumes,
+                                                                  &task.privileged,
+                                                                  &task.sla,
+                                                                  &task.is_metered,
+                                                                  &false,
+                                                                  &task.is_job,
+                                                                  &task.max_retries,
+                                                                  &task.network_type,
+                                                                  &task.constraints)
                 }
             }
         }
     }
 
+    audit(request, "start_group", target, outcome);
+
+    let response = SimpleResponse { result: "done".to_string() };
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    Ok(Response::with((content_type, status::Ok, json::encode(&response).unwrap())))
+}
+
+fn handle_stop_service_group(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let params = read_query_params(request);
+
+    let mut outcome = "rejected";
+    let mut target = "".to_string();
+
+    if let Some(name) = params.get("name") {
+        outcome = "unknown_group";
+        target = name.to_string();
+        let config = state_manager.lock().unwrap().get_config();
+
+        if let Some(service_group) = config.get_service_group(name) {
+            outcome = "requested";
+            let tiers = start_tiers(&service_group.services);
+            let stop_state_manager = state_manager.lock().unwrap().clone();
+            thread::spawn(move || stop_in_tiers(&stop_state_manager, tiers));
+        }
+    }
+
+    audit(request, "stop_group", target, outcome);
+
     let response = SimpleResponse { result: "done".to_string() };
     let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
     Ok(Response::with((content_type, status::Ok, json::encode(&response).unwrap())))
 }
+
+// services go down before the ones they depend on, a tier is only stopped once
+// every task of the tier before it stopped or STOP_TIER_TIMEOUT_IN_SECONDS passed
+fn stop_in_tiers(state_manager: &StateManager, tiers: Vec<Vec<Task>>) {
+    for tier in tiers.iter().rev() {
+        let task_names: Vec<String> = tier.iter().flat_map(|task| instance_names(task)).collect();
+        for task_name in &task_names {
+            state_manager.send_kill_task_by_name(task_name.clone());
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(STOP_TIER_TIMEOUT_IN_SECONDS);
+        loop {
+            let running: Vec<&String> = task_names.iter()
+                .filter(|task_name| {
+                    match state_manager.request_task_state((*task_name).clone()) {
+                        TaskState::Running | TaskState::Accepted => true,
+                        _ => false,
+                    }
+                })
+                .collect();
+
+            if running.is_empty() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                log_warn!("tasks still running, stopping the next tier anyway";
+                          "tasks" => running.iter().map(|task_name| task_name.as_str()).collect::<Vec<&str>>().join(", "));
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+fn handle_config_reload(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let result = state_manager.lock().unwrap().reload_config();
+
+    let (response, status_code) = match result {
+        Ok(changes) => {
+            audit(request, "reload_config", "".to_string(), "ok");
+            let response = ReloadResponse {
+                result: "reloaded".to_string(),
+                changes: Some(changes),
+                errors: vec![],
+            };
+            (response, status::Ok)
+        }
+        Err(errors) => {
+            audit(request, "reload_config", "".to_string(), "invalid_config");
+            let response = ReloadResponse {
+                result: "rejected".to_string(),
+                changes: None,
+                errors: errors.errors.iter().map(|error| format!("{}", error)).collect(),
+            };
+            (response, status::UnprocessableEntity)
+        }
+    };
+
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    Ok(Response::with((content_type, status_code, json::encode(&response).unwrap())))
+}
+
+fn handle_simulate(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let params = read_query_params(request);
+    let state_manager = state_manager.lock().unwrap().clone();
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+
+    // without a service the tasks waiting for an offer get checked
+    let tasks = match params.get("service") {
+        Some(name) => {
+            let tasks = configured_tasks(&state_manager, &state_manager.get_config(), name);
+            if tasks.is_empty() {
+                let response = SimpleResponse { result: "unknown_service".to_string() };
+                return Ok(Response::with((content_type, status::NotFound, json::encode(&response).unwrap())));
+            }
+            tasks
+        }
+        None => state_manager.request_list_requested_tasks(),
+    };
+
+    let result = simulate_placement(&state_manager, &tasks);
+    Ok(Response::with((content_type, status::Ok, json::encode(&result).unwrap())))
+}
+
+fn handle_simulate_spec(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let state_manager = state_manager.lock().unwrap().clone();
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+
+    let mut body = String::new();
+    if let Err(err) = request.body.read_to_string(&mut body) {
+        let response = ErrorsResponse {
+            result: "rejected".to_string(),
+            errors: vec![format!("can't read request body: {}", err)],
+        };
+        return Ok(Response::with((content_type, status::BadRequest, json::encode(&response).unwrap())));
+    }
+
+    // JSON is valid YAML, so either works as the body
+    let task = match load_service("request", &body, &state_manager.get_config()) {
+        Ok(task) => task,
+        Err(errors) => {
+            let response = ErrorsResponse {
+                result: "rejected".to_string(),
+                errors: errors.errors.iter().map(|error| format!("{}", error)).collect(),
+            };
+            return Ok(Response::with((content_type, status::UnprocessableEntity, json::encode(&response).unwrap())));
+        }
+    };
+
+    let tasks: Vec<state::Task> = instance_names(&task)
+        .into_iter()
+        .enumerate()
+        .map(|(instance, task_name)| requested_task(&state_manager, &task, task_name, instance as i64, false))
+        .collect();
+
+    let result = simulate_placement(&state_manager, &tasks);
+    Ok(Response::with((content_type, status::Ok, json::encode(&result).unwrap())))
+}
+
+// latest runs first, `name` limits them to one job
+fn handle_jobs(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let params = read_query_params(request);
+    let job = params.get("name").cloned();
+    let limit = params.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(100);
+
+    let runs = state_manager.lock().unwrap().request_job_runs(job, limit);
+
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    Ok(Response::with((content_type, status::Ok, json::encode(&runs).unwrap())))
+}
+
+// `count` upcoming and `limit` past runs of every scheduled job
+fn handle_schedules(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let params = read_query_params(request);
+    let count = params.get("count").and_then(|count| count.parse().ok()).unwrap_or(5);
+    let limit = params.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(10);
+
+    let state_manager = state_manager.lock().unwrap().clone();
+    let schedules = describe_schedules(&state_manager, &state_manager.get_config(), None, count, limit);
+
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    Ok(Response::with((content_type, status::Ok, json::encode(&schedules).unwrap())))
+}
+
+fn handle_schedule_by_name(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let name = request.extensions.get::<Router>().unwrap().find("name").unwrap_or("").to_string();
+    let params = read_query_params(request);
+    let count = params.get("count").and_then(|count| count.parse().ok()).unwrap_or(5);
+    let limit = params.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(100);
+
+    let state_manager = state_manager.lock().unwrap().clone();
+    let schedules = describe_schedules(&state_manager, &state_manager.get_config(), Some(&name), count, limit);
+
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    match schedules.first() {
+        Some(schedule) => Ok(Response::with((content_type, status::Ok, json::encode(schedule).unwrap()))),
+        None => {
+            let response = SimpleResponse { result: "unknown_schedule".to_string() };
+            Ok(Response::with((content_type, status::NotFound, json::encode(&response).unwrap())))
+        }
+    }
+}
+
+fn handle_workflows(state_manager: &Mutex<StateManager>) -> IronResult<Response> {
+    let workflows = list_workflows(&state_manager.lock().unwrap().get_config());
+
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    Ok(Response::with((content_type, status::Ok, json::encode(&workflows).unwrap())))
+}
+
+fn handle_define_workflow(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let config = state_manager.lock().unwrap().get_config();
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+
+    let mut body = String::new();
+    if let Err(err) = request.body.read_to_string(&mut body) {
+        let response = ErrorsResponse {
+            result: "rejected".to_string(),
+            errors: vec![format!("can't read request body: {}", err)],
+        };
+        return Ok(Response::with((content_type, status::BadRequest, json::encode(&response).unwrap())));
+    }
+
+    // JSON is valid YAML, so either works as the body
+    let workflow = match load_workflow("request", &body, &config) {
+        Ok(workflow) => workflow,
+        Err(errors) => {
+            audit(request, "define_workflow", "".to_string(), "invalid_workflow");
+            let response = ErrorsResponse {
+                result: "rejected".to_string(),
+                errors: errors.errors.iter().map(|error| format!("{}", error)).collect(),
+            };
+            return Ok(Response::with((content_type, status::UnprocessableEntity, json::encode(&response).unwrap())));
+        }
+    };
+
+    let name = workflow.name.clone();
+    let (outcome, status_code) = match define_workflow(&config, workflow) {
+        Ok(()) => ("ok", status::Ok),
+        Err(outcome) => (outcome, status::Conflict),
+    };
+    audit(request, "define_workflow", name, outcome);
+
+    let response = SimpleResponse { result: outcome.to_string() };
+    Ok(Response::with((content_type, status_code, json::encode(&response).unwrap())))
+}
+
+fn handle_remove_workflow(request: &mut Request) -> IronResult<Response> {
+    let name = request.extensions.get::<Router>().unwrap().find("name").unwrap_or("").to_string();
+
+    let (outcome, status_code) = match remove_workflow(&name) {
+        true => ("ok", status::Ok),
+        false => ("unknown_workflow", status::NotFound),
+    };
+    audit(request, "remove_workflow", name, outcome);
+
+    let response = SimpleResponse { result: outcome.to_string() };
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    Ok(Response::with((content_type, status_code, json::encode(&response).unwrap())))
+}
+
+fn handle_start_workflow(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let name = request.extensions.get::<Router>().unwrap().find("name").unwrap_or("").to_string();
+    let state_manager = state_manager.lock().unwrap().clone();
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+
+    match start_workflow(&state_manager, &state_manager.get_config(), &name) {
+        Some(run) => {
+            audit(request, "start_workflow", name, "ok");
+            Ok(Response::with((content_type, status::Ok, json::encode(&run).unwrap())))
+        }
+        None => {
+            audit(request, "start_workflow", name, "unknown_workflow");
+            let response = SimpleResponse { result: "unknown_workflow".to_string() };
+            Ok(Response::with((content_type, status::NotFound, json::encode(&response).unwrap())))
+        }
+    }
+}
+
+// latest runs first, `workflow` limits them to one workflow
+fn handle_workflow_runs(request: &mut Request) -> IronResult<Response> {
+    let params = read_query_params(request);
+    let workflow = params.get("workflow").cloned();
+    let limit = params.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(100);
+
+    let runs = list_runs(workflow, limit);
+
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    Ok(Response::with((content_type, status::Ok, json::encode(&runs).unwrap())))
+}
+
+fn handle_workflow_run(request: &mut Request) -> IronResult<Response> {
+    let id = request.extensions.get::<Router>().unwrap().find("id").unwrap_or("").to_string();
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+
+    match get_run(&id) {
+        Some(run) => Ok(Response::with((content_type, status::Ok, json::encode(&run).unwrap()))),
+        None => {
+            let response = SimpleResponse { result: "unknown_run".to_string() };
+            Ok(Response::with((content_type, status::NotFound, json::encode(&response).unwrap())))
+        }
+    }
+}
+
+fn handle_retry_step(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let (id, step) = {
+        let router = request.extensions.get::<Router>().unwrap();
+        (router.find("id").unwrap_or("").to_string(), router.find("step").unwrap_or("").to_string())
+    };
+    let state_manager = state_manager.lock().unwrap().clone();
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+
+    match retry_step(&state_manager, &id, &step) {
+        Ok(run) => {
+            audit(request, "retry_step", format!("{}/{}", id, step), "ok");
+            Ok(Response::with((content_type, status::Ok, json::encode(&run).unwrap())))
+        }
+        Err(outcome) => {
+            audit(request, "retry_step", format!("{}/{}", id, step), outcome);
+            let status_code = match outcome {
+                "step_not_failed" => status::Conflict,
+                _ => status::NotFound,
+            };
+            let response = SimpleResponse { result: outcome.to_string() };
+            Ok(Response::with((content_type, status_code, json::encode(&response).unwrap())))
+        }
+    }
+}
+
+fn handle_graph(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let params = read_query_params(request);
+    let graph = DependencyGraph::from_config(&state_manager.lock().unwrap().get_config());
+
+    match params.get("format").map(|format| format.as_str()).unwrap_or("json") {
+        "json" => {
+            let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+            Ok(Response::with((content_type, status::Ok, json::encode(&graph).unwrap())))
+        }
+        "dot" => {
+            let content_type = Mime(TopLevel::Text, SubLevel::Ext("vnd.graphviz".to_string()), Vec::new());
+            Ok(Response::with((content_type, status::Ok, graph.to_dot())))
+        }
+        _ => {
+            let response = SimpleResponse { result: "unknown_format".to_string() };
+            let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+            Ok(Response::with((content_type, status::BadRequest, json::encode(&response).unwrap())))
+        }
+    }
+}
+
+fn handle_audit(request: &mut Request) -> IronResult<Response> {
+    let params = read_query_params(request);
+
+    let query = AuditQuery {
+        since: params.get("since").and_then(|since| since.parse().ok()),
+        until: params.get("until").and_then(|until| until.parse().ok()),
+        actor: params.get("actor").cloned(),
+        limit: params.get("limit").and_then(|limit| limit.parse().ok()),
+    };
+
+    let result = query_audit_log(&query);
+
+    let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+    Ok(Response::with((content_type, status::Ok, json::encode(&result).unwrap())))
+}
+
+fn handle_metrics(state_manager: &Mutex<StateManager>) -> IronResult<Response> {
+    let tasks_per_state = state_manager.lock().unwrap().request_task_state_counts();
+
+    let content_type = Mime(TopLevel::Text,
+                            SubLevel::Plain,
+                            vec![(Attr::Ext("version".to_string()), Value::Ext("0.0.4".to_string()))]);
+    Ok(Response::with((content_type, status::Ok, metrics::render(&tasks_per_state))))
+}
+
+fn handle_events(state_manager: &Mutex<StateManager>, request: &mut Request) -> IronResult<Response> {
+    let slot = match SubscriberSlot::reserve() {
+        Some(slot) => slot,
+        None => {
+            let content_type = Mime(TopLevel::Application, SubLevel::Json, Vec::new());
+            let response = SimpleResponse { result: "too_many_subscribers".to_string() };
+            return Ok(Response::with((content_type, status::ServiceUnavailable, json::encode(&response).unwrap())));
+        }
+    };
+
+    let params = read_query_params(request);
+
+    let filter = EventFilter {
+        service: params.get("service").cloned(),
+        node: params.get("node").cloned(),
+    };
+
+    // browsers send Last-Event-ID on reconnect, other clients can use the query parameter
+    let mut last_event_id: Option<u64> = params.get("last_event_id").and_then(|id| id.parse().ok());
+    if let Some(values) = request.headers.get_raw("Last-Event-ID") {
+        if let Some(value) = values.first() {
+            last_event_id = String::from_utf8_lossy(value).trim().parse().ok().or(last_event_id);
+        }
+    }
+
+    let events = state_manager.lock().unwrap().request_event_subscription(filter, last_event_id);
+
+    let content_type = Mime(TopLevel::Text, SubLevel::Ext("event-stream".to_string()), Vec::new());
+    let mut res = Response::with((content_type, status::Ok));
+    res.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+    res.body = Some(Box::new(EventStream {
+        events: events,
+        _slot: slot,
+    }));
+    Ok(res)
+}
+
+fn read_query_params(request: &Request) -> HashMap<String, String> {
+    let url = request.url.clone().into_generic_url();
+    let mut result = HashMap::new();
+
+    // query_pairs percent-decodes keys and values
+    for (key, value) in url.query_pairs().unwrap_or(vec![]) {
+        if !key.is_empty() && !value.is_empty() {
+            result.insert(key, value);
+        }
+    }
+
+    result
+}
+
+fn instance_names(task: &Task) -> Vec<String> {
+    let mut result = vec![];
+
+    for cnt in 0..task.number_of_instances {
+        let mut task_name = task.name.clone();
+        if task.number_of_instances > 1 {
+            task_name = format!("{}-{}", task_name, cnt);
+        }
+        result.push(task_name);
+    }
+
+    result
+}
+
+// the tasks a configured service or system service would be started as
+fn configured_tasks(state_manager: &StateManager, config: &Config, name: &str) -> Vec<state::Task> {
+    let mut result = vec![];
+
+    for task in config.healthcheck.system_services.iter().filter(|task| task.name == name) {
+        result.push(requested_task(state_manager, task, task.name.clone(), 0, true));
+    }
+
+    for service_group in &config.api.service_groups {
+        for task in service_group.services.iter().filter(|task| task.name == name) {
+            for (instance, task_name) in instance_names(task).into_iter().enumerate() {
+                result.push(requested_task(state_manager, task, task_name, instance as i64, false));
+            }
+        }
+    }
+
+    result
+}
+
+fn requested_task(state_manager: &StateManager,
+                  task: &Task,
+                  task_name: String,
+                  instance: i64,
+                  is_system_service: bool)
+                  -> state::Task {
+    state::Task {
+        name: task_name,
+        controller: state_manager.get_my_name(),
+        id: "".to_string(),
+        image: task.image.clone(),
+        node_name: task.node_name.clone(),
+        node_type: task.node_type.clone(),
+        node_function: task.node_function.clone(),
+        dependent_service: task.dependent_service.clone(),
+        dependencies: task.dependencies.clone(),
+        health_check: task.health_check.clone(),
+        arguments: task.arguments.clone(),
+        parameters: task.parameters.clone(),
+        memory: task.memory,
+        cpu: task.cpu,
+        volumes: task.volumes.clone(),
+        privileged: task.privileged,
+        sla: task.sla.clone(),
+        is_metered: task.is_metered,
+        is_system_service: is_system_service,
+        is_job: task.is_job,
+        max_retries: task.max_retries,
+        attempt: 0,
+        network_type: task.network_type.clone(),
+        constraints: task.constraints.clone(),
+        instance: instance,
+        ip: "".to_string(),
+        ports: vec![],
+        slave_id: "".to_string(),
+        state: TaskState::Requested,
+        healthy: None,
+        started_at: 0,
+        finished_at: 0,
+        exit_status: None,
+        last_update: UTC::now().timestamp(),
+        pending_reasons: vec![],
+    }
+}
+
+fn audit(request: &Request, action: &str, target: String, outcome: &str) {
+    let identity = match request.extensions.get::<AuthIdentity>() {
+        Some(identity) => identity.name.clone(),
+        None => "".to_string(),
+    };
+
+    record_audit_entry(request.remote_addr.to_string(), identity, action, target, outcome);
+}
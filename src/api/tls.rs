@@ -0,0 +1,174 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use hyper;
+use hyper::net::{HttpStream, Openssl, Ssl};
+use hyper::server::{Handler as HyperHandler, Request as HttpRequest, Response as HttpResponse, Server};
+use hyper::net::Fresh;
+use iron::{Handler, Protocol, Request};
+use iron::status;
+use openssl::ssl::{SSL_OP_NO_SSLV2, SSL_OP_NO_SSLV3, SSL_OP_NO_TLSV1, SSL_VERIFY_FAIL_IF_NO_PEER_CERT, SSL_VERIFY_PEER,
+                   SslContext, SslMethod};
+use openssl::x509::X509FileType;
+use config::TlsConfig;
+use utils::on_sighup;
+
+pub const SERVER_THREADS: usize = 16;
+// forward secret AEAD ciphers first, no RC4, 3DES, export or anonymous suites
+const CIPHER_LIST: &'static str = "ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256:\
+                                   ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384:\
+                                   DHE-RSA-AES128-GCM-SHA256:DHE-RSA-AES256-GCM-SHA384:\
+                                   ECDHE-ECDSA-AES128-SHA256:ECDHE-RSA-AES128-SHA256:\
+                                   ECDHE-ECDSA-AES256-SHA384:ECDHE-RSA-AES256-SHA384:\
+                                   !aNULL:!eNULL:!EXPORT:!RC4:!3DES:!MD5:!PSK";
+
+/// hyper ssl wrapper whose certificates can be swapped while the server is running.
+#[derive(Clone)]
+pub struct ReloadableSsl {
+    config: TlsConfig,
+    ssl: Arc<RwLock<Openssl>>,
+}
+
+struct IronAdapter<H: Handler> {
+    handler: H,
+    local_addr: SocketAddr,
+    protocol: Protocol,
+}
+
+impl ReloadableSsl {
+    pub fn new(config: TlsConfig) -> Result<ReloadableSsl, String> {
+        let ssl = try!(build_ssl(&config));
+        Ok(ReloadableSsl {
+            config: config,
+            ssl: Arc::new(RwLock::new(ssl)),
+        })
+    }
+
+    /// Re-reads certificate, key and CA bundle from disk. The old context stays
+    /// active if the new files can't be loaded.
+    pub fn reload(&self) -> Result<(), String> {
+        let ssl = try!(build_ssl(&self.config));
+        *self.ssl.write().unwrap() = ssl;
+        Ok(())
+    }
+}
+
+impl Ssl for ReloadableSsl {
+    type Stream = <Openssl as Ssl>::Stream;
+
+    fn wrap_client(&self, stream: HttpStream, host: &str) -> hyper::Result<Self::Stream> {
+        self.ssl.read().unwrap().wrap_client(stream, host)
+    }
+
+    fn wrap_server(&self, stream: HttpStream) -> hyper::Result<Self::Stream> {
+        self.ssl.read().unwrap().wrap_server(stream)
+    }
+}
+
+impl<H: Handler> HyperHandler for IronAdapter<H> {
+    fn handle(&self, http_req: HttpRequest, mut http_res: HttpResponse<Fresh>) {
+        match Request::from_http(http_req, self.local_addr, &self.protocol) {
+            Ok(mut request) => {
+                match self.handler.handle(&mut request) {
+                    Ok(response) => response.write_back(http_res),
+                    Err(error) => error.response.write_back(http_res),
+                }
+            }
+            Err(error_msg) => {
+                log_warn!("can't parse request"; "error" => error_msg);
+                *http_res.status_mut() = status::BadRequest;
+                let _ = http_res.send(b"bad request");
+            }
+        }
+    }
+}
+
+/// Serves `handler` over plain HTTP, with as many threads as `serve_https`.
+pub fn serve_http<H: Handler>(handler: H, address: &str) -> Result<(), String> {
+    let local_addr = try!(socket_addr(address));
+    let adapter = IronAdapter {
+        handler: handler,
+        local_addr: local_addr,
+        protocol: Protocol::Http,
+    };
+
+    let server = try!(Server::http(local_addr).map_err(|err| err.to_string()));
+    try!(server.handle_threads(adapter, SERVER_THREADS).map_err(|err| err.to_string()));
+    Ok(())
+}
+
+/// Serves `handler` over HTTPS, certificates get reloaded on SIGHUP.
+pub fn serve_https<H: Handler>(handler: H, address: &str, config: TlsConfig) -> Result<(), String> {
+    let local_addr = try!(socket_addr(address));
+    let ssl = try!(ReloadableSsl::new(config.clone()));
+
+    let reload_ssl = ssl.clone();
+    on_sighup(move || {
+        match reload_ssl.reload() {
+            Ok(_) => log_info!("reloaded api certificates"),
+            Err(error_msg) => log_error!("can't reload api certificates"; "error" => error_msg),
+        }
+    });
+
+    let adapter = IronAdapter {
+        handler: handler,
+        local_addr: local_addr,
+        protocol: Protocol::Https {
+            certificate: PathBuf::from(config.certificate.clone()),
+            key: PathBuf::from(config.key.clone()),
+        },
+    };
+
+    let server = try!(Server::https(local_addr, ssl).map_err(|err| err.to_string()));
+    try!(server.handle_threads(adapter, SERVER_THREADS).map_err(|err| err.to_string()));
+    Ok(())
+}
+
+fn socket_addr(address: &str) -> Result<SocketAddr, String> {
+    match address.to_socket_addrs().ok().and_then(|mut addrs| addrs.next()) {
+        Some(addr) => Ok(addr),
+        None => Err(format!("invalid listen address {}", address)),
+    }
+}
+
+fn build_ssl(config: &TlsConfig) -> Result<Openssl, String> {
+    // Sslv23 negotiates the highest version both sides support, only TLS 1.1 and newer are allowed
+    let mut context = try!(SslContext::new(SslMethod::Sslv23).map_err(|err| err.to_string()));
+    context.set_options(SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3 | SSL_OP_NO_TLSV1);
+    try!(context.set_cipher_list(CIPHER_LIST).map_err(|err| err.to_string()));
+    try!(context.set_certificate_file(&config.certificate, X509FileType::PEM)
+        .map_err(|err| format!("can't load certificate {}: {}", config.certificate, err)));
+    try!(context.set_private_key_file(&config.key, X509FileType::PEM)
+        .map_err(|err| format!("can't load key {}: {}", config.key, err)));
+    try!(context.check_private_key().map_err(|err| format!("certificate and key don't match: {}", err)));
+
+    if !config.client_ca.is_empty() {
+        try!(context.set_CA_file(&config.client_ca)
+            .map_err(|err| format!("can't load client ca {}: {}", config.client_ca, err)));
+        context.set_verify(SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT, None);
+    }
+
+    Ok(Openssl { context: Arc::new(context) })
+}
@@ -0,0 +1,208 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use chrono::UTC;
+use rustc_serialize::json;
+
+const DEFAULT_MAX_SIZE_IN_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_FILES: u64 = 5;
+
+lazy_static! {
+    static ref AUDIT: Arc<Mutex<Option<AuditLog>>> = {
+        Arc::new(Mutex::new(None))
+    };
+}
+
+struct AuditLog {
+    path: String,
+    max_size_in_bytes: u64,
+    max_files: u64,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct AuditEntry {
+    pub timestamp: i64,
+    pub client: String,
+    pub identity: String,
+    pub action: String,
+    pub target: String,
+    pub outcome: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditQuery {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub actor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Enables the audit log, entries get appended to `path` as one JSON document per line.
+/// Once a file grows beyond `max_size_in_bytes` it's rotated to `path.1`, `path.2`, ..
+/// keeping at most `max_files` rotated files.
+pub fn set_audit_log(path: String, max_size_in_bytes: Option<u64>, max_files: Option<u64>) {
+    let mut audit = AUDIT.lock().unwrap();
+    *audit = Some(AuditLog {
+        path: path,
+        max_size_in_bytes: max_size_in_bytes.unwrap_or(DEFAULT_MAX_SIZE_IN_BYTES),
+        max_files: max_files.unwrap_or(DEFAULT_MAX_FILES),
+    });
+}
+
+pub fn record_audit_entry(client: String, identity: String, action: &str, target: String, outcome: &str) {
+    let entry = AuditEntry {
+        timestamp: UTC::now().timestamp(),
+        client: client,
+        identity: identity,
+        action: action.to_string(),
+        target: target,
+        outcome: outcome.to_string(),
+    };
+
+    log_info!("audit";
+              "client" => entry.client,
+              "identity" => entry.identity,
+              "action" => entry.action,
+              "target" => entry.target,
+              "outcome" => entry.outcome);
+
+    let audit = AUDIT.lock().unwrap();
+    if let Some(ref audit_log) = *audit {
+        if let Err(error_msg) = audit_log.append(&entry) {
+            log_error!("can't write audit log"; "path" => audit_log.path, "error" => error_msg);
+        }
+    }
+}
+
+pub fn query_audit_log(query: &AuditQuery) -> Vec<AuditEntry> {
+    let audit = AUDIT.lock().unwrap();
+    let mut result = vec![];
+
+    if let Some(ref audit_log) = *audit {
+        // oldest rotated file first, so entries come out in chronological order
+        for idx in (0..audit_log.max_files + 1).rev() {
+            let path = audit_log.file_path(idx);
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+
+            for line in BufReader::new(file).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                match json::decode::<AuditEntry>(&line) {
+                    Ok(entry) => {
+                        if query.matches(&entry) {
+                            result.push(entry);
+                        }
+                    }
+                    Err(error_msg) => log_warn!("skipping malformed audit entry"; "path" => path, "error" => error_msg),
+                }
+            }
+        }
+    }
+
+    // the limit keeps the most recent entries
+    if let Some(limit) = query.limit {
+        if result.len() > limit {
+            let skip = result.len() - limit;
+            result = result.split_off(skip);
+        }
+    }
+
+    result
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        if let Some(since) = self.since {
+            if entry.timestamp < since {
+                return false;
+            }
+        }
+
+        if let Some(until) = self.until {
+            if entry.timestamp > until {
+                return false;
+            }
+        }
+
+        if let Some(ref actor) = self.actor {
+            if *actor != entry.identity && *actor != entry.client && !entry.client.starts_with(&format!("{}:", actor)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl AuditLog {
+    fn file_path(&self, idx: u64) -> String {
+        match idx {
+            0 => self.path.clone(),
+            _ => format!("{}.{}", self.path, idx),
+        }
+    }
+
+    fn append(&self, entry: &AuditEntry) -> Result<(), String> {
+        let line = format!("{}\n", json::encode(entry).unwrap());
+
+        let current_size = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        if current_size > 0 && current_size + line.len() as u64 > self.max_size_in_bytes {
+            try!(self.rotate());
+        }
+
+        let mut file = try!(OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| err.to_string()));
+        try!(file.write_all(line.as_bytes()).map_err(|err| err.to_string()));
+        file.flush().map_err(|err| err.to_string())
+    }
+
+    fn rotate(&self) -> Result<(), String> {
+        let oldest = self.file_path(self.max_files);
+        if Path::new(&oldest).exists() {
+            try!(fs::remove_file(&oldest).map_err(|err| err.to_string()));
+        }
+
+        for idx in (0..self.max_files).rev() {
+            let from = self.file_path(idx);
+            if Path::new(&from).exists() {
+                try!(fs::rename(&from, self.file_path(idx + 1)).map_err(|err| err.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+extern crate clap;
+extern crate yaml_rust;
+#[macro_use]
+extern crate torc_sub_scheduler;
+
+use std::io::{self, Write};
+use std::process;
+use std::thread;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use yaml_rust::YamlEmitter;
+use torc_sub_scheduler::api::run_api;
+use torc_sub_scheduler::cron::run_cron;
+use torc_sub_scheduler::health::run_health_checker;
+use torc_sub_scheduler::logging::{self, Format};
+use torc_sub_scheduler::probe::run_node_prober;
+use torc_sub_scheduler::scheduler::run_scheduler;
+use torc_sub_scheduler::state::StateManager;
+use torc_sub_scheduler::workflow::run_workflows;
+use torc_sub_scheduler::config::load_config;
+
+fn main() {
+    let config_arg = Arg::with_name("config")
+        .long("config")
+        .short("f")
+        .takes_value(true)
+        .required(true)
+        .help("Path to the sub-scheduler YAML config");
+
+    let overlay_arg = Arg::with_name("overlay")
+        .long("overlay")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("YAML file merged over the config, can be given more than once");
+
+    let matches = App::new("torc-sub-scheduler")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("ToRC sub-scheduler for Mesos")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("run")
+            .about("Registers with Mesos and starts the scheduler, API, health checker, cron and workflows")
+            .arg(Arg::with_name("master")
+                .long("master")
+                .short("m")
+                .takes_value(true)
+                .required(true)
+                .help("IP of the Mesos master"))
+            .arg(Arg::with_name("controller")
+                .long("controller")
+                .short("c")
+                .takes_value(true)
+                .required(true)
+                .help("IP of the ToRC controller"))
+            .arg(config_arg.clone())
+            .arg(overlay_arg.clone())
+            .arg(Arg::with_name("log")
+                .long("log")
+                .takes_value(true)
+                .help("Log filter, e.g. info,torc_sub_scheduler::scheduler=debug"))
+            .arg(Arg::with_name("log-format")
+                .long("log-format")
+                .takes_value(true)
+                .possible_values(&["human", "json"])
+                .help("Log output format")))
+        .subcommand(SubCommand::with_name("validate")
+            .about("Checks a config file and reports every problem found")
+            .arg(config_arg.clone())
+            .arg(overlay_arg.clone()))
+        .subcommand(SubCommand::with_name("print-config")
+            .about("Prints the effective config with defaults filled in")
+            .arg(config_arg.clone())
+            .arg(overlay_arg.clone()))
+        .get_matches();
+
+    match matches.subcommand() {
+        ("run", Some(sub_matches)) => run(sub_matches),
+        ("validate", Some(sub_matches)) => validate(sub_matches),
+        ("print-config", Some(sub_matches)) => print_config(sub_matches),
+        _ => unreachable!(),
+    }
+}
+
+fn run(matches: &ArgMatches) {
+    // flags are applied before the state manager reads the config, so they win over its logging section
+    if matches.is_present("log") || matches.is_present("log-format") {
+        let format = matches.value_of("log-format").and_then(Format::from_str);
+        if let Err(error_msg) = logging::configure(matches.value_of("log").unwrap_or("info"), format) {
+            exit_with_error(&error_msg);
+        }
+    }
+
+    let state_manager = StateManager::new(matches.value_of("master").unwrap().to_string(),
+                                          matches.value_of("controller").unwrap().to_string(),
+                                          matches.value_of("config").unwrap().to_string(),
+                                          overlays(matches));
+
+    let api_state_manager = state_manager.clone();
+    thread::Builder::new()
+        .name("api".to_string())
+        .spawn(move || run_api(&api_state_manager))
+        .unwrap();
+
+    let health_state_manager = state_manager.clone();
+    thread::Builder::new()
+        .name("health-check".to_string())
+        .spawn(move || run_health_checker(&health_state_manager))
+        .unwrap();
+
+    let cron_state_manager = state_manager.clone();
+    thread::Builder::new()
+        .name("cron".to_string())
+        .spawn(move || run_cron(&cron_state_manager))
+        .unwrap();
+
+    let workflows_state_manager = state_manager.clone();
+    thread::Builder::new()
+        .name("workflows".to_string())
+        .spawn(move || run_workflows(&workflows_state_manager))
+        .unwrap();
+
+    let probe_state_manager = state_manager.clone();
+    thread::Builder::new()
+        .name("node-prober".to_string())
+        .spawn(move || run_node_prober(&probe_state_manager))
+        .unwrap();
+
+    log_info!("starting scheduler"; "master" => state_manager.get_master_ip(), "name" => state_manager.get_my_name());
+    run_scheduler(&state_manager);
+}
+
+fn validate(matches: &ArgMatches) {
+    let config_file = matches.value_of("config").unwrap().to_string();
+    match load_config(&config_file, &overlays(matches)) {
+        Ok(_) => println!("{}: ok", config_file),
+        Err(errors) => {
+            let _ = writeln!(io::stderr(), "{}", errors);
+            process::exit(1);
+        }
+    }
+}
+
+fn print_config(matches: &ArgMatches) {
+    let config_file = matches.value_of("config").unwrap().to_string();
+    let config = match load_config(&config_file, &overlays(matches)) {
+        Ok(config) => config,
+        Err(_) => exit_with_error(&format!("{} is invalid, run validate for details", config_file)),
+    };
+
+    let mut out = String::new();
+    YamlEmitter::new(&mut out).dump(&config.to_yaml()).unwrap();
+    println!("{}", out);
+}
+
+fn overlays(matches: &ArgMatches) -> Vec<String> {
+    match matches.values_of("overlay") {
+        Some(values) => values.map(|value| value.to_string()).collect(),
+        None => vec![],
+    }
+}
+
+fn exit_with_error(error_msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "error: {}", error_msg);
+    process::exit(1);
+}
@@ -0,0 +1,537 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+extern crate clap;
+extern crate hyper;
+extern crate rustc_serialize;
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use hyper::Client;
+use hyper::client::RequestBuilder;
+use hyper::header::Headers;
+use rustc_serialize::base64::{STANDARD, ToBase64};
+use rustc_serialize::json::{self, Json};
+
+const NODE_COLUMNS: &'static [&'static str] = &["name", "ip", "node_type", "node_function", "active", "reachable", "discovered"];
+const CAPACITY_COLUMNS: &'static [&'static str] = &["resource", "total", "allocated", "free"];
+const NODE_TASK_COLUMNS: &'static [&'static str] = &["task"];
+const SERVICE_COLUMNS: &'static [&'static str] = &["name", "node_name", "ip", "state", "image"];
+const PENDING_REASON_COLUMNS: &'static [&'static str] = &["node", "rejected_by", "detail"];
+const SIMULATION_COLUMNS: &'static [&'static str] = &["node", "fits", "rejected_by", "detail"];
+const JOB_RUN_COLUMNS: &'static [&'static str] =
+    &["job", "attempt", "node", "result", "exit_status", "duration_in_seconds", "retried"];
+const SCHEDULE_COLUMNS: &'static [&'static str] =
+    &["job", "cron", "timezone", "concurrency_policy", "missed_runs", "upcoming_runs"];
+const PAST_RUN_COLUMNS: &'static [&'static str] = &["run", "scheduled_for", "action", "state", "result", "detail"];
+const WORKFLOW_COLUMNS: &'static [&'static str] = &["name", "source"];
+const WORKFLOW_RUN_COLUMNS: &'static [&'static str] = &["id", "workflow", "state", "started_at", "finished_at"];
+const STEP_RUN_COLUMNS: &'static [&'static str] =
+    &["step", "state", "task", "attempt", "exit_status", "ip", "node", "message"];
+const RESULT_COLUMNS: &'static [&'static str] = &["result"];
+
+struct ApiClient {
+    client: Client,
+    base_url: String,
+    headers: Headers,
+    json_output: bool,
+}
+
+fn main() {
+    let name_arg = Arg::with_name("name").required(true).index(1);
+
+    let matches = App::new("torcctl")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Command-line client for the ToRC sub-scheduler API")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("api")
+            .long("api")
+            .takes_value(true)
+            .default_value("http://localhost:3005")
+            .help("Base URL of the sub-scheduler API"))
+        .arg(Arg::with_name("token")
+            .long("token")
+            .takes_value(true)
+            .help("Bearer token for token authentication"))
+        .arg(Arg::with_name("user")
+            .long("user")
+            .takes_value(true)
+            .help("user:password for basic authentication"))
+        .arg(Arg::with_name("output")
+            .long("output")
+            .short("o")
+            .takes_value(true)
+            .possible_values(&["table", "json"])
+            .default_value("table")
+            .help("Output format"))
+        .subcommand(SubCommand::with_name("nodes").about("Lists active nodes"))
+        .subcommand(SubCommand::with_name("node")
+            .about("Shows a node with its capacity and tasks")
+            .arg(name_arg.clone()))
+        .subcommand(SubCommand::with_name("services")
+            .about("Lists running or metered services")
+            .arg(Arg::with_name("filter")
+                .index(1)
+                .possible_values(&["running", "metered"])
+                .default_value("running")))
+        .subcommand(SubCommand::with_name("service")
+            .about("Shows a service and why it hasn't been placed yet")
+            .arg(name_arg.clone()))
+        .subcommand(SubCommand::with_name("start-group")
+            .about("Starts all services of a service group")
+            .arg(name_arg.clone()))
+        .subcommand(SubCommand::with_name("stop-group")
+            .about("Kills all services of a service group")
+            .arg(name_arg.clone()))
+        .subcommand(SubCommand::with_name("kill")
+            .about("Kills a single service")
+            .arg(name_arg.clone()))
+        .subcommand(SubCommand::with_name("reload-config").about("Reloads the sub-scheduler config file"))
+        .subcommand(SubCommand::with_name("simulate")
+            .about("Shows on which nodes pending tasks, a configured service or a service spec would fit")
+            .arg(Arg::with_name("name").index(1).conflicts_with("file"))
+            .arg(Arg::with_name("file")
+                .long("file")
+                .short("f")
+                .takes_value(true)
+                .help("YAML or JSON file with a service definition")))
+        .subcommand(SubCommand::with_name("jobs")
+            .about("Lists the latest job runs")
+            .arg(Arg::with_name("name").index(1).help("Only runs of this job")))
+        .subcommand(SubCommand::with_name("schedules")
+            .about("Lists scheduled jobs and their upcoming runs")
+            .arg(Arg::with_name("name").index(1).help("Also shows the past runs of this job")))
+        .subcommand(SubCommand::with_name("workflows").about("Lists the workflows of the config and the api"))
+        .subcommand(SubCommand::with_name("define-workflow")
+            .about("Adds or replaces a workflow from a YAML or JSON file")
+            .arg(Arg::with_name("file").required(true).index(1)))
+        .subcommand(SubCommand::with_name("run-workflow")
+            .about("Starts a run of a workflow")
+            .arg(name_arg.clone()))
+        .subcommand(SubCommand::with_name("workflow-runs")
+            .about("Lists the latest workflow runs")
+            .arg(Arg::with_name("name").index(1).help("Only runs of this workflow")))
+        .subcommand(SubCommand::with_name("workflow-run")
+            .about("Shows the steps of a workflow run")
+            .arg(Arg::with_name("id").required(true).index(1)))
+        .subcommand(SubCommand::with_name("retry-step")
+            .about("Starts a failed step of a workflow run again")
+            .arg(Arg::with_name("id").required(true).index(1))
+            .arg(Arg::with_name("step").required(true).index(2)))
+        .subcommand(SubCommand::with_name("graph")
+            .about("Prints the service dependency graph")
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["dot", "json"])
+                .default_value("dot")))
+        .subcommand(SubCommand::with_name("events")
+            .about("Follows task and node state changes")
+            .arg(Arg::with_name("service").long("service").takes_value(true))
+            .arg(Arg::with_name("node").long("node").takes_value(true))
+            .arg(Arg::with_name("last-event-id").long("last-event-id").takes_value(true)))
+        .get_matches();
+
+    let api = ApiClient::new(&matches);
+
+    match matches.subcommand() {
+        ("nodes", Some(_)) => api.list("/nodes", NODE_COLUMNS),
+        ("services", Some(sub_matches)) => {
+            api.list(&format!("/services/{}", sub_matches.value_of("filter").unwrap()),
+                     SERVICE_COLUMNS)
+        }
+        ("node", Some(sub_matches)) => api.node(sub_matches.value_of("name").unwrap()),
+        ("service", Some(sub_matches)) => api.service(sub_matches.value_of("name").unwrap()),
+        ("start-group", Some(sub_matches)) => {
+            api.list(&format!("/start/group?name={}", percent_encode(sub_matches.value_of("name").unwrap())),
+                     RESULT_COLUMNS)
+        }
+        ("stop-group", Some(sub_matches)) => {
+            api.list(&format!("/stop/group?name={}", percent_encode(sub_matches.value_of("name").unwrap())),
+                     RESULT_COLUMNS)
+        }
+        ("kill", Some(sub_matches)) => api.kill(sub_matches.value_of("name").unwrap()),
+        ("reload-config", Some(_)) => api.reload_config(),
+        ("simulate", Some(sub_matches)) => api.simulate(sub_matches),
+        ("jobs", Some(sub_matches)) => {
+            match sub_matches.value_of("name") {
+                Some(name) => api.list(&format!("/jobs?name={}", percent_encode(name)), JOB_RUN_COLUMNS),
+                None => api.list("/jobs", JOB_RUN_COLUMNS),
+            }
+        }
+        ("schedules", Some(sub_matches)) => {
+            match sub_matches.value_of("name") {
+                Some(name) => api.schedule(name),
+                None => api.list("/schedules?count=1", SCHEDULE_COLUMNS),
+            }
+        }
+        ("workflows", Some(_)) => api.list("/workflows", WORKFLOW_COLUMNS),
+        ("define-workflow", Some(sub_matches)) => api.define_workflow(sub_matches.value_of("file").unwrap()),
+        ("run-workflow", Some(sub_matches)) => {
+            let path = format!("/workflows/{}/runs", sub_matches.value_of("name").unwrap());
+            api.workflow_run(api.client.post(&*api.url(&path)))
+        }
+        ("workflow-runs", Some(sub_matches)) => {
+            match sub_matches.value_of("name") {
+                Some(name) => api.list(&format!("/workflow-runs?workflow={}", percent_encode(name)), WORKFLOW_RUN_COLUMNS),
+                None => api.list("/workflow-runs", WORKFLOW_RUN_COLUMNS),
+            }
+        }
+        ("workflow-run", Some(sub_matches)) => {
+            let path = format!("/workflow-runs/{}", sub_matches.value_of("id").unwrap());
+            api.workflow_run(api.client.get(&*api.url(&path)))
+        }
+        ("retry-step", Some(sub_matches)) => {
+            let path = format!("/workflow-runs/{}/steps/{}/retry",
+                               sub_matches.value_of("id").unwrap(),
+                               sub_matches.value_of("step").unwrap());
+            api.workflow_run(api.client.post(&*api.url(&path)))
+        }
+        ("graph", Some(sub_matches)) => api.graph(sub_matches.value_of("format").unwrap()),
+        ("events", Some(sub_matches)) => api.events(sub_matches),
+        _ => unreachable!(),
+    }
+}
+
+impl ApiClient {
+    fn new(matches: &ArgMatches) -> ApiClient {
+        let mut headers = Headers::new();
+        if let Some(token) = matches.value_of("token") {
+            headers.set_raw("Authorization", vec![format!("Bearer {}", token).into_bytes()]);
+        } else if let Some(user) = matches.value_of("user") {
+            let credentials = user.as_bytes().to_base64(STANDARD);
+            headers.set_raw("Authorization", vec![format!("Basic {}", credentials).into_bytes()]);
+        }
+
+        ApiClient {
+            client: Client::new(),
+            base_url: matches.value_of("api").unwrap().trim_right_matches('/').to_string(),
+            headers: headers,
+            json_output: matches.value_of("output") == Some("json"),
+        }
+    }
+
+    fn list(&self, path: &str, columns: &[&str]) {
+        let body = self.send(self.client.get(&*self.url(path)));
+        self.print(&body, columns);
+    }
+
+    fn node(&self, name: &str) {
+        let body = self.send(self.client.get(&*self.url(&format!("/nodes/{}", name))));
+        self.print(&body, NODE_COLUMNS);
+        if self.json_output {
+            return;
+        }
+
+        if let Some(&Json::Array(ref capacity)) = body.find("capacity") {
+            if !capacity.is_empty() {
+                println!("");
+                self.print(&Json::Array(capacity.clone()), CAPACITY_COLUMNS);
+            }
+        }
+
+        if let Some(&Json::Array(ref tasks)) = body.find("tasks") {
+            if !tasks.is_empty() {
+                let rows = tasks.iter()
+                    .map(|task| {
+                        let mut row = BTreeMap::new();
+                        row.insert("task".to_string(), task.clone());
+                        Json::Object(row)
+                    })
+                    .collect();
+                println!("");
+                self.print(&Json::Array(rows), NODE_TASK_COLUMNS);
+            }
+        }
+    }
+
+    fn service(&self, name: &str) {
+        let body = self.send(self.client.get(&*self.url(&format!("/services/{}", name))));
+        self.print(&body, SERVICE_COLUMNS);
+        if self.json_output {
+            return;
+        }
+
+        if let Some(&Json::Array(ref reasons)) = body.find("pending_reasons") {
+            if !reasons.is_empty() {
+                println!("");
+                self.print(&Json::Array(reasons.clone()), PENDING_REASON_COLUMNS);
+            }
+        }
+    }
+
+    fn schedule(&self, name: &str) {
+        let body = self.send(self.client.get(&*self.url(&format!("/schedules/{}", name))));
+        self.print(&body, SCHEDULE_COLUMNS);
+        if self.json_output {
+            return;
+        }
+
+        if let Some(&Json::Array(ref runs)) = body.find("past_runs") {
+            if !runs.is_empty() {
+                println!("");
+                self.print(&Json::Array(runs.clone()), PAST_RUN_COLUMNS);
+            }
+        }
+    }
+
+    fn define_workflow(&self, file: &str) {
+        let mut definition = String::new();
+        if let Err(err) = File::open(file).and_then(|mut file| file.read_to_string(&mut definition)) {
+            exit_with_error(&format!("can't read {}: {}", file, err));
+        }
+        let body = self.send(self.client.post(&*self.url("/workflows")).body(&*definition));
+        self.print(&body, RESULT_COLUMNS);
+    }
+
+    // a run followed by its steps
+    fn workflow_run(&self, request: RequestBuilder) {
+        let body = self.send(request);
+        self.print(&body, WORKFLOW_RUN_COLUMNS);
+        if self.json_output {
+            return;
+        }
+
+        if let Some(&Json::Array(ref steps)) = body.find("steps") {
+            println!("");
+            self.print(&Json::Array(steps.clone()), STEP_RUN_COLUMNS);
+        }
+    }
+
+    fn kill(&self, name: &str) {
+        let body = self.send(self.client.delete(&*self.url(&format!("/service?name={}", percent_encode(name)))));
+        self.print(&body, RESULT_COLUMNS);
+    }
+
+    fn reload_config(&self) {
+        let body = self.send(self.client.post(&*self.url("/config/reload")));
+        self.print(&body, RESULT_COLUMNS);
+        if self.json_output {
+            return;
+        }
+
+        if let Some(&Json::Object(ref changes)) = body.find("changes") {
+            for (kind, names) in changes {
+                let names: Vec<String> = match *names {
+                    Json::Array(ref names) => names.iter().filter_map(|name| name.as_string()).map(String::from).collect(),
+                    _ => vec![],
+                };
+                if !names.is_empty() {
+                    println!("{}: {}", kind, names.join(", "));
+                }
+            }
+        }
+    }
+
+    fn simulate(&self, matches: &ArgMatches) {
+        let body = match (matches.value_of("file"), matches.value_of("name")) {
+            (Some(file), _) => {
+                let mut spec = String::new();
+                if let Err(err) = File::open(file).and_then(|mut file| file.read_to_string(&mut spec)) {
+                    exit_with_error(&format!("can't read {}: {}", file, err));
+                }
+                self.send(self.client.post(&*self.url("/simulate")).body(&*spec))
+            }
+            (None, Some(name)) => self.send(self.client.get(&*self.url(&format!("/simulate?service={}", percent_encode(name))))),
+            (None, None) => self.send(self.client.get(&*self.url("/simulate"))),
+        };
+
+        if self.json_output {
+            println!("{}", json::as_pretty_json(&body));
+            return;
+        }
+
+        let tasks = body.as_array().cloned().unwrap_or(vec![]);
+        if tasks.is_empty() {
+            println!("no pending tasks");
+        }
+        for (idx, task) in tasks.iter().enumerate() {
+            if idx > 0 {
+                println!("");
+            }
+            println!("{}", field(task, "task"));
+            self.print(task.find("nodes").unwrap_or(&Json::Array(vec![])), SIMULATION_COLUMNS);
+        }
+    }
+
+    fn graph(&self, format: &str) {
+        let body = self.send_raw(self.client.get(&*self.url(&format!("/graph?format={}", percent_encode(format)))));
+        print!("{}", body);
+    }
+
+    fn events(&self, matches: &ArgMatches) {
+        let mut params = vec![];
+        for key in &["service", "node"] {
+            if let Some(value) = matches.value_of(key) {
+                params.push(format!("{}={}", key, percent_encode(value)));
+            }
+        }
+        if let Some(last_event_id) = matches.value_of("last-event-id") {
+            params.push(format!("last_event_id={}", percent_encode(last_event_id)));
+        }
+
+        let url = self.url(&format!("/events?{}", params.join("&")));
+        let response = match self.client.get(&*url).headers(self.headers.clone()).send() {
+            Ok(response) => response,
+            Err(err) => exit_with_error(&format!("request failed: {}", err)),
+        };
+        if !response.status.is_success() {
+            exit_with_error(&format!("request failed: {}", response.status));
+        }
+
+        // server-sent events, a blank line terminates each event
+        let mut id = "".to_string();
+        let mut kind = "".to_string();
+        let mut data = "".to_string();
+        for line in BufReader::new(response).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => exit_with_error(&format!("stream closed: {}", err)),
+            };
+
+            if line.is_empty() {
+                if !data.is_empty() {
+                    self.print_event(&id, &kind, &data);
+                }
+                data.clear();
+                kind.clear();
+            } else if line.starts_with("id: ") {
+                id = line[4..].to_string();
+            } else if line.starts_with("event: ") {
+                kind = line[7..].to_string();
+            } else if line.starts_with("data: ") {
+                data = line[6..].to_string();
+            }
+        }
+    }
+
+    fn print_event(&self, id: &str, kind: &str, data: &str) {
+        if self.json_output {
+            println!("{}", data);
+            return;
+        }
+
+        let event = Json::from_str(data).unwrap_or(Json::Null);
+        println!("{:<8} {:<16} {:<24} {}",
+                 id,
+                 kind,
+                 field(&event, "service"),
+                 field(&event, "node"));
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn send(&self, request: RequestBuilder) -> Json {
+        let body = self.send_raw(request);
+        match Json::from_str(&body) {
+            Ok(json) => json,
+            Err(err) => exit_with_error(&format!("invalid response: {}", err)),
+        }
+    }
+
+    fn send_raw(&self, request: RequestBuilder) -> String {
+        let mut response = match request.headers(self.headers.clone()).send() {
+            Ok(response) => response,
+            Err(err) => exit_with_error(&format!("request failed: {}", err)),
+        };
+
+        let mut body = String::new();
+        if let Err(err) = response.read_to_string(&mut body) {
+            exit_with_error(&format!("can't read response: {}", err));
+        }
+
+        if !response.status.is_success() {
+            exit_with_error(&format!("request failed: {} {}", response.status, body.trim()));
+        }
+
+        body
+    }
+
+    fn print(&self, body: &Json, columns: &[&str]) {
+        if self.json_output {
+            println!("{}", json::as_pretty_json(body));
+            return;
+        }
+
+        let rows: Vec<Json> = match *body {
+            Json::Array(ref rows) => rows.clone(),
+            ref other => vec![other.clone()],
+        };
+
+        let mut widths: Vec<usize> = columns.iter().map(|column| column.len()).collect();
+        let cells: Vec<Vec<String>> = rows.iter()
+            .map(|row| columns.iter().map(|column| field(row, column)).collect())
+            .collect();
+        for row in &cells {
+            for (idx, cell) in row.iter().enumerate() {
+                widths[idx] = cmp::max(widths[idx], cell.len());
+            }
+        }
+
+        let header: Vec<String> = columns.iter().map(|column| column.to_uppercase()).collect();
+        print_row(&header, &widths);
+        for row in &cells {
+            print_row(row, &widths);
+        }
+    }
+}
+
+fn field(row: &Json, column: &str) -> String {
+    match row.find(column) {
+        Some(&Json::String(ref value)) => value.clone(),
+        Some(&Json::Null) | None => "".to_string(),
+        Some(value) => value.to_string(),
+    }
+}
+
+fn print_row(row: &Vec<String>, widths: &Vec<usize>) {
+    let cells: Vec<String> = row.iter()
+        .enumerate()
+        .map(|(idx, cell)| format!("{:<width$}", cell, width = widths[idx]))
+        .collect();
+    println!("{}", cells.join("  ").trim_right());
+}
+
+// encodes everything but unreserved characters so names can be passed as query values
+fn percent_encode(value: &str) -> String {
+    let mut result = String::new();
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => result.push(byte as char),
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    result
+}
+
+fn exit_with_error(error_msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "error: {}", error_msg);
+    process::exit(1);
+}
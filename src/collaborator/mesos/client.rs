@@ -43,10 +43,10 @@ pub fn kill_task(task_name: &String) {
         let mut task_id = TaskID::new();
         task_id.set_value(task_name.clone());
         match client.kill(task_id, None) {
-            Ok(response) => println!("Task Deleted {:?}", response),
-            Err(error_msg) => println!("Kill Task Mesos Problem: {}", error_msg),
+            Ok(_) => log_info!("task deleted"; "task" => task_name),
+            Err(error_msg) => log_error!("can't kill task"; "task" => task_name, "error" => error_msg),
         }
     } else {
-        println!("Error killing task: Mesos-Client not set")
+        log_error!("can't kill task, mesos client not set"; "task" => task_name)
     }
 }
@@ -26,6 +26,7 @@ use hyper::Client;
 use hyper::header::Connection;
 
 use rustc_serialize::json;
+use metrics;
 
 lazy_static! {
     static ref CLIENT: Client = Client::new();
@@ -35,5 +36,8 @@ pub fn register_running_task(controller_ip: &String, task: &Task) {
     let address = format!("http://{}:3000/service/announce", controller_ip);
 
     let encoded = json::encode(&task).unwrap();
-    let _ = CLIENT.post(&address).body(&encoded).header(Connection::close()).send();
+    match CLIENT.post(&address).body(&encoded).header(Connection::close()).send() {
+        Ok(ref response) if response.status.is_success() => {}
+        _ => metrics::record_announce_failure(),
+    }
 }
@@ -0,0 +1,131 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use utils::{Task, task_to_yaml};
+use super::model::{Config, NodeConfig, ServiceGroup, Workflow};
+
+/// What a config reload changed, by name.
+#[derive(Clone, Debug, Default, RustcEncodable)]
+pub struct ConfigChanges {
+    pub nodes_added: Vec<String>,
+    pub nodes_removed: Vec<String>,
+    pub nodes_updated: Vec<String>,
+    pub system_services_added: Vec<String>,
+    pub system_services_removed: Vec<String>,
+    pub system_services_updated: Vec<String>,
+    pub service_groups_added: Vec<String>,
+    pub service_groups_removed: Vec<String>,
+    pub service_groups_updated: Vec<String>,
+    pub workflows_added: Vec<String>,
+    pub workflows_removed: Vec<String>,
+    pub workflows_updated: Vec<String>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.nodes_added.is_empty() && self.nodes_removed.is_empty() && self.nodes_updated.is_empty() &&
+        self.system_services_added.is_empty() && self.system_services_removed.is_empty() &&
+        self.system_services_updated.is_empty() && self.service_groups_added.is_empty() &&
+        self.service_groups_removed.is_empty() && self.service_groups_updated.is_empty() &&
+        self.workflows_added.is_empty() && self.workflows_removed.is_empty() && self.workflows_updated.is_empty()
+    }
+}
+
+pub fn diff_config(old: &Config, new: &Config) -> ConfigChanges {
+    let mut changes = ConfigChanges::default();
+
+    let (added, removed, updated) = diff_named(&old.nodes, &new.nodes, |node| &node.name, same_node);
+    changes.nodes_added = added;
+    changes.nodes_removed = removed;
+    changes.nodes_updated = updated;
+
+    let (added, removed, updated) = diff_named(&old.healthcheck.system_services,
+                                               &new.healthcheck.system_services,
+                                               |task| &task.name,
+                                               same_task);
+    changes.system_services_added = added;
+    changes.system_services_removed = removed;
+    changes.system_services_updated = updated;
+
+    let (added, removed, updated) = diff_named(&old.api.service_groups,
+                                               &new.api.service_groups,
+                                               |group| &group.name,
+                                               same_group);
+    changes.service_groups_added = added;
+    changes.service_groups_removed = removed;
+    changes.service_groups_updated = updated;
+
+    let (added, removed, updated) = diff_named(&old.workflows,
+                                               &new.workflows,
+                                               |workflow| &workflow.name,
+                                               same_workflow);
+    changes.workflows_added = added;
+    changes.workflows_removed = removed;
+    changes.workflows_updated = updated;
+
+    changes
+}
+
+pub fn same_task(a: &Task, b: &Task) -> bool {
+    task_to_yaml(a) == task_to_yaml(b)
+}
+
+fn same_node(a: &NodeConfig, b: &NodeConfig) -> bool {
+    a.ip == b.ip && a.external_ip == b.external_ip && a.management_ip == b.management_ip && a.port == b.port &&
+    a.node_type == b.node_type
+}
+
+fn same_group(a: &ServiceGroup, b: &ServiceGroup) -> bool {
+    a.services.len() == b.services.len() && a.services.iter().zip(b.services.iter()).all(|(a, b)| same_task(a, b))
+}
+
+fn same_workflow(a: &Workflow, b: &Workflow) -> bool {
+    a.to_yaml() == b.to_yaml()
+}
+
+fn diff_named<T, N, S>(old: &[T], new: &[T], name: N, same: S) -> (Vec<String>, Vec<String>, Vec<String>)
+    where N: Fn(&T) -> &String,
+          S: Fn(&T, &T) -> bool
+{
+    let mut added = vec![];
+    let mut removed = vec![];
+    let mut updated = vec![];
+
+    for new_item in new {
+        match old.iter().find(|old_item| name(old_item) == name(new_item)) {
+            Some(old_item) => {
+                if !same(old_item, new_item) {
+                    updated.push(name(new_item).clone());
+                }
+            }
+            None => added.push(name(new_item).clone()),
+        }
+    }
+
+    for old_item in old {
+        if !new.iter().any(|new_item| name(new_item) == name(old_item)) {
+            removed.push(name(old_item).clone());
+        }
+    }
+
+    (added, removed, updated)
+}
@@ -0,0 +1,79 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+// Config files for the unit tests of the config modules.
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use super::errors::ConfigErrors;
+use super::loader::load_config;
+use super::model::Config;
+
+// the least a config needs next to its services
+const BASE_CONFIG: &'static [&'static str] = &["nodes:",
+                                               "  - name: sl1",
+                                               "    ip: 10.0.0.1",
+                                               "statesync:",
+                                               "  poll_interval_in_seconds: 10",
+                                               "healthcheck:",
+                                               "  poll_interval_in_seconds: 10",
+                                               "  system_services: []"];
+
+/// Writes the files into a directory of the test's own and returns its path.
+pub fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory = env::temp_dir().join(format!("torc-test-{}", test));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    for &(name, text) in files {
+        File::create(directory.join(name)).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+    directory
+}
+
+/// Loads a config whose only service group holds `services`, `sections` is
+/// YAML added at the top level such as a `templates:` section.
+pub fn load_services(test: &str, sections: &str, services: &[String]) -> Result<Config, ConfigErrors> {
+    let mut text = BASE_CONFIG.join("\n");
+    text.push('\n');
+    text.push_str(sections);
+    text.push_str("api:\n  service-groups:\n    - name: group\n      services:\n");
+    text.push_str(&services.concat());
+
+    let directory = write_files(test, &[("config.yml", &text)]);
+    load_config(&directory.join("config.yml").to_string_lossy().into_owned(), &[])
+}
+
+/// A service group entry, `fields` are further `key: value` lines.
+pub fn service(name: &str, fields: &[&str]) -> String {
+    let mut result = format!("        - name: {}\n          image_name: {}\n          network_type: host\n",
+                             name,
+                             name);
+    for field in fields {
+        result.push_str(&format!("          {}\n", field));
+    }
+    result
+}
+
+/// Every error as `path: message`.
+pub fn error_messages(errors: ConfigErrors) -> Vec<String> {
+    errors.errors.into_iter().map(|error| format!("{}: {}", error.path, error.message)).collect()
+}
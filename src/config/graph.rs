@@ -0,0 +1,365 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::{BTreeMap, HashSet};
+use state::DependencyCondition;
+use utils::Task;
+use super::model::Config;
+use super::reader::ConfigReader;
+
+/// The configured services and what they depend on, for `/graph`.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct DependencyGraph {
+    pub services: Vec<GraphService>,
+    pub dependencies: Vec<GraphDependency>,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct GraphService {
+    pub name: String,
+    pub is_system_service: bool,
+    pub groups: Vec<String>,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct GraphDependency {
+    pub service: String,
+    pub depends_on: String,
+    pub condition: String,
+}
+
+impl DependencyGraph {
+    pub fn from_config(config: &Config) -> DependencyGraph {
+        let mut graph = DependencyGraph {
+            services: vec![],
+            dependencies: vec![],
+        };
+
+        for task in &config.healthcheck.system_services {
+            graph.add(task, None);
+        }
+        for service_group in &config.api.service_groups {
+            for task in &service_group.services {
+                graph.add(task, Some(&service_group.name));
+            }
+        }
+
+        graph
+    }
+
+    fn add(&mut self, task: &Task, group: Option<&String>) {
+        if !self.services.iter().any(|service| service.name == task.name) {
+            self.services.push(GraphService {
+                name: task.name.clone(),
+                is_system_service: group.is_none(),
+                groups: vec![],
+            });
+        }
+
+        if let Some(group) = group {
+            let service = self.services.iter_mut().find(|service| service.name == task.name).unwrap();
+            if !service.groups.contains(group) {
+                service.groups.push(group.clone());
+            }
+        }
+
+        for dependency in &task.dependencies {
+            let known = self.dependencies
+                .iter()
+                .any(|existing| existing.service == task.name && existing.depends_on == dependency.service);
+            if !known {
+                self.dependencies.push(GraphDependency {
+                    service: task.name.clone(),
+                    depends_on: dependency.service.clone(),
+                    condition: dependency.condition.name().to_string(),
+                });
+            }
+        }
+    }
+
+    /// Graphviz source, edges point from a service to the services it depends on.
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph services {\n".to_string();
+
+        for service in &self.services {
+            let mut label = service.name.clone();
+            if service.is_system_service {
+                label = format!("{}\\n(system)", label);
+            } else if !service.groups.is_empty() {
+                label = format!("{}\\n({})", label, service.groups.join(", "));
+            }
+            dot.push_str(&format!("    \"{}\" [label=\"{}\"];\n", service.name, label));
+        }
+
+        for dependency in &self.dependencies {
+            dot.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                                  dependency.service,
+                                  dependency.depends_on,
+                                  dependency.condition));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// The services in the order they have to be started, each after the ones it
+/// depends on. Dependencies outside of `services` don't affect the order,
+/// otherwise the given order is kept.
+pub fn start_order(services: &[Task]) -> Vec<Task> {
+    let names: HashSet<&String> = services.iter().map(|task| &task.name).collect();
+    let mut remaining: Vec<&Task> = services.iter().collect();
+    let mut started: HashSet<String> = HashSet::new();
+    let mut result = vec![];
+
+    while !remaining.is_empty() {
+        let next = remaining.iter()
+            .position(|task| {
+                task.dependencies
+                    .iter()
+                    .all(|dependency| !names.contains(&dependency.service) || started.contains(&dependency.service))
+            })
+            // only with a cycle, which the config doesn't allow
+            .unwrap_or(0);
+
+        let task = remaining.remove(next);
+        started.insert(task.name.clone());
+        result.push(task.clone());
+    }
+
+    result
+}
+
+/// `start_order` grouped into tiers, the services of a tier only depend on
+/// services of earlier tiers. Stopping goes through them in reverse.
+pub fn start_tiers(services: &[Task]) -> Vec<Vec<Task>> {
+    let mut tier_of: BTreeMap<String, usize> = BTreeMap::new();
+    let mut result: Vec<Vec<Task>> = vec![];
+
+    for task in start_order(services) {
+        let tier = task.dependencies
+            .iter()
+            .filter_map(|dependency| tier_of.get(&dependency.service).map(|tier| tier + 1))
+            .max()
+            .unwrap_or(0);
+
+        tier_of.insert(task.name.clone(), tier);
+        if result.len() <= tier {
+            result.push(vec![]);
+        }
+        result[tier].push(task);
+    }
+
+    result
+}
+
+/// Reports dependency cycles and `healthy` dependencies on services without a
+/// health check. Dependencies on services that aren't configured are allowed,
+/// they may be started by another controller.
+pub fn check_dependencies(reader: &mut ConfigReader, config: &Config) {
+    // path of each service in the config and the task read from it
+    let mut services: Vec<(String, &Task)> = vec![];
+    for (idx, task) in config.healthcheck.system_services.iter().enumerate() {
+        services.push((ConfigReader::item_path("healthcheck.system_services", idx), task));
+    }
+    for (group_idx, service_group) in config.api.service_groups.iter().enumerate() {
+        let group_path = ConfigReader::item_path("api.service-groups", group_idx);
+        for (idx, task) in service_group.services.iter().enumerate() {
+            services.push((ConfigReader::item_path(&ConfigReader::child_path(&group_path, "services"), idx), task));
+        }
+    }
+
+    let mut edges: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for &(_, task) in &services {
+        let entry = edges.entry(task.name.clone()).or_insert(vec![]);
+        for dependency in &task.dependencies {
+            if !entry.contains(&dependency.service) {
+                entry.push(dependency.service.clone());
+            }
+        }
+    }
+
+    for &(ref path, task) in &services {
+        // `dependent_service` comes first and isn't part of the `dependencies` list
+        let offset = match task.dependent_service.is_empty() {
+            true => 0,
+            false => 1,
+        };
+
+        for (idx, dependency) in task.dependencies.iter().enumerate().skip(offset) {
+            if dependency.condition != DependencyCondition::Healthy {
+                continue;
+            }
+
+            let has_health_check = services.iter()
+                .filter(|&&(_, other)| other.name == dependency.service)
+                .any(|&(_, other)| other.health_check.is_some());
+            let is_configured = edges.contains_key(&dependency.service);
+            if is_configured && !has_health_check {
+                reader.error(&ConfigReader::item_path(&ConfigReader::child_path(path, "dependencies"), idx - offset),
+                             format!("{} has no health_check, it never becomes healthy", dependency.service));
+            }
+        }
+    }
+
+    let mut visited: HashSet<String> = HashSet::new();
+    for &(_, task) in &services {
+        let mut stack = vec![];
+        if let Some(cycle) = find_cycle(&task.name, &edges, &mut visited, &mut stack) {
+            // reported on the service the cycle starts with
+            let path = services.iter().find(|&&(_, other)| other.name == cycle[0]).map(|&(ref path, _)| path.clone());
+            reader.error(&ConfigReader::child_path(&path.unwrap_or("".to_string()), "dependencies"),
+                         format!("dependency cycle {}", cycle.join(" -> ")));
+        }
+    }
+}
+
+/// Follows `edges` depth first from `name`, returns the names along the first
+/// cycle found with the first one repeated at the end.
+pub fn find_cycle(name: &String,
+                  edges: &BTreeMap<String, Vec<String>>,
+                  visited: &mut HashSet<String>,
+                  stack: &mut Vec<String>)
+                  -> Option<Vec<String>> {
+    if let Some(pos) = stack.iter().position(|parent| parent == name) {
+        let mut cycle = stack[pos..].to_vec();
+        cycle.push(name.clone());
+        return Some(cycle);
+    }
+
+    if !visited.insert(name.clone()) {
+        return None;
+    }
+
+    stack.push(name.clone());
+    if let Some(dependencies) = edges.get(name) {
+        for dependency in dependencies {
+            if let Some(cycle) = find_cycle(dependency, edges, visited, stack) {
+                return Some(cycle);
+            }
+        }
+    }
+    stack.pop();
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+    use super::{find_cycle, start_order, start_tiers};
+    use super::super::fixtures::{error_messages, load_services, service};
+
+    fn edges(entries: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        entries.iter()
+            .map(|&(name, dependencies)| (name.to_string(), dependencies.iter().map(|name| name.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn start_order_starts_dependencies_first() {
+        let config = load_services("graph-order",
+                                   "",
+                                   &[service("web", &["dependencies: [api, cache]"]),
+                                     service("api", &["dependencies: [db, external]"]),
+                                     service("cache", &[]),
+                                     service("db", &[])])
+            .unwrap();
+
+        let order: Vec<String> =
+            start_order(&config.api.service_groups[0].services).into_iter().map(|task| task.name).collect();
+        assert_eq!(order, vec!["cache", "db", "api", "web"]);
+    }
+
+    #[test]
+    fn start_tiers_groups_services_by_dependency_depth() {
+        let config = load_services("graph-tiers",
+                                   "",
+                                   &[service("web", &["dependencies: [api, cache]"]),
+                                     service("api", &["dependencies: [db]"]),
+                                     service("cache", &[]),
+                                     service("db", &["dependencies: [external]"])])
+            .unwrap();
+
+        let tiers: Vec<Vec<String>> = start_tiers(&config.api.service_groups[0].services)
+            .into_iter()
+            .map(|tier| tier.into_iter().map(|task| task.name).collect())
+            .collect();
+        assert_eq!(tiers, vec![vec!["cache", "db"], vec!["api"], vec!["web"]]);
+    }
+
+    #[test]
+    fn find_cycle_returns_the_names_along_the_cycle() {
+        let edges = edges(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &["a"])]);
+
+        let mut visited = HashSet::new();
+        assert_eq!(find_cycle(&"d".to_string(), &edges, &mut visited, &mut vec![]),
+                   Some(vec!["a".to_string(), "b".to_string(), "c".to_string(), "a".to_string()]));
+        // everything on the way was visited, the cycle isn't found a second time
+        assert_eq!(find_cycle(&"b".to_string(), &edges, &mut visited, &mut vec![]), None);
+    }
+
+    #[test]
+    fn find_cycle_finds_self_edges() {
+        let edges = edges(&[("a", &["a"])]);
+        assert_eq!(find_cycle(&"a".to_string(), &edges, &mut HashSet::new(), &mut vec![]),
+                   Some(vec!["a".to_string(), "a".to_string()]));
+    }
+
+    #[test]
+    fn find_cycle_ignores_acyclic_graphs() {
+        let edges = edges(&[("a", &["b", "c"]), ("b", &["c"]), ("c", &["external"])]);
+        assert_eq!(find_cycle(&"a".to_string(), &edges, &mut HashSet::new(), &mut vec![]), None);
+    }
+
+    #[test]
+    fn check_dependencies_reports_a_cycle_once() {
+        let errors = load_services("graph-cycle",
+                                   "",
+                                   &[service("a", &["dependencies: [b]"]), service("b", &["dependencies: [a]"])])
+            .unwrap_err();
+
+        assert_eq!(error_messages(errors),
+                   vec!["api.service-groups[0].services[0].dependencies: dependency cycle a -> b -> a"]);
+    }
+
+    #[test]
+    fn check_dependencies_reports_self_edges() {
+        let errors = load_services("graph-self", "", &[service("a", &["dependencies: [a]"])]).unwrap_err();
+
+        assert_eq!(error_messages(errors),
+                   vec!["api.service-groups[0].services[0].dependencies: dependency cycle a -> a"]);
+    }
+
+    #[test]
+    fn check_dependencies_requires_health_checks_for_healthy_dependencies() {
+        let errors = load_services("graph-healthy",
+                                   "",
+                                   &[service("web", &["dependencies: [{service: db, condition: healthy}]"]),
+                                     service("db", &[])])
+            .unwrap_err();
+
+        assert_eq!(error_messages(errors),
+                   vec!["api.service-groups[0].services[0].dependencies[0]: db has no health_check, it never \
+                         becomes healthy"]);
+    }
+}
@@ -0,0 +1,451 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::HashSet;
+use glob::Pattern;
+use yaml_rust::Yaml;
+use logging::{Format, Level};
+use utils::{Task, read_task};
+use super::errors::ConfigErrors;
+use super::graph::check_dependencies;
+use super::model::{ApiConfig, AuditConfig, AuthConfig, Config, CorsConfig, HealthCheckConfig, JobsConfig, LoggingConfig,
+                    NodeConfig, NodeDiscoveryConfig, NodeProbeConfig, ReloadConfig, ServiceGroup, StateSyncConfig,
+                    TlsConfig, Workflow};
+use super::reader::ConfigReader;
+use super::sources::ConfigSources;
+use super::templates::Templates;
+use super::workflow::{read_workflow, read_workflows};
+
+const AUTH_TYPES: &'static [&'static str] = &["none", "token", "basic"];
+const DEFAULT_CORS_METHODS: &'static [&'static str] = &["GET", "POST", "DELETE", "OPTIONS"];
+const DEFAULT_CORS_HEADERS: &'static [&'static str] = &["Authorization", "Content-Type", "Last-Event-ID"];
+
+/// Reads and validates the config file with its includes and the given
+/// overlays, returns every problem found rather than stopping at the first one.
+pub fn load_config(config_file: &String, overlays: &[String]) -> Result<Config, ConfigErrors> {
+    let sources = ConfigSources::load(config_file, overlays);
+    if !sources.errors.is_empty() {
+        return Err(ConfigErrors {
+            file: config_file.clone(),
+            errors: sources.errors,
+        });
+    }
+
+    let mut reader = ConfigReader::new(sources.files, sources.origins);
+    let files = reader.file_names();
+    let config = read_config(&mut reader, sources.doc, files.clone());
+    check_dependencies(&mut reader, &config);
+
+    match reader.has_errors() {
+        true => {
+            let mut errors = reader.into_errors();
+            errors.sort_by_key(|error| (files.iter().position(|file| *file == error.file), error.line));
+            Err(ConfigErrors {
+                file: config_file.clone(),
+                errors: errors,
+            })
+        }
+        false => Ok(config),
+    }
+}
+
+/// Reads a single service definition that isn't part of the config, like one
+/// posted to the api. It can extend the templates of `config`.
+pub fn load_service(name: &str, text: &str, config: &Config) -> Result<Task, ConfigErrors> {
+    let sources = ConfigSources::from_text(name, text);
+    if !sources.errors.is_empty() {
+        return Err(ConfigErrors {
+            file: name.to_string(),
+            errors: sources.errors,
+        });
+    }
+
+    let mut reader = ConfigReader::new(sources.files, sources.origins);
+    // the config was checked when it got loaded, this can't add errors
+    let templates = Templates::read(&mut reader, &config.raw);
+
+    let task = match reader.expect_hash(&sources.doc, "") {
+        true => Some(read_task(&mut reader, &templates, &sources.doc, "")),
+        false => None,
+    };
+
+    match (task, reader.has_errors()) {
+        (Some(task), false) => Ok(task),
+        _ => {
+            let mut errors = reader.into_errors();
+            errors.sort_by_key(|error| error.line);
+            Err(ConfigErrors {
+                file: name.to_string(),
+                errors: errors,
+            })
+        }
+    }
+}
+
+/// Reads a workflow definition that isn't part of the config, like one posted
+/// to the api. Its steps can extend the templates of `config`.
+pub fn load_workflow(name: &str, text: &str, config: &Config) -> Result<Workflow, ConfigErrors> {
+    let sources = ConfigSources::from_text(name, text);
+    if !sources.errors.is_empty() {
+        return Err(ConfigErrors {
+            file: name.to_string(),
+            errors: sources.errors,
+        });
+    }
+
+    let mut reader = ConfigReader::new(sources.files, sources.origins);
+    let templates = Templates::read(&mut reader, &config.raw);
+
+    let workflow = match reader.expect_hash(&sources.doc, "") {
+        true => Some(read_workflow(&mut reader, &templates, &sources.doc, "")),
+        false => None,
+    };
+
+    match (workflow, reader.has_errors()) {
+        (Some(workflow), false) => Ok(workflow),
+        _ => {
+            let mut errors = reader.into_errors();
+            errors.sort_by_key(|error| error.line);
+            Err(ConfigErrors {
+                file: name.to_string(),
+                errors: errors,
+            })
+        }
+    }
+}
+
+fn read_config(reader: &mut ConfigReader, doc: Yaml, files: Vec<String>) -> Config {
+    let templates = Templates::read(reader, &doc);
+    let name = reader.string_or(&doc, "", "name", "torc-controller");
+    let node_discovery = read_node_discovery(reader, &doc["node_discovery"]);
+
+    Config {
+        name: name,
+        nodes: read_nodes(reader, &doc, !node_discovery.auto_register),
+        statesync: StateSyncConfig {
+            poll_interval_in_seconds: read_interval(reader, &doc["statesync"], "statesync"),
+        },
+        healthcheck: HealthCheckConfig {
+            poll_interval_in_seconds: read_interval(reader, &doc["healthcheck"], "healthcheck"),
+            system_services: read_services(reader,
+                                           &templates,
+                                           &doc["healthcheck"],
+                                           "healthcheck",
+                                           "system_services"),
+        },
+        api: read_api(reader, &templates, &doc["api"]),
+        reload: ReloadConfig {
+            watch_interval_in_seconds: reader.int(&doc["reload"], "reload", "watch_interval_in_seconds", 0) as u64,
+        },
+        jobs: JobsConfig {
+            retention_in_seconds: reader.positive_int(&doc["jobs"], "jobs", "retention_in_seconds", Some(3600)) as u64,
+            history_size: reader.positive_int(&doc["jobs"], "jobs", "history_size", Some(100)) as u64,
+            state_file: reader.string(&doc["jobs"], "jobs", "state_file"),
+        },
+        node_discovery: node_discovery,
+        node_probe: read_node_probe(reader, &doc["node_probe"]),
+        workflows: read_workflows(reader, &templates, &doc),
+        logging: read_logging(reader, &doc["logging"]),
+        files: files,
+        raw: doc,
+    }
+}
+
+fn read_interval(reader: &mut ConfigReader, section: &Yaml, path: &str) -> u64 {
+    if section.is_badvalue() {
+        reader.error(path, "missing".to_string());
+        return 1;
+    }
+
+    reader.positive_int(section, path, "poll_interval_in_seconds", None) as u64
+}
+
+/// `nodes` may be left out when agents register themselves.
+fn read_nodes(reader: &mut ConfigReader, doc: &Yaml, required: bool) -> Vec<NodeConfig> {
+    let mut nodes = vec![];
+    let mut names = HashSet::new();
+
+    for (idx, node) in reader.list(doc, "", "nodes", required).iter().enumerate() {
+        let path = ConfigReader::item_path("nodes", idx);
+        if !reader.expect_hash(node, &path) {
+            continue;
+        }
+
+        let node = NodeConfig {
+            name: reader.required_string(node, &path, "name"),
+            ip: reader.required_string(node, &path, "ip"),
+            external_ip: reader.string(node, &path, "external_ip"),
+            management_ip: reader.string(node, &path, "management_ip"),
+            port: read_port(reader, node, &path, 0) as i64,
+            node_type: reader.string(node, &path, "type"),
+        };
+
+        if !node.name.is_empty() && !names.insert(node.name.clone()) {
+            reader.error(&ConfigReader::child_path(&path, "name"),
+                         format!("node {} is defined more than once", node.name));
+        }
+        nodes.push(node);
+    }
+
+    nodes
+}
+
+fn read_node_discovery(reader: &mut ConfigReader, section: &Yaml) -> NodeDiscoveryConfig {
+    let path = "node_discovery";
+    let patterns = |reader: &mut ConfigReader, key: &str| {
+        let list_path = ConfigReader::child_path(path, key);
+        let patterns = reader.string_list(section, path, key);
+        for (idx, pattern) in patterns.iter().enumerate() {
+            if let Err(error) = Pattern::new(pattern) {
+                reader.error(&ConfigReader::item_path(&list_path, idx),
+                             format!("{} is not a valid pattern: {}", pattern, error.msg));
+            }
+        }
+        patterns
+    };
+
+    let expire_after_in_seconds = reader.int(section, path, "expire_after_in_seconds", 0);
+    if expire_after_in_seconds < 0 {
+        reader.error(&ConfigReader::child_path(path, "expire_after_in_seconds"),
+                     "must not be negative".to_string());
+    }
+
+    NodeDiscoveryConfig {
+        auto_register: reader.bool(section, path, "auto_register", false),
+        allow: patterns(reader, "allow"),
+        deny: patterns(reader, "deny"),
+        expire_after_in_seconds: if expire_after_in_seconds < 0 { 0 } else { expire_after_in_seconds as u64 },
+    }
+}
+
+fn read_node_probe(reader: &mut ConfigReader, section: &Yaml) -> NodeProbeConfig {
+    let path = "node_probe";
+
+    let poll_interval_in_seconds = reader.int(section, path, "poll_interval_in_seconds", 0);
+    if poll_interval_in_seconds < 0 {
+        reader.error(&ConfigReader::child_path(path, "poll_interval_in_seconds"),
+                     "must not be negative".to_string());
+    }
+
+    let port = read_port(reader, section, path, 0);
+    if poll_interval_in_seconds > 0 && port == 0 {
+        reader.error(&ConfigReader::child_path(path, "port"),
+                     "missing, nodes are probed on it".to_string());
+    }
+
+    let probe_path = reader.string(section, path, "path");
+    if !probe_path.is_empty() && !probe_path.starts_with("/") {
+        reader.error(&ConfigReader::child_path(path, "path"),
+                     format!("{} has to start with /", probe_path));
+    }
+
+    NodeProbeConfig {
+        poll_interval_in_seconds: if poll_interval_in_seconds < 0 { 0 } else { poll_interval_in_seconds as u64 },
+        port: port,
+        path: probe_path,
+        timeout_in_seconds: reader.positive_int(section, path, "timeout_in_seconds", Some(5)) as u64,
+    }
+}
+
+fn read_services(reader: &mut ConfigReader,
+                 templates: &Templates,
+                 section: &Yaml,
+                 path: &str,
+                 key: &str)
+                 -> Vec<Task> {
+    let mut services = vec![];
+    let mut names = HashSet::new();
+    let list_path = ConfigReader::child_path(path, key);
+
+    for (idx, service) in reader.list(section, path, key, true).iter().enumerate() {
+        let service_path = ConfigReader::item_path(&list_path, idx);
+        if !reader.expect_hash(service, &service_path) {
+            continue;
+        }
+
+        let task = read_task(reader, templates, service, &service_path);
+        if !task.name.is_empty() && !names.insert(task.name.clone()) {
+            reader.error(&ConfigReader::child_path(&service_path, "name"),
+                         format!("service {} is defined more than once in {}", task.name, list_path));
+        }
+        services.push(task);
+    }
+
+    services
+}
+
+fn read_api(reader: &mut ConfigReader, templates: &Templates, api: &Yaml) -> ApiConfig {
+    let mut service_groups = vec![];
+    let mut names = HashSet::new();
+
+    for (idx, group) in reader.list(api, "api", "service-groups", false).iter().enumerate() {
+        let path = ConfigReader::item_path("api.service-groups", idx);
+        if !reader.expect_hash(group, &path) {
+            continue;
+        }
+
+        let group = ServiceGroup {
+            name: reader.required_string(group, &path, "name"),
+            services: read_services(reader, templates, group, &path, "services"),
+        };
+
+        if !group.name.is_empty() && !names.insert(group.name.clone()) {
+            reader.error(&ConfigReader::child_path(&path, "name"),
+                         format!("service group {} is defined more than once", group.name));
+        }
+        service_groups.push(group);
+    }
+
+    let audit = &api["audit"];
+    ApiConfig {
+        listen_address: reader.string_or(api, "api", "listen_address", "0.0.0.0"),
+        port: read_port(reader, api, "api", 3005),
+        service_groups: service_groups,
+        audit: AuditConfig {
+            path: reader.string(audit, "api.audit", "path"),
+            max_size_in_bytes: reader.positive_int(audit, "api.audit", "max_size_in_bytes", Some(10 * 1024 * 1024)) as u64,
+            max_files: reader.positive_int(audit, "api.audit", "max_files", Some(5)) as u64,
+        },
+        auth: read_auth(reader, &api["auth"]),
+        tls: read_tls(reader, &api["tls"]),
+        cors: read_cors(reader, &api["cors"]),
+    }
+}
+
+fn read_auth(reader: &mut ConfigReader, auth: &Yaml) -> AuthConfig {
+    let path = "api.auth";
+    reader.expect_hash(auth, path);
+
+    let auth_type = reader.string_or(auth, path, "type", "none");
+    let (tokens_file, users_file) = match &*auth_type {
+        "token" => (reader.required_string(auth, path, "tokens_file"), reader.string(auth, path, "users_file")),
+        "basic" => (reader.string(auth, path, "tokens_file"), reader.required_string(auth, path, "users_file")),
+        _ => (reader.string(auth, path, "tokens_file"), reader.string(auth, path, "users_file")),
+    };
+    if !AUTH_TYPES.iter().any(|known| *known == auth_type) {
+        reader.error(&ConfigReader::child_path(path, "type"),
+                     format!("unknown type {}, expected one of {}", auth_type, AUTH_TYPES.join(", ")));
+    }
+
+    AuthConfig {
+        auth_type: auth_type,
+        tokens_file: tokens_file,
+        users_file: users_file,
+    }
+}
+
+fn read_tls(reader: &mut ConfigReader, tls: &Yaml) -> Option<TlsConfig> {
+    let path = "api.tls";
+    if !reader.expect_hash(tls, path) {
+        return None;
+    }
+
+    Some(TlsConfig {
+        certificate: reader.required_string(tls, path, "certificate"),
+        key: reader.required_string(tls, path, "key"),
+        client_ca: reader.string(tls, path, "client_ca"),
+    })
+}
+
+fn read_cors(reader: &mut ConfigReader, cors: &Yaml) -> Option<CorsConfig> {
+    let path = "api.cors";
+    if !reader.expect_hash(cors, path) {
+        return None;
+    }
+
+    let strings = |reader: &mut ConfigReader, key: &str, default: &[&str]| {
+        match cors[key].is_badvalue() {
+            true => default.iter().map(|value| value.to_string()).collect(),
+            false => reader.string_list(cors, path, key),
+        }
+    };
+
+    Some(CorsConfig {
+        allowed_origins: strings(reader, "allowed_origins", &[]),
+        allowed_methods: strings(reader, "allowed_methods", DEFAULT_CORS_METHODS),
+        allowed_headers: strings(reader, "allowed_headers", DEFAULT_CORS_HEADERS),
+        max_age_in_seconds: reader.positive_int(cors, path, "max_age_in_seconds", Some(600)) as u64,
+    })
+}
+
+fn read_logging(reader: &mut ConfigReader, logging: &Yaml) -> LoggingConfig {
+    let path = "logging";
+    reader.expect_hash(logging, path);
+
+    let read_level = |reader: &mut ConfigReader, level_path: &str, name: &str| {
+        match Level::from_str(name) {
+            Some(level) => level,
+            None => {
+                reader.error(level_path, format!("unknown log level {}", name));
+                Level::Info
+            }
+        }
+    };
+
+    let level_name = reader.string_or(logging, path, "level", "info");
+    let level = read_level(reader, &ConfigReader::child_path(path, "level"), &level_name);
+
+    let format_name = reader.string_or(logging, path, "format", "human");
+    let format = match Format::from_str(&format_name) {
+        Some(format) => format,
+        None => {
+            reader.error(&ConfigReader::child_path(path, "format"),
+                         format!("unknown log format {}, expected human or json", format_name));
+            Format::Human
+        }
+    };
+
+    let mut modules = vec![];
+    let modules_path = ConfigReader::child_path(path, "modules");
+    if reader.expect_hash(&logging["modules"], &modules_path) {
+        for (module, _) in logging["modules"].as_hash().unwrap() {
+            let module = match module.as_str() {
+                Some(module) => module,
+                None => {
+                    reader.error(&modules_path, "module names must be strings".to_string());
+                    continue;
+                }
+            };
+            let level_path = ConfigReader::child_path(&modules_path, module);
+            match logging["modules"][module].as_str() {
+                Some(level_name) => modules.push((module.to_string(), read_level(reader, &level_path, level_name))),
+                None => reader.error(&level_path, "must be a log level".to_string()),
+            }
+        }
+    }
+
+    LoggingConfig {
+        level: level,
+        format: format,
+        modules: modules,
+    }
+}
+
+fn read_port(reader: &mut ConfigReader, element: &Yaml, path: &str, default: u16) -> u16 {
+    let port = reader.int(element, path, "port", default as i64);
+    if port < 0 || port > 65535 {
+        reader.error(&ConfigReader::child_path(path, "port"),
+                     format!("{} is not a valid port", port));
+        return default;
+    }
+    port as u16
+}
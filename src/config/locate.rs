@@ -0,0 +1,257 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+// Maps a config path like `api.service-groups[0].services[2].privileged` back to
+// a line in the YAML source. yaml-rust doesn't keep positions on the parsed
+// document, so this walks the block structure of the text. When a segment can't
+// be found, e.g. because the key is missing, the line of its closest parent is used.
+
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+struct Line<'a> {
+    indent: usize,
+    text: &'a str,
+    is_item: bool,
+}
+
+pub fn find_line(source: &str, path: &str) -> Option<usize> {
+    let lines: Vec<Option<Line>> = source.lines().map(parse_line).collect();
+
+    let mut found = None;
+    let mut start = 0;
+    let mut end = lines.len();
+
+    for segment in parse_path(path) {
+        let result = match segment {
+            Segment::Key(ref key) => find_key(&lines, start, end, key),
+            Segment::Index(index) => find_item(&lines, start, end, index),
+        };
+
+        match result {
+            Some((line, block_start, block_end)) => {
+                found = Some(line + 1);
+                start = block_start;
+                end = block_end;
+            }
+            None => break,
+        }
+    }
+
+    found
+}
+
+fn parse_line(line: &str) -> Option<Line> {
+    let text = line.trim_left_matches(' ');
+    if text.is_empty() || text.starts_with('#') || text.starts_with("---") {
+        return None;
+    }
+
+    Some(Line {
+        indent: line.len() - text.len(),
+        text: text,
+        is_item: text == "-" || text.starts_with("- "),
+    })
+}
+
+fn parse_path(path: &str) -> Vec<Segment> {
+    let mut result = vec![];
+
+    for part in path.split('.').filter(|part| !part.is_empty()) {
+        let mut elmts = part.split('[');
+        let key = elmts.next().unwrap();
+        if !key.is_empty() {
+            result.push(Segment::Key(key.to_string()));
+        }
+        for index in elmts {
+            if let Ok(index) = index.trim_right_matches(']').parse() {
+                result.push(Segment::Index(index));
+            }
+        }
+    }
+
+    result
+}
+
+// column and text of the key on a line, for `- key: value` that's the part after the dash
+fn key_of<'a>(line: &Line<'a>) -> (usize, &'a str) {
+    match line.is_item {
+        true => (line.indent + 2, line.text[1..].trim_left_matches(' ')),
+        false => (line.indent, line.text),
+    }
+}
+
+fn find_key(lines: &Vec<Option<Line>>, start: usize, end: usize, key: &str) -> Option<(usize, usize, usize)> {
+    let mut column = None;
+
+    for idx in start..end {
+        let line = match lines[idx] {
+            Some(ref line) => line,
+            None => continue,
+        };
+
+        let (key_column, text) = key_of(line);
+        if column.is_none() {
+            column = Some(key_column);
+        }
+        if Some(key_column) != column {
+            continue;
+        }
+
+        let matches = text.starts_with(key) && text[key.len()..].trim_left_matches(' ').starts_with(':') ||
+                      text.starts_with(&format!("\"{}\":", key)) ||
+                      text.starts_with(&format!("'{}':", key));
+        if matches {
+            // the value block ends with the next line at or left of the key, except for
+            // sequence items which may sit at the same column as their key
+            let mut block_end = idx + 1;
+            while block_end < end {
+                if let Some(ref next) = lines[block_end] {
+                    if next.indent < key_column || (next.indent == key_column && !next.is_item) {
+                        break;
+                    }
+                }
+                block_end += 1;
+            }
+            return Some((idx, idx + 1, block_end));
+        }
+    }
+
+    None
+}
+
+fn find_item(lines: &Vec<Option<Line>>, start: usize, end: usize, index: usize) -> Option<(usize, usize, usize)> {
+    let mut column = None;
+    let mut count = 0;
+
+    for idx in start..end {
+        let line = match lines[idx] {
+            Some(ref line) if line.is_item => line,
+            _ => continue,
+        };
+
+        if column.is_none() {
+            column = Some(line.indent);
+        }
+        let expected = line.indent;
+        if Some(expected) != column {
+            continue;
+        }
+
+        if count == index {
+            let mut block_end = idx + 1;
+            while block_end < end {
+                if let Some(ref next) = lines[block_end] {
+                    if next.indent <= expected {
+                        break;
+                    }
+                }
+                block_end += 1;
+            }
+            // the item's first key sits on the dash line, so the block starts there
+            return Some((idx, idx, block_end));
+        }
+        count += 1;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_line;
+
+    const SOURCE: &'static str = "
+# sub-scheduler config
+api:
+  port: 8080
+  cors:
+    allowed_origins: [https://a.example, https://b.example]
+
+node_probe:
+  port: 5051
+
+service-groups:
+  - name: web
+    services:
+    - name: cache
+      cpu: 0.5
+    - name: db
+      cpu: 1
+      \"memory\": 512
+    -
+      name: api
+      cpu: 2
+      dependencies: [{service: db, condition: healthy}]
+  - name: batch
+    services:
+      - name: report
+        cpu: 4
+";
+
+    fn line(path: &str) -> Option<usize> {
+        find_line(SOURCE, path)
+    }
+
+    #[test]
+    fn nested_keys() {
+        assert_eq!(line("api"), Some(3));
+        assert_eq!(line("api.port"), Some(4));
+        assert_eq!(line("api.cors.allowed_origins"), Some(6));
+    }
+
+    #[test]
+    fn keys_under_several_parents() {
+        assert_eq!(line("node_probe.port"), Some(9));
+        assert_eq!(line("api.port"), Some(4));
+        assert_eq!(line("service-groups[1].name"), Some(23));
+        assert_eq!(line("service-groups[1].services[0].cpu"), Some(26));
+    }
+
+    #[test]
+    fn list_items() {
+        assert_eq!(line("service-groups[0]"), Some(12));
+        assert_eq!(line("service-groups[0].services[0].cpu"), Some(15));
+        assert_eq!(line("service-groups[0].services[1].memory"), Some(18));
+        // an item whose first key is on the line after the dash
+        assert_eq!(line("service-groups[0].services[2]"), Some(19));
+        assert_eq!(line("service-groups[0].services[2].name"), Some(20));
+        assert_eq!(line("service-groups[0].services[2].cpu"), Some(21));
+    }
+
+    #[test]
+    fn flow_style_values_use_the_line_of_their_key() {
+        assert_eq!(line("api.cors.allowed_origins[1]"), Some(6));
+        assert_eq!(line("service-groups[0].services[2].dependencies[0].condition"), Some(22));
+    }
+
+    #[test]
+    fn missing_segments_use_the_closest_parent() {
+        assert_eq!(line("service-groups[0].services[1].privileged"), Some(16));
+        assert_eq!(line("service-groups[0].services[5].cpu"), Some(13));
+        assert_eq!(line("api.tls.certificate"), Some(3));
+        assert_eq!(line("logging"), None);
+        assert_eq!(line(""), None);
+    }
+}
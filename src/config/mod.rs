@@ -0,0 +1,44 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+pub use self::model::{ApiConfig, AuditConfig, AuthConfig, Config, CorsConfig, HealthCheckConfig, JobsConfig, LoggingConfig,
+                      NodeConfig, NodeDiscoveryConfig, NodeProbeConfig, ReloadConfig, ServiceGroup, StateSyncConfig,
+                      TlsConfig, Workflow, WorkflowStep};
+pub use self::diff::{ConfigChanges, diff_config, same_task};
+pub use self::graph::{DependencyGraph, GraphDependency, GraphService, start_order, start_tiers};
+pub use self::loader::{load_config, load_service, load_workflow};
+pub use self::errors::{ConfigError, ConfigErrors};
+pub use self::reader::ConfigReader;
+pub use self::templates::Templates;
+
+mod model;
+mod loader;
+mod errors;
+mod reader;
+mod locate;
+mod diff;
+mod graph;
+mod sources;
+mod templates;
+mod workflow;
+#[cfg(test)]
+pub mod fixtures;
@@ -0,0 +1,368 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::BTreeMap;
+use glob::Pattern;
+use yaml_rust::Yaml;
+use logging::{Format, Level};
+use utils::{Task, task_to_yaml, yaml_hash};
+
+/// The sub-scheduler config file after validation, see `load_config`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub name: String,
+    pub nodes: Vec<NodeConfig>,
+    pub statesync: StateSyncConfig,
+    pub healthcheck: HealthCheckConfig,
+    pub api: ApiConfig,
+    pub reload: ReloadConfig,
+    pub jobs: JobsConfig,
+    pub node_discovery: NodeDiscoveryConfig,
+    pub node_probe: NodeProbeConfig,
+    pub workflows: Vec<Workflow>,
+    pub logging: LoggingConfig,
+    /// Every file the config was read from, the main file first.
+    pub files: Vec<String>,
+    /// The merged document as parsed, for templates and `${config:...}` references.
+    pub raw: Yaml,
+}
+
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    pub name: String,
+    pub ip: String,
+    pub external_ip: String,
+    pub management_ip: String,
+    pub port: i64,
+    pub node_type: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct StateSyncConfig {
+    pub poll_interval_in_seconds: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct HealthCheckConfig {
+    pub poll_interval_in_seconds: u64,
+    pub system_services: Vec<Task>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReloadConfig {
+    /// How often the config file is checked for changes, 0 turns the watch off.
+    pub watch_interval_in_seconds: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct JobsConfig {
+    /// How long finished and failed jobs stay in the task list.
+    pub retention_in_seconds: u64,
+    /// How many job runs are kept in the history.
+    pub history_size: u64,
+    /// Where the last run of every scheduled job is remembered so runs missed
+    /// while the sub-scheduler was down can be caught up, empty keeps it in memory.
+    pub state_file: String,
+}
+
+/// Which mesos agents become nodes, besides the ones listed under `nodes`.
+#[derive(Clone, Debug)]
+pub struct NodeDiscoveryConfig {
+    /// Adds a node for every allowed agent that offers but isn't listed.
+    pub auto_register: bool,
+    /// Glob patterns of node names offers are taken from, empty allows every node.
+    pub allow: Vec<String>,
+    /// Glob patterns of node names whose offers are declined, wins over `allow`.
+    pub deny: Vec<String>,
+    /// How long a node may go without offers before a discovered node is removed
+    /// and a listed one marked inactive, 0 keeps them forever.
+    pub expire_after_in_seconds: u64,
+}
+
+/// How nodes are checked for being reachable on their management ip, no
+/// work is placed on a node that doesn't answer.
+#[derive(Clone, Debug)]
+pub struct NodeProbeConfig {
+    /// 0 turns probing off.
+    pub poll_interval_in_seconds: u64,
+    pub port: u16,
+    /// With a path the probe is an HTTP GET that has to succeed, without one
+    /// a TCP connect.
+    pub path: String,
+    pub timeout_in_seconds: u64,
+}
+
+/// Jobs that run one after another, a step starts once every step it comes
+/// `after` succeeded.
+#[derive(Clone, Debug)]
+pub struct Workflow {
+    pub name: String,
+    pub steps: Vec<WorkflowStep>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WorkflowStep {
+    pub name: String,
+    pub after: Vec<String>,
+    /// Its name is the step name, every run starts it under a name of its own.
+    pub task: Task,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiConfig {
+    pub listen_address: String,
+    pub port: u16,
+    pub service_groups: Vec<ServiceGroup>,
+    pub audit: AuditConfig,
+    pub auth: AuthConfig,
+    /// Plain HTTP without one.
+    pub tls: Option<TlsConfig>,
+    /// Without one only `/service` answers cross origin requests, from any origin.
+    pub cors: Option<CorsConfig>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ServiceGroup {
+    pub name: String,
+    pub services: Vec<Task>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditConfig {
+    pub path: String,
+    pub max_size_in_bytes: u64,
+    pub max_files: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuthConfig {
+    /// none, token or basic.
+    pub auth_type: String,
+    /// Lines of "<token> <role> <identity>", used by token authentication.
+    pub tokens_file: String,
+    /// Lines of "<user>:<iterations>:<salt>:<pbkdf2-hmac-sha256>:<role>", used by basic authentication.
+    pub users_file: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub certificate: String,
+    pub key: String,
+    /// Clients have to present a certificate signed by it when set.
+    pub client_ca: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub max_age_in_seconds: u64,
+}
+
+/// Command line flags take precedence over it.
+#[derive(Clone, Debug)]
+pub struct LoggingConfig {
+    pub level: Level,
+    pub format: Format,
+    /// (module prefix, level), the longest matching prefix wins.
+    pub modules: Vec<(String, Level)>,
+}
+
+impl Config {
+    pub fn get_service_group(&self, name: &str) -> Option<&ServiceGroup> {
+        self.api.service_groups.iter().find(|group| group.name == name)
+    }
+
+    pub fn get_workflow(&self, name: &str) -> Option<&Workflow> {
+        self.workflows.iter().find(|workflow| workflow.name == name)
+    }
+
+    /// The config as the sub-scheduler sees it, with defaults filled in for
+    /// every optional node, service and api setting.
+    pub fn to_yaml(&self) -> Yaml {
+        let mut result = match self.raw.as_hash() {
+            Some(hash) => hash.clone(),
+            None => Default::default(),
+        };
+
+        let nodes = self.nodes
+            .iter()
+            .map(|node| {
+                yaml_hash(vec![("name", Yaml::String(node.name.clone())),
+                               ("ip", Yaml::String(node.ip.clone())),
+                               ("external_ip", Yaml::String(node.external_ip.clone())),
+                               ("management_ip", Yaml::String(node.management_ip.clone())),
+                               ("port", Yaml::Integer(node.port)),
+                               ("type", Yaml::String(node.node_type.clone()))])
+            })
+            .collect();
+
+        let mut healthcheck = self.raw["healthcheck"].as_hash().cloned().unwrap_or(Default::default());
+        healthcheck.insert(Yaml::String("poll_interval_in_seconds".to_string()),
+                           Yaml::Integer(self.healthcheck.poll_interval_in_seconds as i64));
+        healthcheck.insert(Yaml::String("system_services".to_string()),
+                           Yaml::Array(self.healthcheck.system_services.iter().map(task_to_yaml).collect()));
+
+        let mut api = self.raw["api"].as_hash().cloned().unwrap_or(Default::default());
+        api.insert(Yaml::String("listen_address".to_string()),
+                   Yaml::String(self.api.listen_address.clone()));
+        api.insert(Yaml::String("port".to_string()), Yaml::Integer(self.api.port as i64));
+        let groups = self.api
+            .service_groups
+            .iter()
+            .map(|group| {
+                yaml_hash(vec![("name", Yaml::String(group.name.clone())),
+                               ("services", Yaml::Array(group.services.iter().map(task_to_yaml).collect()))])
+            })
+            .collect();
+        api.insert(Yaml::String("service-groups".to_string()), Yaml::Array(groups));
+        api.insert(Yaml::String("auth".to_string()),
+                   yaml_hash(vec![("type", Yaml::String(self.api.auth.auth_type.clone())),
+                                  ("tokens_file", Yaml::String(self.api.auth.tokens_file.clone())),
+                                  ("users_file", Yaml::String(self.api.auth.users_file.clone()))]));
+        if let Some(ref tls) = self.api.tls {
+            api.insert(Yaml::String("tls".to_string()),
+                       yaml_hash(vec![("certificate", Yaml::String(tls.certificate.clone())),
+                                      ("key", Yaml::String(tls.key.clone())),
+                                      ("client_ca", Yaml::String(tls.client_ca.clone()))]));
+        }
+        let strings = |values: &Vec<String>| Yaml::Array(values.iter().cloned().map(Yaml::String).collect());
+        if let Some(ref cors) = self.api.cors {
+            api.insert(Yaml::String("cors".to_string()),
+                       yaml_hash(vec![("allowed_origins", strings(&cors.allowed_origins)),
+                                      ("allowed_methods", strings(&cors.allowed_methods)),
+                                      ("allowed_headers", strings(&cors.allowed_headers)),
+                                      ("max_age_in_seconds", Yaml::Integer(cors.max_age_in_seconds as i64))]));
+        }
+
+        result.insert(Yaml::String("name".to_string()), Yaml::String(self.name.clone()));
+        result.insert(Yaml::String("nodes".to_string()), Yaml::Array(nodes));
+        result.insert(Yaml::String("statesync".to_string()),
+                      yaml_hash(vec![("poll_interval_in_seconds",
+                                      Yaml::Integer(self.statesync.poll_interval_in_seconds as i64))]));
+        result.insert(Yaml::String("healthcheck".to_string()), Yaml::Hash(healthcheck));
+        result.insert(Yaml::String("api".to_string()), Yaml::Hash(api));
+        result.insert(Yaml::String("reload".to_string()),
+                      yaml_hash(vec![("watch_interval_in_seconds",
+                                      Yaml::Integer(self.reload.watch_interval_in_seconds as i64))]));
+        result.insert(Yaml::String("jobs".to_string()),
+                      yaml_hash(vec![("retention_in_seconds", Yaml::Integer(self.jobs.retention_in_seconds as i64)),
+                                     ("history_size", Yaml::Integer(self.jobs.history_size as i64)),
+                                     ("state_file", Yaml::String(self.jobs.state_file.clone()))]));
+        result.insert(Yaml::String("node_discovery".to_string()),
+                      yaml_hash(vec![("auto_register", Yaml::Boolean(self.node_discovery.auto_register)),
+                                     ("allow", strings(&self.node_discovery.allow)),
+                                     ("deny", strings(&self.node_discovery.deny)),
+                                     ("expire_after_in_seconds",
+                                      Yaml::Integer(self.node_discovery.expire_after_in_seconds as i64))]));
+        result.insert(Yaml::String("node_probe".to_string()),
+                      yaml_hash(vec![("poll_interval_in_seconds",
+                                      Yaml::Integer(self.node_probe.poll_interval_in_seconds as i64)),
+                                     ("port", Yaml::Integer(self.node_probe.port as i64)),
+                                     ("path", Yaml::String(self.node_probe.path.clone())),
+                                     ("timeout_in_seconds", Yaml::Integer(self.node_probe.timeout_in_seconds as i64))]));
+        result.insert(Yaml::String("workflows".to_string()),
+                      Yaml::Array(self.workflows.iter().map(Workflow::to_yaml).collect()));
+        let mut modules = BTreeMap::new();
+        for &(ref module, level) in &self.logging.modules {
+            modules.insert(Yaml::String(module.clone()), Yaml::String(level.name().to_string()));
+        }
+        result.insert(Yaml::String("logging".to_string()),
+                      yaml_hash(vec![("level", Yaml::String(self.logging.level.name().to_string())),
+                                     ("format", Yaml::String(self.logging.format.name().to_string())),
+                                     ("modules", Yaml::Hash(modules))]));
+
+        Yaml::Hash(result)
+    }
+}
+
+impl NodeDiscoveryConfig {
+    /// Whether offers from the node are used, patterns were checked when the
+    /// config was loaded.
+    pub fn allows(&self, node_name: &str) -> bool {
+        let matches = |pattern: &String| Pattern::new(pattern).map(|pattern| pattern.matches(node_name)).unwrap_or(false);
+
+        if self.deny.iter().any(&matches) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(&matches)
+    }
+}
+
+impl NodeProbeConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.poll_interval_in_seconds > 0
+    }
+}
+
+impl Workflow {
+    pub fn get_step(&self, name: &str) -> Option<&WorkflowStep> {
+        self.steps.iter().find(|step| step.name == name)
+    }
+
+    pub fn to_yaml(&self) -> Yaml {
+        let steps = self.steps
+            .iter()
+            .map(|step| {
+                let mut step_yaml = match task_to_yaml(&step.task) {
+                    Yaml::Hash(hash) => hash,
+                    _ => Default::default(),
+                };
+                step_yaml.insert(Yaml::String("after".to_string()),
+                                 Yaml::Array(step.after.iter().map(|after| Yaml::String(after.clone())).collect()));
+                Yaml::Hash(step_yaml)
+            })
+            .collect();
+
+        yaml_hash(vec![("name", Yaml::String(self.name.clone())), ("steps", Yaml::Array(steps))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlEmitter;
+    use super::Config;
+    use super::super::fixtures::{load_services, service, write_files};
+    use super::super::loader::load_config;
+
+    fn dependency_names(config: &Config) -> Vec<String> {
+        config.api.service_groups[0].services[0].dependencies.iter().map(|dependency| dependency.service.clone()).collect()
+    }
+
+    #[test]
+    fn to_yaml_loads_back_with_the_same_dependencies() {
+        let config = load_services("model-dump",
+                                   "",
+                                   &[service("web", &["dependent_service: db", "dependencies: [cache]"]),
+                                     service("db", &[]),
+                                     service("cache", &[])])
+            .unwrap();
+        assert_eq!(dependency_names(&config), vec!["db", "cache"]);
+
+        let mut text = String::new();
+        YamlEmitter::new(&mut text).dump(&config.to_yaml()).unwrap();
+        let directory = write_files("model-dump-reload", &[("config.yml", &text)]);
+        let reloaded = load_config(&directory.join("config.yml").to_string_lossy().into_owned(), &[]).unwrap();
+        assert_eq!(dependency_names(&reloaded), vec!["db", "cache"]);
+    }
+}
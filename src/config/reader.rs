@@ -0,0 +1,223 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use yaml_rust::Yaml;
+use super::errors::ConfigError;
+use super::locate::find_line;
+use super::sources::{Origins, Source};
+
+const NO_VALUES: &'static [Yaml] = &[];
+
+/// Reads typed values out of the parsed YAML. Instead of failing on the first
+/// problem every missing or mistyped value is recorded together with its path
+/// and line, and a default is returned so reading can carry on.
+pub struct ConfigReader {
+    files: Vec<Source>,
+    origins: Origins,
+    errors: Vec<ConfigError>,
+}
+
+impl ConfigReader {
+    pub fn new(files: Vec<Source>, origins: Origins) -> ConfigReader {
+        ConfigReader {
+            files: files,
+            origins: origins,
+            errors: vec![],
+        }
+    }
+
+    pub fn child_path(path: &str, key: &str) -> String {
+        match path.is_empty() {
+            true => key.to_string(),
+            false => format!("{}.{}", path, key),
+        }
+    }
+
+    pub fn item_path(path: &str, idx: usize) -> String {
+        format!("{}[{}]", path, idx)
+    }
+
+    pub fn error(&mut self, path: &str, message: String) {
+        let (file, line) = match self.origins.lookup(path) {
+            Some((file, source_path)) => {
+                let source = &self.files[file];
+                (source.file.clone(), find_line(&source.text, &source_path))
+            }
+            None => (self.files[0].file.clone(), None),
+        };
+
+        self.errors.push(ConfigError {
+            file: file,
+            path: path.to_string(),
+            line: line,
+            message: message,
+        });
+    }
+
+    /// All files the config was read from, the main file first.
+    pub fn file_names(&self) -> Vec<String> {
+        self.files.iter().map(|source| source.file.clone()).collect()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn into_errors(self) -> Vec<ConfigError> {
+        self.errors
+    }
+
+    pub fn string(&mut self, element: &Yaml, path: &str, key: &str) -> String {
+        self.string_or(element, path, key, "")
+    }
+
+    pub fn string_or(&mut self, element: &Yaml, path: &str, key: &str, default: &str) -> String {
+        match element[key] {
+            Yaml::BadValue | Yaml::Null => default.to_string(),
+            Yaml::String(ref value) => value.clone(),
+            _ => {
+                self.error(&ConfigReader::child_path(path, key), "must be a string".to_string());
+                default.to_string()
+            }
+        }
+    }
+
+    pub fn required_string(&mut self, element: &Yaml, path: &str, key: &str) -> String {
+        if element[key].is_badvalue() {
+            self.error(&ConfigReader::child_path(path, key), "missing".to_string());
+            return "".to_string();
+        }
+
+        let value = self.string(element, path, key);
+        if value.is_empty() {
+            self.error(&ConfigReader::child_path(path, key), "must not be empty".to_string());
+        }
+        value
+    }
+
+    pub fn int(&mut self, element: &Yaml, path: &str, key: &str, default: i64) -> i64 {
+        match element[key] {
+            Yaml::BadValue | Yaml::Null => default,
+            Yaml::Integer(value) => value,
+            _ => {
+                self.error(&ConfigReader::child_path(path, key), "must be an integer".to_string());
+                default
+            }
+        }
+    }
+
+    pub fn required_int(&mut self, element: &Yaml, path: &str, key: &str) -> i64 {
+        if element[key].is_badvalue() {
+            self.error(&ConfigReader::child_path(path, key), "missing".to_string());
+            return 0;
+        }
+
+        self.int(element, path, key, 0)
+    }
+
+    pub fn positive_int(&mut self, element: &Yaml, path: &str, key: &str, default: Option<i64>) -> i64 {
+        let value = match default {
+            Some(default) => self.int(element, path, key, default),
+            None => self.required_int(element, path, key),
+        };
+
+        if value <= 0 && !element[key].is_badvalue() {
+            self.error(&ConfigReader::child_path(path, key), "must be greater than 0".to_string());
+        }
+        value
+    }
+
+    // integers are accepted too, `memory: 256` means 256.0
+    pub fn float(&mut self, element: &Yaml, path: &str, key: &str, default: f64) -> f64 {
+        match element[key] {
+            Yaml::BadValue | Yaml::Null => default,
+            Yaml::Integer(value) => value as f64,
+            Yaml::Real(_) => element[key].as_f64().unwrap_or(default),
+            _ => {
+                self.error(&ConfigReader::child_path(path, key), "must be a number".to_string());
+                default
+            }
+        }
+    }
+
+    pub fn bool(&mut self, element: &Yaml, path: &str, key: &str, default: bool) -> bool {
+        match element[key] {
+            Yaml::BadValue | Yaml::Null => default,
+            Yaml::Boolean(value) => value,
+            _ => {
+                self.error(&ConfigReader::child_path(path, key), "must be true or false".to_string());
+                default
+            }
+        }
+    }
+
+    pub fn required_bool(&mut self, element: &Yaml, path: &str, key: &str) -> bool {
+        if element[key].is_badvalue() {
+            self.error(&ConfigReader::child_path(path, key), "missing".to_string());
+            return false;
+        }
+
+        self.bool(element, path, key, false)
+    }
+
+    pub fn list<'y>(&mut self, element: &'y Yaml, path: &str, key: &str, required: bool) -> &'y [Yaml] {
+        match element[key] {
+            Yaml::Array(ref values) => values,
+            Yaml::BadValue | Yaml::Null => {
+                if required {
+                    self.error(&ConfigReader::child_path(path, key), "missing".to_string());
+                }
+                NO_VALUES
+            }
+            _ => {
+                self.error(&ConfigReader::child_path(path, key), "must be a list".to_string());
+                NO_VALUES
+            }
+        }
+    }
+
+    pub fn string_list(&mut self, element: &Yaml, path: &str, key: &str) -> Vec<String> {
+        let list_path = ConfigReader::child_path(path, key);
+        let mut result = vec![];
+
+        for (idx, value) in self.list(element, path, key, false).iter().enumerate() {
+            match value.as_str() {
+                Some(value) => result.push(value.to_string()),
+                None => self.error(&ConfigReader::item_path(&list_path, idx), "must be a string".to_string()),
+            }
+        }
+
+        result
+    }
+
+    /// Checks that `element` is a mapping, e.g. a list entry or an optional section.
+    pub fn expect_hash(&mut self, element: &Yaml, path: &str) -> bool {
+        match *element {
+            Yaml::Hash(_) => true,
+            Yaml::BadValue => false,
+            _ => {
+                self.error(path, "must be a mapping".to_string());
+                false
+            }
+        }
+    }
+}
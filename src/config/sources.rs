@@ -0,0 +1,661 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+// Reads the main config file together with everything it pulls in and merges
+// it all into one document:
+//
+//  * `include:` lists files or glob patterns that are merged before the file
+//    itself, so the including file overrides what it includes
+//  * `services_from:` on a service group and `system_services_from:` under
+//    `healthcheck` load every service file matching a glob pattern, each file
+//    holds one service or a list of them
+//  * `overlays:` in the main file and `--overlay` on the command line are
+//    merged last, in that order
+//
+// Mappings merge key by key, lists of mappings that all have a `name` merge
+// entry by entry matched on the name, anything else is replaced. Relative paths
+// are relative to the file they appear in. For every value the file and path it
+// came from is recorded, so problems are reported against the file to fix.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use glob::glob;
+use yaml_rust::{Yaml, YamlLoader};
+use super::errors::ConfigError;
+
+pub struct Source {
+    pub file: String,
+    pub text: String,
+}
+
+/// Maps a path in the merged document to the source file and the path in it.
+pub struct Origins {
+    entries: BTreeMap<String, (usize, String)>,
+}
+
+pub struct ConfigSources {
+    pub doc: Yaml,
+    pub files: Vec<Source>,
+    pub origins: Origins,
+    pub errors: Vec<ConfigError>,
+    stack: Vec<PathBuf>,
+}
+
+impl Origins {
+    fn new() -> Origins {
+        Origins { entries: BTreeMap::new() }
+    }
+
+    fn record(&mut self, doc: &Yaml, file: usize, merged_path: &str, source_path: &str) {
+        self.entries.insert(merged_path.to_string(), (file, source_path.to_string()));
+
+        match *doc {
+            Yaml::Hash(ref hash) => {
+                for (key, value) in hash {
+                    if let Some(key) = key.as_str() {
+                        self.record(value, file, &child_path(merged_path, key), &child_path(source_path, key));
+                    }
+                }
+            }
+            Yaml::Array(ref items) => {
+                for (idx, item) in items.iter().enumerate() {
+                    self.record(item, file, &item_path(merged_path, idx), &item_path(source_path, idx));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn forget(&mut self, merged_path: &str) {
+        let nested: Vec<String> = self.entries
+            .keys()
+            .filter(|path| is_within(path, merged_path))
+            .cloned()
+            .collect();
+        for path in nested {
+            self.entries.remove(&path);
+        }
+    }
+
+    /// File index and source path for `merged_path`, keys missing from the
+    /// merged document are resolved against their closest parent.
+    pub fn lookup(&self, merged_path: &str) -> Option<(usize, String)> {
+        let mut candidate = merged_path;
+
+        loop {
+            if let Some(&(file, ref source_path)) = self.entries.get(candidate) {
+                let rest = &merged_path[candidate.len()..];
+                let rest = match source_path.is_empty() {
+                    true => rest.trim_left_matches('.'),
+                    false => rest,
+                };
+                return Some((file, format!("{}{}", source_path, rest)));
+            }
+
+            if candidate.is_empty() {
+                return None;
+            }
+            candidate = match candidate.rfind(|c: char| c == '.' || c == '[') {
+                Some(pos) => &candidate[..pos],
+                None => "",
+            };
+        }
+    }
+}
+
+impl ConfigSources {
+    pub fn load(config_file: &str, overlays: &[String]) -> ConfigSources {
+        let mut sources = ConfigSources {
+            doc: Yaml::Hash(BTreeMap::new()),
+            files: vec![],
+            origins: Origins::new(),
+            errors: vec![],
+            stack: vec![],
+        };
+
+        let mut overlay_files = sources.load_file(Path::new(config_file), true);
+        if !sources.files.is_empty() {
+            // problems with the document as a whole are reported against the main file
+            sources.origins.entries.insert("".to_string(), (0, "".to_string()));
+        }
+
+        overlay_files.extend(overlays.iter().map(PathBuf::from));
+        for overlay in &overlay_files {
+            sources.load_file(overlay, false);
+        }
+
+        sources
+    }
+
+    /// A single document that doesn't come from the config files, such as a
+    /// service posted to the api. Includes and overlays aren't followed.
+    pub fn from_text(name: &str, text: &str) -> ConfigSources {
+        let mut sources = ConfigSources {
+            doc: Yaml::Null,
+            files: vec![],
+            origins: Origins::new(),
+            errors: vec![],
+            stack: vec![],
+        };
+
+        let docs = match YamlLoader::load_from_str(text) {
+            Ok(docs) => docs,
+            Err(err) => {
+                sources.error(name, "", format!("{}", err));
+                return sources;
+            }
+        };
+
+        sources.files.push(Source {
+            file: name.to_string(),
+            text: text.to_string(),
+        });
+
+        match docs.into_iter().next() {
+            Some(doc) => {
+                sources.origins.record(&doc, 0, "", "");
+                sources.doc = doc;
+            }
+            None => sources.error(name, "", "is empty".to_string()),
+        }
+
+        sources
+    }
+
+    // returns the overlays declared in the main file
+    fn load_file(&mut self, path: &Path, is_main: bool) -> Vec<PathBuf> {
+        let file_name = path.to_string_lossy().into_owned();
+
+        let canonical_path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        if self.stack.iter().any(|parent| *parent == canonical_path) {
+            self.error(&file_name, "", "include cycle, the file ends up including itself".to_string());
+            return vec![];
+        }
+
+        let (file, mut doc) = match self.read_file(path) {
+            Some(result) => result,
+            None => return vec![],
+        };
+        if !doc.as_hash().is_some() {
+            self.error(&file_name, "", "must be a mapping at the top level".to_string());
+            return vec![];
+        }
+
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+        self.stack.push(canonical_path);
+        for include in self.read_paths(&mut doc, file, "include", &directory) {
+            self.load_file(&include, false);
+        }
+        self.stack.pop();
+
+        let overlays = self.read_paths(&mut doc, file, "overlays", &directory);
+        if !is_main && !overlays.is_empty() {
+            self.error(&file_name, "overlays", "only allowed in the main config file".to_string());
+        }
+
+        let service_dirs = self.take_service_dirs(&mut doc, file, &directory);
+
+        let mut merged = Yaml::Null;
+        ::std::mem::swap(&mut merged, &mut self.doc);
+        merge(&mut merged, "", doc, file, "", &mut self.origins);
+        self.doc = merged;
+
+        for (list_path, patterns) in service_dirs {
+            for service_file in patterns {
+                self.load_services(&list_path, &service_file);
+            }
+        }
+
+        match is_main {
+            true => overlays,
+            false => vec![],
+        }
+    }
+
+    fn read_file(&mut self, path: &Path) -> Option<(usize, Yaml)> {
+        let file_name = path.to_string_lossy().into_owned();
+
+        let mut text = String::new();
+        let read_result = File::open(path).and_then(|mut file| file.read_to_string(&mut text));
+        if let Err(err) = read_result {
+            self.error(&file_name, "", format!("can't read file: {}", err));
+            return None;
+        }
+
+        let docs = match YamlLoader::load_from_str(&text) {
+            Ok(docs) => docs,
+            Err(err) => {
+                self.error(&file_name, "", format!("{}", err));
+                return None;
+            }
+        };
+
+        self.files.push(Source {
+            file: file_name.clone(),
+            text: text,
+        });
+
+        // Multi document support, only the first one is used
+        match docs.into_iter().next() {
+            Some(doc) => Some((self.files.len() - 1, doc)),
+            None => {
+                self.error(&file_name, "", "is empty".to_string());
+                None
+            }
+        }
+    }
+
+    // removes `key` from the document and expands the file names or patterns it lists
+    fn read_paths(&mut self, doc: &mut Yaml, file: usize, key: &str, directory: &Path) -> Vec<PathBuf> {
+        let value = match take_key(doc, key) {
+            Some(value) => value,
+            None => return vec![],
+        };
+        self.expand_paths(&value, file, key, directory)
+    }
+
+    fn expand_paths(&mut self, value: &Yaml, file: usize, path: &str, directory: &Path) -> Vec<PathBuf> {
+        let patterns = match *value {
+            Yaml::String(ref pattern) => vec![(path.to_string(), pattern.clone())],
+            Yaml::Array(ref items) => {
+                let mut patterns = vec![];
+                for (idx, item) in items.iter().enumerate() {
+                    match item.as_str() {
+                        Some(pattern) => patterns.push((item_path(path, idx), pattern.to_string())),
+                        None => self.file_error(file, &item_path(path, idx), "must be a file name or pattern".to_string()),
+                    }
+                }
+                patterns
+            }
+            _ => {
+                self.file_error(file, path, "must be a file name or pattern, or a list of them".to_string());
+                vec![]
+            }
+        };
+
+        let mut result = vec![];
+        for (pattern_path, pattern) in patterns {
+            let full_pattern = directory.join(&pattern);
+
+            // plain file names are read as they are so a missing file gets reported
+            if !pattern.contains(|c: char| c == '*' || c == '?' || c == '[') {
+                result.push(full_pattern);
+                continue;
+            }
+
+            match glob(&full_pattern.to_string_lossy()) {
+                Ok(paths) => {
+                    let mut matches: Vec<PathBuf> = paths.filter_map(|path| path.ok()).collect();
+                    matches.sort();
+                    result.extend(matches);
+                }
+                Err(err) => self.file_error(file, &pattern_path, format!("invalid pattern {}: {}", pattern, err)),
+            }
+        }
+
+        result
+    }
+
+    // finds the `services_from` patterns of a file before it gets merged, returns them
+    // by the path of the list in the merged document they go into
+    fn take_service_dirs(&mut self, doc: &mut Yaml, file: usize, directory: &Path) -> Vec<(String, Vec<PathBuf>)> {
+        let mut result = vec![];
+
+        if let Some(healthcheck) = get_key_mut(doc, "healthcheck") {
+            if let Some(value) = take_key(healthcheck, "system_services_from") {
+                let paths = self.expand_paths(&value, file, "healthcheck.system_services_from", directory);
+                result.push(("healthcheck.system_services".to_string(), paths));
+            }
+        }
+
+        let groups = get_key_mut(doc, "api").and_then(|api| get_key_mut(api, "service-groups"));
+        if let Some(&mut Yaml::Array(ref mut groups)) = groups {
+            for (idx, group) in groups.iter_mut().enumerate() {
+                if let Some(value) = take_key(group, "services_from") {
+                    let path = format!("api.service-groups[{}].services_from", idx);
+                    let paths = self.expand_paths(&value, file, &path, directory);
+                    match group["name"].as_str() {
+                        Some(name) => result.push((format!("group:{}", name), paths)),
+                        None => self.file_error(file, &path, "the service group needs a name".to_string()),
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    fn load_services(&mut self, list_path: &str, path: &Path) {
+        let (file, doc) = match self.read_file(path) {
+            Some(result) => result,
+            None => return,
+        };
+
+        let list_path = match self.find_list_path(list_path) {
+            Some(list_path) => list_path,
+            None => return,
+        };
+        let items = match doc {
+            Yaml::Hash(_) => vec![(doc, "".to_string())],
+            Yaml::Array(items) => items.into_iter().enumerate().map(|(idx, item)| (item, item_path("", idx))).collect(),
+            _ => {
+                self.file_error(file, "", "must be a service or a list of services".to_string());
+                return;
+            }
+        };
+
+        let mut merged = Yaml::Null;
+        ::std::mem::swap(&mut merged, &mut self.doc);
+        {
+            let list = ensure_list(&mut merged, &list_path);
+            for (item, source_path) in items {
+                merge_named_item(list, &list_path, item, file, &source_path, &mut self.origins);
+            }
+        }
+        self.doc = merged;
+    }
+
+    // `group:NAME` stands for the services of the service group with that name
+    fn find_list_path(&self, list_path: &str) -> Option<String> {
+        if !list_path.starts_with("group:") {
+            return Some(list_path.to_string());
+        }
+
+        let name = &list_path[6..];
+        match self.doc["api"]["service-groups"].as_vec() {
+            Some(groups) => {
+                groups.iter()
+                    .position(|group| group["name"].as_str() == Some(name))
+                    .map(|idx| format!("api.service-groups[{}].services", idx))
+            }
+            None => None,
+        }
+    }
+
+    fn file_error(&mut self, file: usize, path: &str, message: String) {
+        let file_name = self.files[file].file.clone();
+        let line = super::locate::find_line(&self.files[file].text, path);
+        self.errors.push(ConfigError {
+            file: file_name,
+            path: path.to_string(),
+            line: line,
+            message: message,
+        });
+    }
+
+    fn error(&mut self, file_name: &str, path: &str, message: String) {
+        self.errors.push(ConfigError {
+            file: file_name.to_string(),
+            path: path.to_string(),
+            line: None,
+            message: message,
+        });
+    }
+}
+
+pub fn merge(base: &mut Yaml, base_path: &str, doc: Yaml, file: usize, source_path: &str, origins: &mut Origins) {
+    let doc = match doc {
+        Yaml::Hash(hash) => {
+            if let Yaml::Hash(ref mut base_hash) = *base {
+                for (key, value) in hash {
+                    let key_name = key.as_str().unwrap_or("").to_string();
+                    let child_base_path = child_path(base_path, &key_name);
+                    let child_source_path = child_path(source_path, &key_name);
+
+                    if base_hash.contains_key(&key) {
+                        merge(base_hash.get_mut(&key).unwrap(),
+                              &child_base_path,
+                              value,
+                              file,
+                              &child_source_path,
+                              origins);
+                    } else {
+                        origins.record(&value, file, &child_base_path, &child_source_path);
+                        base_hash.insert(key, value);
+                    }
+                }
+                return;
+            }
+            Yaml::Hash(hash)
+        }
+        Yaml::Array(items) => {
+            if is_named_list(&items) {
+                if let Yaml::Array(ref mut base_items) = *base {
+                    if is_named_list(base_items) {
+                        for (idx, item) in items.into_iter().enumerate() {
+                            merge_named_item(base_items, base_path, item, file, &item_path(source_path, idx), origins);
+                        }
+                        return;
+                    }
+                }
+            }
+            Yaml::Array(items)
+        }
+        doc => doc,
+    };
+
+    origins.forget(base_path);
+    origins.record(&doc, file, base_path, source_path);
+    *base = doc;
+}
+
+fn merge_named_item(list: &mut Vec<Yaml>,
+                    list_path: &str,
+                    item: Yaml,
+                    file: usize,
+                    source_path: &str,
+                    origins: &mut Origins) {
+    let existing = match item["name"].as_str() {
+        Some(name) => list.iter().position(|other| other["name"].as_str() == Some(name)),
+        None => None,
+    };
+
+    match existing {
+        Some(idx) => merge(&mut list[idx], &item_path(list_path, idx), item, file, source_path, origins),
+        None => {
+            origins.record(&item, file, &item_path(list_path, list.len()), source_path);
+            list.push(item);
+        }
+    }
+}
+
+fn is_named_list(items: &Vec<Yaml>) -> bool {
+    !items.is_empty() && items.iter().all(|item| item["name"].as_str().is_some())
+}
+
+fn take_key(doc: &mut Yaml, key: &str) -> Option<Yaml> {
+    match *doc {
+        Yaml::Hash(ref mut hash) => hash.remove(&Yaml::String(key.to_string())),
+        _ => None,
+    }
+}
+
+fn get_key_mut<'a>(doc: &'a mut Yaml, key: &str) -> Option<&'a mut Yaml> {
+    match *doc {
+        Yaml::Hash(ref mut hash) => hash.get_mut(&Yaml::String(key.to_string())),
+        _ => None,
+    }
+}
+
+// the list at a path like `healthcheck.system_services`, created if it isn't there yet
+fn ensure_list<'a>(doc: &'a mut Yaml, path: &str) -> &'a mut Vec<Yaml> {
+    let mut current = doc;
+
+    for part in path.split('.') {
+        let (key, index) = match part.find('[') {
+            Some(pos) => (&part[..pos], part[pos + 1..].trim_right_matches(']').parse::<usize>().ok()),
+            None => (part, None),
+        };
+
+        let node = current;
+        if !node.as_hash().is_some() {
+            *node = Yaml::Hash(BTreeMap::new());
+        }
+        let child = match *node {
+            Yaml::Hash(ref mut hash) => hash.entry(Yaml::String(key.to_string())).or_insert(Yaml::Null),
+            _ => unreachable!(),
+        };
+        current = match index {
+            Some(index) => {
+                match *child {
+                    Yaml::Array(ref mut items) => &mut items[index],
+                    _ => unreachable!(),
+                }
+            }
+            None => child,
+        };
+    }
+
+    if !current.as_vec().is_some() {
+        *current = Yaml::Array(vec![]);
+    }
+    match *current {
+        Yaml::Array(ref mut items) => items,
+        _ => unreachable!(),
+    }
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+fn item_path(path: &str, idx: usize) -> String {
+    format!("{}[{}]", path, idx)
+}
+
+fn is_within(path: &str, parent: &str) -> bool {
+    if parent.is_empty() {
+        return true;
+    }
+    path == parent || path.starts_with(parent) && (path[parent.len()..].starts_with('.') || path[parent.len()..].starts_with('['))
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::{Yaml, YamlLoader};
+    use super::{ConfigSources, Origins, merge, merge_named_item};
+    use super::super::fixtures::write_files;
+
+    fn parse(text: &str) -> Yaml {
+        YamlLoader::load_from_str(text).unwrap().into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn merge_combines_mappings_and_replaces_scalars_and_lists() {
+        let mut base = parse("api:\n  port: 3005\n  listen_address: 0.0.0.0\nports: [1, 2]\n");
+        let mut origins = Origins::new();
+        origins.record(&base, 0, "", "");
+
+        merge(&mut base, "", parse("api:\n  port: 4000\nports: [3]\nname: edge\n"), 1, "", &mut origins);
+
+        assert_eq!(base, parse("api:\n  port: 4000\n  listen_address: 0.0.0.0\nports: [3]\nname: edge\n"));
+        assert_eq!(origins.lookup("api.port"), Some((1, "api.port".to_string())));
+        assert_eq!(origins.lookup("api.listen_address"), Some((0, "api.listen_address".to_string())));
+        assert_eq!(origins.lookup("ports[0]"), Some((1, "ports[0]".to_string())));
+        assert_eq!(origins.lookup("ports[1]"), Some((1, "ports[1]".to_string())));
+    }
+
+    #[test]
+    fn merge_named_item_overrides_items_with_the_same_name() {
+        let mut list = vec![parse("name: grafana\nimage: grafana:3\nmemory: 256"), parse("name: influx\nimage: influx:1")];
+        let mut origins = Origins::new();
+
+        merge_named_item(&mut list, "services", parse("name: grafana\nimage: grafana:4"), 1, "[0]", &mut origins);
+        merge_named_item(&mut list, "services", parse("name: loki\nimage: loki:2"), 1, "[1]", &mut origins);
+
+        assert_eq!(list,
+                   vec![parse("name: grafana\nimage: grafana:4\nmemory: 256"),
+                        parse("name: influx\nimage: influx:1"),
+                        parse("name: loki\nimage: loki:2")]);
+        assert_eq!(origins.lookup("services[0].image"), Some((1, "[0].image".to_string())));
+        assert_eq!(origins.lookup("services[2].name"), Some((1, "[1].name".to_string())));
+    }
+
+    #[test]
+    fn overlays_override_named_list_items() {
+        let main = ["api:",
+                    "  service-groups:",
+                    "    - name: monitoring",
+                    "      services:",
+                    "        - name: grafana",
+                    "          image: grafana:3",
+                    "        - name: influx",
+                    "          image: influx:1"]
+            .join("\n");
+        let edge = ["api:",
+                    "  service-groups:",
+                    "    - name: monitoring",
+                    "      services:",
+                    "        - name: grafana",
+                    "          image: grafana:4"]
+            .join("\n");
+        let directory = write_files("sources-overlay", &[("main.yml", &main), ("edge.yml", &edge)]);
+        let main_file = directory.join("main.yml").to_string_lossy().into_owned();
+        let overlay = directory.join("edge.yml").to_string_lossy().into_owned();
+
+        let sources = ConfigSources::load(&main_file, &[overlay.clone()]);
+
+        assert!(sources.errors.is_empty());
+        let services = &sources.doc["api"]["service-groups"][0]["services"];
+        assert_eq!(services[0]["image"].as_str(), Some("grafana:4"));
+        assert_eq!(services[1]["image"].as_str(), Some("influx:1"));
+        assert_eq!(services.as_vec().map(|services| services.len()), Some(2));
+
+        let (file, _) = sources.origins.lookup("api.service-groups[0].services[0].image").unwrap();
+        assert_eq!(sources.files[file].file, overlay);
+        let (file, _) = sources.origins.lookup("api.service-groups[0].services[1].image").unwrap();
+        assert_eq!(sources.files[file].file, main_file);
+    }
+
+    #[test]
+    fn includes_are_overridden_by_the_including_file() {
+        let directory = write_files("sources-include",
+                                    &[("main.yml", "include: [defaults.yml]\nname: edge\n"),
+                                      ("defaults.yml", "name: default\nreload:\n  watch_interval_in_seconds: 10\n")]);
+
+        let sources = ConfigSources::load(&directory.join("main.yml").to_string_lossy(), &[]);
+
+        assert!(sources.errors.is_empty());
+        assert_eq!(sources.doc, parse("name: edge\nreload:\n  watch_interval_in_seconds: 10\n"));
+    }
+
+    #[test]
+    fn include_cycles_are_reported() {
+        let directory = write_files("sources-cycle",
+                                    &[("main.yml", "include: [a.yml]\nname: edge\n"),
+                                      ("a.yml", "include: [b.yml]\n"),
+                                      ("b.yml", "include: [a.yml]\n")]);
+
+        let sources = ConfigSources::load(&directory.join("main.yml").to_string_lossy(), &[]);
+
+        assert_eq!(sources.errors.len(), 1);
+        assert!(sources.errors[0].file.ends_with("a.yml"));
+        assert_eq!(sources.errors[0].message, "include cycle, the file ends up including itself");
+        assert_eq!(sources.doc["name"].as_str(), Some("edge"));
+    }
+}
@@ -0,0 +1,234 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::BTreeMap;
+use yaml_rust::Yaml;
+use super::reader::ConfigReader;
+
+/// Named service templates from the `templates:` section. A service or another
+/// template picks one with `extends: NAME` and gets all of its fields, fields
+/// set on the service itself win. Lists such as `volumes` are replaced, not
+/// merged.
+pub struct Templates {
+    templates: BTreeMap<String, BTreeMap<Yaml, Yaml>>,
+    // reported while reading the section already
+    invalid: Vec<String>,
+}
+
+impl Templates {
+    pub fn read(reader: &mut ConfigReader, doc: &Yaml) -> Templates {
+        let mut result = Templates {
+            templates: BTreeMap::new(),
+            invalid: vec![],
+        };
+
+        let section = match doc["templates"] {
+            Yaml::Hash(ref section) => section.clone(),
+            Yaml::BadValue | Yaml::Null => return result,
+            _ => {
+                reader.error("templates", "must be a mapping of template names to service fields".to_string());
+                return result;
+            }
+        };
+
+        for name in section.keys().filter_map(|name| name.as_str()) {
+            let mut chain = vec![];
+            match flatten(reader, &section, name, &mut chain) {
+                Some(fields) => {
+                    result.templates.insert(name.to_string(), fields);
+                }
+                None => result.invalid.push(name.to_string()),
+            }
+        }
+
+        result
+    }
+
+    /// The service definition with its template applied, `path` is where it sits in the config.
+    pub fn apply(&self, reader: &mut ConfigReader, service: &Yaml, path: &str) -> Yaml {
+        let name = match service["extends"] {
+            Yaml::BadValue => return service.clone(),
+            Yaml::String(ref name) => name.clone(),
+            _ => {
+                reader.error(&ConfigReader::child_path(path, "extends"), "must be a template name".to_string());
+                return service.clone();
+            }
+        };
+
+        match self.templates.get(&name) {
+            Some(fields) => Yaml::Hash(override_fields(fields, service)),
+            None => {
+                if !self.invalid.contains(&name) {
+                    reader.error(&ConfigReader::child_path(path, "extends"),
+                                 format!("unknown template {}", name));
+                }
+                service.clone()
+            }
+        }
+    }
+}
+
+// the fields of template `name` with everything it extends resolved
+fn flatten(reader: &mut ConfigReader,
+           section: &BTreeMap<Yaml, Yaml>,
+           name: &str,
+           chain: &mut Vec<String>)
+           -> Option<BTreeMap<Yaml, Yaml>> {
+    let path = ConfigReader::child_path("templates", name);
+
+    let template = match section.get(&Yaml::String(name.to_string())) {
+        Some(&Yaml::Hash(ref template)) => template,
+        Some(_) => {
+            reader.error(&path, "must be a mapping of service fields".to_string());
+            return None;
+        }
+        None => return None,
+    };
+
+    let parent_name = match template.get(&Yaml::String("extends".to_string())) {
+        None => return Some(template.clone()),
+        Some(&Yaml::String(ref parent_name)) => parent_name.clone(),
+        Some(_) => {
+            reader.error(&ConfigReader::child_path(&path, "extends"), "must be a template name".to_string());
+            return None;
+        }
+    };
+
+    // a cycle is reported by the templates in it, not by the ones extending into it
+    if chain.contains(&parent_name) || parent_name == name {
+        if chain.is_empty() || chain[0] == parent_name {
+            chain.push(name.to_string());
+            chain.push(parent_name.clone());
+            reader.error(&ConfigReader::child_path(&path, "extends"),
+                         format!("templates extend each other: {}", chain.join(" -> ")));
+        }
+        return None;
+    }
+
+    if !section.contains_key(&Yaml::String(parent_name.clone())) {
+        reader.error(&ConfigReader::child_path(&path, "extends"),
+                     format!("unknown template {}", parent_name));
+        return None;
+    }
+
+    chain.push(name.to_string());
+    let parent = flatten(reader, section, &parent_name, chain);
+    chain.pop();
+
+    parent.map(|parent| override_fields(&parent, &Yaml::Hash(template.clone())))
+}
+
+fn override_fields(base: &BTreeMap<Yaml, Yaml>, fields: &Yaml) -> BTreeMap<Yaml, Yaml> {
+    let mut result = base.clone();
+    if let Yaml::Hash(ref fields) = *fields {
+        for (key, value) in fields {
+            result.insert(key.clone(), value.clone());
+        }
+    }
+    result.remove(&Yaml::String("extends".to_string()));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::{error_messages, load_services, service};
+
+    fn templates(lines: &[&str]) -> String {
+        let mut result = lines.join("\n");
+        result.push('\n');
+        result
+    }
+
+    fn small_and_large() -> String {
+        templates(&["templates:",
+                    "  small:",
+                    "    cpu: 0.5",
+                    "    memory: 256",
+                    "    volumes: [{host_path: /var/log, container_path: /log, read_only_mode: true}]",
+                    "  large:",
+                    "    extends: small",
+                    "    memory: 1024"])
+    }
+
+    #[test]
+    fn templates_extend_other_templates() {
+        let config = load_services("templates-extend",
+                                   &small_and_large(),
+                                   &[service("web", &["extends: large", "cpu: 2"])])
+            .unwrap();
+
+        let web = &config.api.service_groups[0].services[0];
+        assert_eq!(web.cpu, 2.0);
+        assert_eq!(web.memory, 1024.0);
+        assert_eq!(web.volumes.len(), 1);
+        assert_eq!(web.volumes[0].host_path, "/var/log");
+    }
+
+    #[test]
+    fn lists_are_replaced_rather_than_merged() {
+        let config = load_services("templates-lists",
+                                   &small_and_large(),
+                                   &[service("web",
+                                             &["extends: small",
+                                               "volumes: [{host_path: /data, container_path: /data, \
+                                                read_only_mode: false}]"])])
+            .unwrap();
+
+        let web = &config.api.service_groups[0].services[0];
+        assert_eq!(web.volumes.len(), 1);
+        assert_eq!(web.volumes[0].host_path, "/data");
+        assert_eq!(web.memory, 256.0);
+    }
+
+    #[test]
+    fn cycles_are_reported_by_the_templates_in_them() {
+        let errors = load_services("templates-cycle",
+                                   &templates(&["templates:",
+                                                "  a:",
+                                                "    extends: b",
+                                                "  b:",
+                                                "    extends: a",
+                                                "  c:",
+                                                "    extends: a",
+                                                "  d:",
+                                                "    extends: d"]),
+                                   &[service("web", &["extends: c"])])
+            .unwrap_err();
+
+        assert_eq!(error_messages(errors),
+                   vec!["templates.a.extends: templates extend each other: b -> a -> b",
+                        "templates.b.extends: templates extend each other: a -> b -> a",
+                        "templates.d.extends: templates extend each other: d -> d"]);
+    }
+
+    #[test]
+    fn unknown_templates_are_reported() {
+        let errors = load_services("templates-unknown",
+                                   &templates(&["templates:", "  e:", "    extends: nothing"]),
+                                   &[service("web", &["extends: missing"])])
+            .unwrap_err();
+
+        assert_eq!(error_messages(errors),
+                   vec!["templates.e.extends: unknown template nothing",
+                        "api.service-groups[0].services[0].extends: unknown template missing"]);
+    }
+}
@@ -0,0 +1,169 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::collections::{BTreeMap, HashSet};
+use yaml_rust::Yaml;
+use state::SLA;
+use utils::{Reference, read_task, template_references};
+use super::graph::find_cycle;
+use super::model::{Workflow, WorkflowStep};
+use super::reader::ConfigReader;
+use super::templates::Templates;
+
+pub fn read_workflows(reader: &mut ConfigReader, templates: &Templates, doc: &Yaml) -> Vec<Workflow> {
+    let mut workflows = vec![];
+    let mut names = HashSet::new();
+
+    for (idx, workflow) in reader.list(doc, "", "workflows", false).iter().enumerate() {
+        let path = ConfigReader::item_path("workflows", idx);
+        if !reader.expect_hash(workflow, &path) {
+            continue;
+        }
+
+        let workflow = read_workflow(reader, templates, workflow, &path);
+        if !workflow.name.is_empty() && !names.insert(workflow.name.clone()) {
+            reader.error(&ConfigReader::child_path(&path, "name"),
+                         format!("workflow {} is defined more than once", workflow.name));
+        }
+        workflows.push(workflow);
+    }
+
+    workflows
+}
+
+/// Reads a workflow, every step is a service definition with an optional list
+/// of the steps it comes `after`.
+pub fn read_workflow(reader: &mut ConfigReader, templates: &Templates, workflow: &Yaml, path: &str) -> Workflow {
+    let name = reader.required_string(workflow, path, "name");
+    let steps_path = ConfigReader::child_path(path, "steps");
+    let mut steps: Vec<WorkflowStep> = vec![];
+
+    for (idx, step) in reader.list(workflow, path, "steps", true).iter().enumerate() {
+        let step_path = ConfigReader::item_path(&steps_path, idx);
+        if !reader.expect_hash(step, &step_path) {
+            continue;
+        }
+
+        let task = read_task(reader, templates, &as_job(step), &step_path);
+        if !task.is_job {
+            reader.error(&ConfigReader::child_path(&step_path, "is_job"),
+                         "workflow steps are jobs".to_string());
+        }
+        if task.schedule.is_some() {
+            reader.error(&ConfigReader::child_path(&step_path, "schedule"),
+                         "steps run when their workflow does, they can't be scheduled".to_string());
+        }
+        if task.number_of_instances != 1 || task.sla != SLA::None {
+            reader.error(&step_path,
+                         "steps run a single instance, number_of_instances and sla can't be set".to_string());
+        }
+
+        if !task.name.is_empty() && steps.iter().any(|other| other.name == task.name) {
+            reader.error(&ConfigReader::child_path(&step_path, "name"),
+                         format!("step {} is defined more than once", task.name));
+        }
+        steps.push(WorkflowStep {
+            name: task.name.clone(),
+            after: reader.string_list(step, &step_path, "after"),
+            task: task,
+        });
+    }
+
+    check_steps(reader, &steps, &steps_path);
+
+    Workflow {
+        name: name,
+        steps: steps,
+    }
+}
+
+// `is_job` defaults to true for steps
+fn as_job(step: &Yaml) -> Yaml {
+    let mut step = step.as_hash().cloned().unwrap_or(Default::default());
+    let is_job = Yaml::String("is_job".to_string());
+    if !step.contains_key(&is_job) {
+        step.insert(is_job, Yaml::Boolean(true));
+    }
+    Yaml::Hash(step)
+}
+
+// unknown or cyclic `after` steps, and step outputs of steps that don't run before
+fn check_steps(reader: &mut ConfigReader, steps: &Vec<WorkflowStep>, steps_path: &str) {
+    let mut edges: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (idx, step) in steps.iter().enumerate() {
+        let after_path = ConfigReader::child_path(&ConfigReader::item_path(steps_path, idx), "after");
+        for (after_idx, after) in step.after.iter().enumerate() {
+            if *after == step.name {
+                reader.error(&ConfigReader::item_path(&after_path, after_idx),
+                             "a step can't run after itself".to_string());
+            } else if !steps.iter().any(|other| other.name == *after) {
+                reader.error(&ConfigReader::item_path(&after_path, after_idx),
+                             format!("unknown step {}", after));
+            }
+        }
+        edges.insert(step.name.clone(), step.after.clone());
+    }
+
+    let mut visited: HashSet<String> = HashSet::new();
+    for step in steps {
+        let mut stack = vec![];
+        if let Some(cycle) = find_cycle(&step.name, &edges, &mut visited, &mut stack) {
+            if cycle.len() > 2 {
+                let idx = steps.iter().position(|other| other.name == cycle[0]).unwrap_or(0);
+                reader.error(&ConfigReader::child_path(&ConfigReader::item_path(steps_path, idx), "after"),
+                             format!("step cycle {}", cycle.join(" -> ")));
+            }
+        }
+    }
+
+    for (idx, step) in steps.iter().enumerate() {
+        let earlier = earlier_steps(&step.name, &edges);
+        let step_path = ConfigReader::item_path(steps_path, idx);
+        for &(key, template) in &[("arguments", &step.task.arguments), ("parameters", &step.task.parameters)] {
+            for reference in template_references(template) {
+                if let Reference::StepOutput(ref name, ref output) = reference {
+                    if !earlier.contains(name) {
+                        reader.error(&ConfigReader::child_path(&step_path, key),
+                                     format!("${{step:{}:{}}}: {} doesn't run before {}, add it to after",
+                                             name,
+                                             output,
+                                             name,
+                                             step.name));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// every step that has to succeed before `name` starts
+fn earlier_steps(name: &String, edges: &BTreeMap<String, Vec<String>>) -> HashSet<String> {
+    let mut result = HashSet::new();
+    let mut pending = edges.get(name).cloned().unwrap_or(vec![]);
+
+    while let Some(step) = pending.pop() {
+        if result.insert(step.clone()) {
+            pending.extend(edges.get(&step).cloned().unwrap_or(vec![]));
+        }
+    }
+
+    result
+}
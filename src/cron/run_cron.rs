@@ -0,0 +1,446 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use chrono::{NaiveDateTime, UTC};
+use rustc_serialize::json;
+use config::Config;
+use state::{StateManager, TaskState};
+use utils::{ConcurrencyPolicy, MissedRuns, Task};
+use metrics;
+
+const CHECK_INTERVAL_IN_SECONDS: u64 = 10;
+// a run noticed later than this was missed, usually because the sub-scheduler was down
+const LATE_AFTER_IN_SECONDS: i64 = 60;
+
+lazy_static! {
+    static ref TRIGGERS: Arc<Mutex<VecDeque<Trigger>>> = {
+        Arc::new(Mutex::new(VecDeque::new()))
+    };
+}
+
+/// What was done when a scheduled job was due.
+#[derive(Clone, Debug)]
+struct Trigger {
+    job: String,
+    run: String,
+    scheduled_for: i64,
+    triggered_at: i64,
+    action: String,
+    detail: String,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct ScheduleStatus {
+    pub job: String,
+    pub cron: String,
+    pub timezone: String,
+    pub concurrency_policy: String,
+    pub missed_runs: String,
+    pub is_system_service: bool,
+    pub upcoming_runs: Vec<i64>,
+    /// The latest first.
+    pub past_runs: Vec<PastRun>,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct PastRun {
+    /// The task the run was started as, empty when nothing was started.
+    pub run: String,
+    pub scheduled_for: i64,
+    pub triggered_at: i64,
+    /// started, skipped or missed
+    pub action: String,
+    pub detail: String,
+    /// None once the run left the task list.
+    pub state: Option<TaskState>,
+    /// The result of its last attempt, empty while it hasn't completed.
+    pub result: String,
+}
+
+/// Starts a run of every scheduled job when its cron expression matches.
+pub fn run_cron(state_manager: &StateManager) {
+    log_info!("cron starting");
+    state_manager.send_ping();
+
+    let mut config = state_manager.get_config();
+    let mut last_runs = load_last_runs(&config.jobs.state_file);
+    // runs started per job that may still be active, for the concurrency policy
+    let mut active_runs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    loop {
+        let now = UTC::now().timestamp();
+        let jobs = scheduled_jobs(&config);
+
+        let mut changed = false;
+        for &(ref job, is_system_service) in &jobs {
+            if check_job(state_manager,
+                         &config,
+                         job,
+                         is_system_service,
+                         now,
+                         &mut last_runs,
+                         &mut active_runs) {
+                changed = true;
+            }
+        }
+
+        // forget jobs that were removed from the config
+        if last_runs.keys().any(|name| !jobs.iter().any(|&(ref job, _)| job.name == *name)) {
+            last_runs = last_runs.into_iter()
+                .filter(|&(ref name, _)| jobs.iter().any(|&(ref job, _)| job.name == *name))
+                .collect();
+            changed = true;
+        }
+
+        if changed && !config.jobs.state_file.is_empty() {
+            if let Err(error_msg) = save_last_runs(&config.jobs.state_file, &last_runs) {
+                log_error!("can't save job schedule state"; "path" => config.jobs.state_file, "error" => error_msg);
+            }
+        }
+
+        thread::sleep(Duration::from_secs(CHECK_INTERVAL_IN_SECONDS));
+        config = state_manager.get_config();
+    }
+}
+
+/// Every job with a schedule, system services and the services of every group,
+/// paired with whether it's a system service.
+pub fn scheduled_jobs(config: &Config) -> Vec<(Task, bool)> {
+    let mut result = vec![];
+
+    for task in &config.healthcheck.system_services {
+        if task.schedule.is_some() {
+            result.push((task.clone(), true));
+        }
+    }
+    for group in &config.api.service_groups {
+        for task in &group.services {
+            if task.schedule.is_some() && !result.iter().any(|&(ref job, _)| job.name == task.name) {
+                result.push((task.clone(), false));
+            }
+        }
+    }
+
+    result
+}
+
+/// The next `count` runs and the latest `limit` past runs of the scheduled
+/// jobs, or only of the job called `name`.
+pub fn describe_schedules(state_manager: &StateManager,
+                          config: &Config,
+                          name: Option<&str>,
+                          count: usize,
+                          limit: usize)
+                          -> Vec<ScheduleStatus> {
+    let now = UTC::now().timestamp();
+    let triggers = TRIGGERS.lock().unwrap().clone();
+    let mut result = vec![];
+
+    for (job, is_system_service) in scheduled_jobs(config) {
+        if name.map(|name| name != job.name).unwrap_or(false) {
+            continue;
+        }
+        let schedule = job.schedule.as_ref().unwrap();
+
+        let mut upcoming_runs = vec![];
+        let mut next = schedule.next_after(now);
+        while let Some(at) = next {
+            if upcoming_runs.len() >= count {
+                break;
+            }
+            upcoming_runs.push(at);
+            next = schedule.next_after(at);
+        }
+
+        let past_runs = triggers.iter()
+            .rev()
+            .filter(|trigger| trigger.job == job.name)
+            .take(limit)
+            .map(|trigger| {
+                let (state, result) = if trigger.run.is_empty() {
+                    (None, "".to_string())
+                } else {
+                    let state = state_manager.request_task(trigger.run.clone()).map(|task| task.state);
+                    let result = state_manager.request_job_runs(Some(trigger.run.clone()), 1)
+                        .first()
+                        .map(|run| run.result.clone())
+                        .unwrap_or("".to_string());
+                    (state, result)
+                };
+                PastRun {
+                    run: trigger.run.clone(),
+                    scheduled_for: trigger.scheduled_for,
+                    triggered_at: trigger.triggered_at,
+                    action: trigger.action.clone(),
+                    detail: trigger.detail.clone(),
+                    state: state,
+                    result: result,
+                }
+            })
+            .collect();
+
+        result.push(ScheduleStatus {
+            job: job.name.clone(),
+            cron: schedule.cron.clone(),
+            timezone: schedule.timezone.clone(),
+            concurrency_policy: schedule.concurrency_policy.name().to_string(),
+            missed_runs: schedule.missed_runs.name().to_string(),
+            is_system_service: is_system_service,
+            upcoming_runs: upcoming_runs,
+            past_runs: past_runs,
+        });
+    }
+
+    result
+}
+
+// returns whether the last run of the job changed
+fn check_job(state_manager: &StateManager,
+             config: &Config,
+             job: &Task,
+             is_system_service: bool,
+             now: i64,
+             last_runs: &mut BTreeMap<String, i64>,
+             active_runs: &mut BTreeMap<String, Vec<String>>)
+             -> bool {
+    let schedule = job.schedule.as_ref().unwrap();
+
+    let last_run = match last_runs.get(&job.name).cloned() {
+        Some(last_run) => last_run,
+        None => {
+            // a new job, or one without saved state, starts counting from now
+            last_runs.insert(job.name.clone(), now);
+            return true;
+        }
+    };
+
+    let mut due = vec![];
+    let mut next = schedule.next_after(last_run);
+    while let Some(at) = next {
+        if at > now {
+            break;
+        }
+        due.push(at);
+        next = schedule.next_after(at);
+    }
+
+    let latest = match due.last() {
+        Some(latest) => *latest,
+        None => return false,
+    };
+    last_runs.insert(job.name.clone(), latest);
+
+    if now - latest <= LATE_AFTER_IN_SECONDS {
+        if due.len() > 1 {
+            record_trigger(config,
+                           job,
+                           "".to_string(),
+                           due[due.len() - 2],
+                           now,
+                           "missed",
+                           format!("{} runs missed", due.len() - 1));
+        }
+        start_run(state_manager,
+                  config,
+                  job,
+                  is_system_service,
+                  latest,
+                  now,
+                  "".to_string(),
+                  active_runs);
+        return true;
+    }
+
+    let too_late = schedule.starting_deadline_in_seconds > 0 && now - latest > schedule.starting_deadline_in_seconds;
+    if schedule.missed_runs == MissedRuns::Skip || too_late {
+        log_warn!("scheduled runs missed"; "job" => job.name, "count" => due.len());
+        record_trigger(config,
+                       job,
+                       "".to_string(),
+                       latest,
+                       now,
+                       "missed",
+                       format!("{} runs missed", due.len()));
+    } else {
+        log_info!("catching up on missed runs"; "job" => job.name, "count" => due.len());
+        start_run(state_manager,
+                  config,
+                  job,
+                  is_system_service,
+                  latest,
+                  now,
+                  format!("catching up on {} missed runs", due.len()),
+                  active_runs);
+    }
+
+    true
+}
+
+fn start_run(state_manager: &StateManager,
+             config: &Config,
+             job: &Task,
+             is_system_service: bool,
+             scheduled_for: i64,
+             now: i64,
+             detail: String,
+             active_runs: &mut BTreeMap<String, Vec<String>>) {
+    let schedule = job.schedule.as_ref().unwrap();
+
+    let mut active = vec![];
+    for run in active_runs.remove(&job.name).unwrap_or(vec![]) {
+        match state_manager.request_task_state(run.clone()) {
+            TaskState::Requested | TaskState::Accepted | TaskState::Running => active.push(run),
+            _ => {}
+        }
+    }
+
+    let mut detail = detail;
+    if !active.is_empty() {
+        match schedule.concurrency_policy {
+            ConcurrencyPolicy::Allow => {}
+            ConcurrencyPolicy::Forbid => {
+                log_info!("skipping scheduled run, previous run still active";
+                          "job" => job.name,
+                          "active" => active.join(","));
+                record_trigger(config,
+                               job,
+                               "".to_string(),
+                               scheduled_for,
+                               now,
+                               "skipped",
+                               format!("{} still active", active.join(", ")));
+                active_runs.insert(job.name.clone(), active);
+                return;
+            }
+            ConcurrencyPolicy::Replace => {
+                for run in &active {
+                    log_info!("replacing scheduled run"; "job" => job.name, "run" => run);
+                    // requested runs were never launched, there's nothing to kill
+                    match state_manager.request_task_state(run.clone()) {
+                        TaskState::Requested => state_manager.send_remove_task_by_name(run.clone()),
+                        _ => state_manager.send_kill_task_by_name(run.clone()),
+                    }
+                }
+                let replaced = format!("replaced {}", active.join(", "));
+                detail = if detail.is_empty() { replaced } else { format!("{}, {}", detail, replaced) };
+                active.clear();
+            }
+        }
+    }
+
+    let run = run_name(&job.name, scheduled_for);
+    log_info!("starting scheduled run"; "job" => job.name, "run" => run);
+    state_manager.send_start_task(&run,
+                                  &0,
+                                  &job.image,
+                                  &job.node_name,
+                                  &job.node_type,
+                                  &job.node_function,
+                                  &job.dependent_service,
+                                  &job.dependencies,
+                                  &job.health_check,
+                                  &job.arguments,
+                                  &job.parameters,
+                                  &job.memory,
+                                  &job.cpu,
+                                  &job.volumes,
+                                  &job.privileged,
+                                  &job.sla,
+                                  &job.is_metered,
+                                  &is_system_service,
+                                  &job.is_job,
+                                  &job.max_retries,
+                                  &job.network_type,
+                                  &job.constraints);
+
+    active.push(run.clone());
+    active_runs.insert(job.name.clone(), active);
+    record_trigger(config, job, run, scheduled_for, now, "started", detail);
+}
+
+// unique per run since cron has a one minute resolution, e.g. backup-201610180300
+fn run_name(job_name: &str, scheduled_for: i64) -> String {
+    format!("{}-{}",
+            job_name,
+            NaiveDateTime::from_timestamp(scheduled_for, 0).format("%Y%m%d%H%M"))
+}
+
+fn record_trigger(config: &Config,
+                  job: &Task,
+                  run: String,
+                  scheduled_for: i64,
+                  now: i64,
+                  action: &str,
+                  detail: String) {
+    metrics::record_scheduled_run(action);
+
+    let mut triggers = TRIGGERS.lock().unwrap();
+    triggers.push_back(Trigger {
+        job: job.name.clone(),
+        run: run,
+        scheduled_for: scheduled_for,
+        triggered_at: now,
+        action: action.to_string(),
+        detail: detail,
+    });
+    while triggers.len() > config.jobs.history_size as usize {
+        triggers.pop_front();
+    }
+}
+
+// the time of the latest run of every job, by job name
+fn load_last_runs(path: &str) -> BTreeMap<String, i64> {
+    if path.is_empty() {
+        log_info!("job schedule state kept in memory, runs missed while down won't be caught up");
+        return BTreeMap::new();
+    }
+
+    let mut content = String::new();
+    if let Err(error) = File::open(path).and_then(|mut file| file.read_to_string(&mut content)) {
+        log_info!("no job schedule state loaded"; "path" => path, "error" => error);
+        return BTreeMap::new();
+    }
+
+    match json::decode(&content) {
+        Ok(last_runs) => last_runs,
+        Err(error) => {
+            log_error!("can't parse job schedule state"; "path" => path, "error" => error);
+            BTreeMap::new()
+        }
+    }
+}
+
+// written to a temporary file first, so a crash can't leave half a file behind
+fn save_last_runs(path: &str, last_runs: &BTreeMap<String, i64>) -> Result<(), String> {
+    let content = try!(json::encode(last_runs).map_err(|error| error.to_string()));
+    let temp_path = format!("{}.tmp", path);
+
+    try!(File::create(&temp_path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|error| format!("{}: {}", temp_path, error)));
+    fs::rename(&temp_path, path).map_err(|error| format!("{}: {}", path, error))
+}
@@ -20,29 +20,112 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use std::collections::HashSet;
 use std::time::Duration;
 use std::thread;
-use state::{SLA, StateManager, TaskState};
-use utils::read_task;
+use config::{diff_config, same_task};
+use state::{Node, SLA, StateManager, TaskState};
+use utils::Task;
+use metrics;
 
 pub fn run_health_checker(state_manager: &StateManager) {
-    println!("health check starting");
+    log_info!("health check starting");
     state_manager.send_ping();
 
     let is_system_service = true;
 
-    let config = state_manager.get_yaml();
-    let wait_time = config["healthcheck"]["poll_interval_in_seconds"].as_i64().unwrap() as u64;
+    let mut config = state_manager.get_config();
+    let mut nodes = state_manager.request_list_nodes();
+    let mut tasks = expand_system_services(&config.healthcheck.system_services, &nodes);
+    // system jobs run once, they are gone from the task list after jobs.retention_in_seconds
+    // and must not be started again then
+    let mut started_jobs: HashSet<String> = HashSet::new();
 
+    loop {
+        thread::sleep(Duration::from_secs(config.healthcheck.poll_interval_in_seconds));
+
+        let new_config = state_manager.get_config();
+        let new_nodes = state_manager.request_list_nodes();
+        let nodes_changed = node_layout(&nodes) != node_layout(&new_nodes);
+        if nodes_changed {
+            log_info!("node list changed, expanding system services"; "nodes" => new_nodes.len());
+        }
+        if nodes_changed || !diff_config(&config, &new_config).is_empty() {
+            let new_tasks = expand_system_services(&new_config.healthcheck.system_services, &new_nodes);
+            retire_system_services(state_manager, &tasks, &new_tasks);
+            // changed jobs run again with their new definition
+            started_jobs = started_jobs.into_iter()
+                .filter(|name| unchanged(name, &tasks, &new_tasks))
+                .collect();
+            tasks = new_tasks;
+        }
+        config = new_config;
+        nodes = new_nodes;
+
+        log_debug!("checking health"; "tasks" => tasks.len());
+
+        for task in &tasks {
+            match state_manager.request_task_state(task.name.to_string()) {
+                // jobs that completed aren't run again
+                TaskState::Running | TaskState::Requested | TaskState::Accepted | TaskState::Finished |
+                TaskState::Failed => {}
+                TaskState::NotRunning if task.is_job && started_jobs.contains(&task.name) => {}
+                TaskState::NotRunning => {
+                    if task.is_job {
+                        started_jobs.insert(task.name.clone());
+                    }
+                    log_info!("restarting system service"; "task" => task.name);
+                    metrics::record_health_check_restart();
+                    state_manager.send_start_task(&task.name,
+                                                  &0,
+                                                  &task.image,
+                                                  &task.node_name,
+                                                  &task.node_type,
+                                                  &task.node_function,
+                                                  &task.dependent_service,
+                                                  &task.dependencies,
+                                                  &task.health_check,
+                                                  &task.arguments,
+                                                  &task.parameters,
+                                                  &task.memory,
+                                                  &task.cpu,
+                                                  &task.volumes,
+                                                  &task.privileged,
+                                                  &task.sla,
+                                                  &task.is_metered,
+                                                  &is_system_service,
+                                                  &task.is_job,
+                                                  &task.max_retries,
+                                                  &task.network_type,
+                                                  &task.constraints)
+                }
+            };
+        }
+    }
+}
+
+/// What the expansion of singleton services depends on, the name and type
+/// of every node.
+fn node_layout(nodes: &Vec<Node>) -> Vec<(String, String)> {
+    let mut layout: Vec<(String, String)> = nodes.iter()
+        .map(|node| (node.name.clone(), node.node_type.clone()))
+        .collect();
+    layout.sort();
+    layout
+}
+
+fn expand_system_services(system_services: &Vec<Task>, nodes: &Vec<Node>) -> Vec<Task> {
     let mut tasks = Vec::new();
 
-    let system_services = config["healthcheck"]["system_services"].as_vec().unwrap();
-    for system_service in system_services {
-        let task = read_task(system_service);
+    for task in system_services {
+        // scheduled jobs are started by the cron thread
+        if task.schedule.is_some() {
+            continue;
+        }
+
         match task.sla {
-            SLA::None => tasks.push(task),
+            SLA::None => tasks.push(task.clone()),
             SLA::SingletonEachNode => {
-                let nodes = state_manager.request_list_nodes();
                 for node in nodes {
                     let mut new_task = task.clone();
                     new_task.node_name = node.name.clone();
@@ -51,7 +134,6 @@ pub fn run_health_checker(state_manager: &StateManager) {
                 }
             }
             SLA::SingletonEachSlave => {
-                let nodes = state_manager.request_list_nodes();
                 for node in nodes {
                     if node.node_type != "slave" {
                         continue;
@@ -65,33 +147,45 @@ pub fn run_health_checker(state_manager: &StateManager) {
         }
     }
 
-    loop {
-        thread::sleep(Duration::from_secs(wait_time));
-        println!("checking health");
+    tasks
+}
 
-        for task in &tasks {
-            match state_manager.request_task_state(task.name.to_string()) {
-                TaskState::Running | TaskState::Requested | TaskState::Accepted => {}
-                TaskState::NotRunning => {
-                    state_manager.send_start_task(&task.name,
-                                                  &task.image,
-                                                  &task.node_name,
-                                                  &task.node_type,
-                                                  &task.node_function,
-                                                  &task.dependent_service,
-                                                  &task.arguments,
-                                                  &task.parameters,
-                                                  &task.memory,
-                                                  &task.cpu,
-                                                  &task.volumes,
-                                                  &task.privileged,
-                                                  &task.sla,
-                                                  &task.is_metered,
-                                                  &is_system_service,
-                                                  &task.is_job,
-                                                  &task.network_type)
+/// Stops the system services a config reload removed or changed and the
+/// per-node ones of nodes that left, changed ones are started again with
+/// their new definition on the next check.
+fn retire_system_services(state_manager: &StateManager, old_tasks: &Vec<Task>, new_tasks: &Vec<Task>) {
+    for old_task in old_tasks {
+        match new_tasks.iter().find(|new_task| new_task.name == old_task.name) {
+            Some(new_task) => {
+                if !same_task(old_task, new_task) {
+                    log_info!("stopping changed system service"; "task" => old_task.name);
+                    stop_system_service(state_manager, &old_task.name);
                 }
-            };
+            }
+            None => {
+                log_info!("stopping removed system service"; "task" => old_task.name, "node" => old_task.node_name);
+                stop_system_service(state_manager, &old_task.name);
+            }
         }
     }
 }
+
+fn unchanged(task_name: &String, old_tasks: &Vec<Task>, new_tasks: &Vec<Task>) -> bool {
+    let old_task = old_tasks.iter().find(|task| task.name == *task_name);
+    let new_task = new_tasks.iter().find(|task| task.name == *task_name);
+
+    match (old_task, new_task) {
+        (Some(old_task), Some(new_task)) => same_task(old_task, new_task),
+        _ => false,
+    }
+}
+
+// a task still waiting for an offer has nothing to kill, and may wait for a
+// node that is gone
+fn stop_system_service(state_manager: &StateManager, task_name: &String) {
+    match state_manager.request_task_state(task_name.clone()) {
+        TaskState::Requested => state_manager.send_remove_task_by_name(task_name.clone()),
+        TaskState::NotRunning => {}
+        _ => state_manager.send_kill_task_by_name(task_name.clone()),
+    }
+}
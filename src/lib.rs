@@ -30,13 +30,25 @@ extern crate yaml_rust;
 extern crate rustc_serialize;
 extern crate uuid;
 extern crate chrono;
+extern crate crypto;
+extern crate libc;
+extern crate openssl;
+extern crate glob;
 
 #[macro_use]
 extern crate lazy_static;
 
+#[macro_use]
+pub mod logging;
 pub mod state;
 pub mod scheduler;
 pub mod api;
 pub mod utils;
 pub mod health;
 pub mod collaborator;
+pub mod metrics;
+pub mod audit;
+pub mod config;
+pub mod cron;
+pub mod workflow;
+pub mod probe;
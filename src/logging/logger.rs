@@ -0,0 +1,203 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::RwLock;
+use chrono::UTC;
+use rustc_serialize::json::{self, Json};
+use config::LoggingConfig;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+struct LogConfig {
+    default_level: Level,
+    // (module prefix, level), the longest matching prefix wins
+    modules: Vec<(String, Level)>,
+    format: Format,
+    overridden: bool,
+}
+
+lazy_static! {
+    static ref LOGGER: RwLock<LogConfig> = {
+        RwLock::new(LogConfig {
+            default_level: Level::Info,
+            modules: vec![],
+            format: Format::Human,
+            overridden: false,
+        })
+    };
+}
+
+impl Level {
+    pub fn from_str(level: &str) -> Option<Level> {
+        match &*level.trim().to_lowercase() {
+            "off" => Some(Level::Off),
+            "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl Format {
+    pub fn from_str(format: &str) -> Option<Format> {
+        match &*format.trim().to_lowercase() {
+            "human" | "text" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::Human => "human",
+            Format::Json => "json",
+        }
+    }
+}
+
+/// Configures logging from a filter spec like `info,torc_sub_scheduler::scheduler=debug`
+/// and an optional output format. Settings made here take precedence over the
+/// `logging` section of the config file, so command line flags win.
+pub fn configure(spec: &str, format: Option<Format>) -> Result<(), String> {
+    let (default_level, modules) = try!(parse_spec(spec));
+
+    let mut config = LOGGER.write().unwrap();
+    config.default_level = default_level;
+    config.modules = modules;
+    if let Some(format) = format {
+        config.format = format;
+    }
+    config.overridden = true;
+
+    Ok(())
+}
+
+/// Applies the `logging` section of the config file, unless `configure` was called before.
+pub fn configure_from_config(logging: &LoggingConfig) {
+    let mut config = LOGGER.write().unwrap();
+    if config.overridden {
+        return;
+    }
+    config.default_level = logging.level;
+    config.modules = logging.modules.clone();
+    config.format = logging.format;
+}
+
+pub fn enabled(level: Level, module: &str) -> bool {
+    let config = LOGGER.read().unwrap();
+
+    let mut result = config.default_level;
+    let mut matched_len = 0;
+    for &(ref prefix, module_level) in &config.modules {
+        if module.starts_with(&**prefix) && prefix.len() >= matched_len {
+            result = module_level;
+            matched_len = prefix.len();
+        }
+    }
+
+    level != Level::Off && level <= result
+}
+
+pub fn log(level: Level, module: &str, message: &str, fields: &[(&str, String)]) {
+    let format = LOGGER.read().unwrap().format;
+    let timestamp = UTC::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+
+    let line = match format {
+        Format::Human => {
+            let mut line = format!("{} {:<5} {} {}", timestamp, level.name().to_uppercase(), module, message);
+            for &(key, ref value) in fields {
+                if value.contains(' ') || value.is_empty() {
+                    line.push_str(&format!(" {}={:?}", key, value));
+                } else {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+            }
+            line
+        }
+        Format::Json => {
+            let mut object = BTreeMap::new();
+            object.insert("timestamp".to_string(), Json::String(timestamp));
+            object.insert("level".to_string(), Json::String(level.name().to_string()));
+            object.insert("module".to_string(), Json::String(module.to_string()));
+            object.insert("message".to_string(), Json::String(message.to_string()));
+            for &(key, ref value) in fields {
+                object.insert(key.to_string(), Json::String(value.clone()));
+            }
+            json::encode(&Json::Object(object)).unwrap()
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    let _ = writeln!(handle, "{}", line);
+}
+
+fn parse_spec(spec: &str) -> Result<(Level, Vec<(String, Level)>), String> {
+    let mut default_level = Level::Info;
+    let mut modules = vec![];
+
+    for directive in spec.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+        let mut parts = directive.splitn(2, '=');
+        let first = parts.next().unwrap();
+        match parts.next() {
+            Some(level) => {
+                let level = try!(Level::from_str(level).ok_or(format!("unknown log level {} for {}", level, first)));
+                modules.push((first.to_string(), level));
+            }
+            None => {
+                default_level = try!(Level::from_str(first).ok_or(format!("unknown log level {}", first)));
+            }
+        }
+    }
+
+    Ok((default_level, modules))
+}
@@ -0,0 +1,263 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use state::TaskState;
+
+const LAUNCH_LATENCY_BUCKETS: &'static [f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+const REQUEST_LATENCY_BUCKETS: &'static [f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+static STATE_QUEUE_DEPTH: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = {
+        Mutex::new(Metrics::new())
+    };
+}
+
+struct Metrics {
+    offers_received: u64,
+    offers_accepted: u64,
+    offers_declined: BTreeMap<String, u64>,
+    health_check_restarts: u64,
+    announce_failures: u64,
+    job_runs: BTreeMap<String, u64>,
+    scheduled_runs: BTreeMap<String, u64>,
+    requested_at: HashMap<String, Instant>,
+    launch_latency: Histogram,
+    state_request_latency: BTreeMap<String, Histogram>,
+}
+
+#[derive(Clone)]
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Histogram {
+        Histogram {
+            buckets: buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (idx, bound) in self.buckets.iter().enumerate() {
+            if value <= *bound {
+                self.counts[idx] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        for (idx, bound) in self.buckets.iter().enumerate() {
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, self.counts[idx]);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        Metrics {
+            offers_received: 0,
+            offers_accepted: 0,
+            offers_declined: BTreeMap::new(),
+            health_check_restarts: 0,
+            announce_failures: 0,
+            job_runs: BTreeMap::new(),
+            scheduled_runs: BTreeMap::new(),
+            requested_at: HashMap::new(),
+            launch_latency: Histogram::new(LAUNCH_LATENCY_BUCKETS),
+            state_request_latency: BTreeMap::new(),
+        }
+    }
+
+    // snapshot without the per-task bookkeeping, so rendering doesn't hold the lock
+    fn clone_for_render(&self) -> Metrics {
+        Metrics {
+            offers_received: self.offers_received,
+            offers_accepted: self.offers_accepted,
+            offers_declined: self.offers_declined.clone(),
+            health_check_restarts: self.health_check_restarts,
+            announce_failures: self.announce_failures,
+            job_runs: self.job_runs.clone(),
+            scheduled_runs: self.scheduled_runs.clone(),
+            requested_at: HashMap::new(),
+            launch_latency: self.launch_latency.clone(),
+            state_request_latency: self.state_request_latency.clone(),
+        }
+    }
+}
+
+pub fn record_offers_received(count: usize) {
+    METRICS.lock().unwrap().offers_received += count as u64;
+}
+
+pub fn record_offer_accepted() {
+    METRICS.lock().unwrap().offers_accepted += 1;
+}
+
+pub fn record_offer_declined(reason: &str) {
+    *METRICS.lock().unwrap().offers_declined.entry(reason.to_string()).or_insert(0) += 1;
+}
+
+pub fn record_health_check_restart() {
+    METRICS.lock().unwrap().health_check_restarts += 1;
+}
+
+pub fn record_announce_failure() {
+    METRICS.lock().unwrap().announce_failures += 1;
+}
+
+pub fn record_job_run(result: &str) {
+    *METRICS.lock().unwrap().job_runs.entry(result.to_string()).or_insert(0) += 1;
+}
+
+pub fn record_scheduled_run(action: &str) {
+    *METRICS.lock().unwrap().scheduled_runs.entry(action.to_string()).or_insert(0) += 1;
+}
+
+pub fn record_task_requested(task_name: &String) {
+    METRICS.lock().unwrap().requested_at.insert(task_name.clone(), Instant::now());
+}
+
+pub fn record_task_running(task_name: &String) {
+    let mut metrics = METRICS.lock().unwrap();
+    if let Some(requested_at) = metrics.requested_at.remove(task_name) {
+        metrics.launch_latency.observe(as_seconds(requested_at.elapsed()));
+    }
+}
+
+pub fn record_task_forgotten(task_name: &String) {
+    METRICS.lock().unwrap().requested_at.remove(task_name);
+}
+
+pub fn record_state_request_queued() {
+    STATE_QUEUE_DEPTH.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn record_state_request_dequeued() {
+    STATE_QUEUE_DEPTH.fetch_sub(1, Ordering::SeqCst);
+}
+
+pub fn record_state_request_done(request: &str, queued_at: Instant) {
+    let latency = as_seconds(queued_at.elapsed());
+    METRICS.lock()
+        .unwrap()
+        .state_request_latency
+        .entry(request.to_string())
+        .or_insert(Histogram::new(REQUEST_LATENCY_BUCKETS))
+        .observe(latency);
+}
+
+/// Renders all metrics in the Prometheus text exposition format. Task counts are
+/// passed in by the caller as they live in the state actor.
+pub fn render(tasks_per_state: &Vec<(TaskState, usize)>) -> String {
+    let metrics = METRICS.lock().unwrap().clone_for_render();
+    let mut out = String::new();
+
+    write_header(&mut out, "torc_offers_received_total", "counter", "Offers received from Mesos.");
+    let _ = writeln!(out, "torc_offers_received_total {}", metrics.offers_received);
+
+    write_header(&mut out, "torc_offers_accepted_total", "counter", "Offers accepted to launch a task.");
+    let _ = writeln!(out, "torc_offers_accepted_total {}", metrics.offers_accepted);
+
+    write_header(&mut out, "torc_offers_declined_total", "counter", "Offers declined, by reason.");
+    for (reason, count) in &metrics.offers_declined {
+        let _ = writeln!(out, "torc_offers_declined_total{{reason=\"{}\"}} {}", reason, count);
+    }
+
+    write_header(&mut out, "torc_tasks", "gauge", "Tasks known to the scheduler, by state.");
+    for &(ref task_state, count) in tasks_per_state {
+        let _ = writeln!(out, "torc_tasks{{state=\"{:?}\"}} {}", task_state, count);
+    }
+
+    write_header(&mut out,
+                 "torc_launch_latency_seconds",
+                 "histogram",
+                 "Time from a task being requested to it running.");
+    metrics.launch_latency.render(&mut out, "torc_launch_latency_seconds", "");
+
+    write_header(&mut out, "torc_state_queue_depth", "gauge", "Requests waiting for the state actor.");
+    let _ = writeln!(out, "torc_state_queue_depth {}", STATE_QUEUE_DEPTH.load(Ordering::SeqCst));
+
+    write_header(&mut out,
+                 "torc_state_request_duration_seconds",
+                 "histogram",
+                 "Time from queueing a state request to it being handled.");
+    for (request, histogram) in &metrics.state_request_latency {
+        histogram.render(&mut out,
+                         "torc_state_request_duration_seconds",
+                         &format!("request=\"{}\"", request));
+    }
+
+    write_header(&mut out,
+                 "torc_health_check_restarts_total",
+                 "counter",
+                 "System services restarted by the health checker.");
+    let _ = writeln!(out, "torc_health_check_restarts_total {}", metrics.health_check_restarts);
+
+    write_header(&mut out, "torc_job_runs_total", "counter", "Completed job runs, by result.");
+    for (result, count) in &metrics.job_runs {
+        let _ = writeln!(out, "torc_job_runs_total{{result=\"{}\"}} {}", result, count);
+    }
+
+    write_header(&mut out,
+                 "torc_scheduled_runs_total",
+                 "counter",
+                 "Due runs of scheduled jobs, by what was done about them.");
+    for (action, count) in &metrics.scheduled_runs {
+        let _ = writeln!(out, "torc_scheduled_runs_total{{action=\"{}\"}} {}", action, count);
+    }
+
+    write_header(&mut out,
+                 "torc_controller_announce_failures_total",
+                 "counter",
+                 "Failed announcements of running tasks to the controller.");
+    let _ = writeln!(out, "torc_controller_announce_failures_total {}", metrics.announce_failures);
+
+    out
+}
+
+fn write_header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+fn as_seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
@@ -0,0 +1,162 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::collections::{HashMap, HashSet};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::UTC;
+use hyper::Client;
+use hyper::header::Connection;
+use config::NodeProbeConfig;
+use state::{Node, StateManager};
+
+// how often the config is looked at again while probing is turned off
+const IDLE_INTERVAL_IN_SECONDS: u64 = 60;
+
+lazy_static! {
+    // nodes whose probe thread hasn't returned yet, neither the connect nor
+    // the hyper client have a connect timeout so a probe can outlive a round
+    static ref IN_FLIGHT: Arc<Mutex<HashSet<String>>> = {
+        Arc::new(Mutex::new(HashSet::new()))
+    };
+}
+
+// takes the node out of `IN_FLIGHT` when its probe thread ends
+struct InFlight {
+    node_name: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap().remove(&self.node_name);
+    }
+}
+
+pub fn run_node_prober(state_manager: &StateManager) {
+    log_info!("node prober starting");
+
+    loop {
+        let probe = state_manager.get_config().node_probe;
+        if !probe.is_enabled() {
+            thread::sleep(Duration::from_secs(IDLE_INTERVAL_IN_SECONDS));
+            continue;
+        }
+
+        probe_nodes(state_manager, &probe);
+        thread::sleep(Duration::from_secs(probe.poll_interval_in_seconds));
+    }
+}
+
+/// Probes every node at once, a node that doesn't answer within the timeout
+/// counts as unreachable even if its probe is still waiting to connect. Such a
+/// node isn't probed again until that probe returns, so an unreachable node
+/// never has more than one probe thread.
+fn probe_nodes(state_manager: &StateManager, probe: &NodeProbeConfig) {
+    let nodes = state_manager.request_list_nodes();
+    let timeout = Duration::from_secs(probe.timeout_in_seconds);
+    let (sender, receiver) = channel();
+
+    let mut skipped = HashSet::new();
+    for node in &nodes {
+        if !IN_FLIGHT.lock().unwrap().insert(node.name.clone()) {
+            skipped.insert(node.name.clone());
+            continue;
+        }
+        let in_flight = InFlight { node_name: node.name.clone() };
+        let sender = sender.clone();
+        let node_name = node.name.clone();
+        let address = probe_address(node);
+        let probe = probe.clone();
+        thread::Builder::new()
+            .name(format!("probe-{}", node.name))
+            .spawn(move || {
+                let _in_flight = in_flight;
+                let result = probe_node(&address, &probe);
+                let _ = sender.send((node_name, result));
+            })
+            .unwrap();
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut results: HashMap<String, Result<(), String>> = HashMap::new();
+    while results.len() + skipped.len() < nodes.len() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        match receiver.recv_timeout(deadline - now) {
+            Ok((node_name, result)) => {
+                results.insert(node_name, result);
+            }
+            Err(_) => break,
+        }
+    }
+
+    let probed_at = UTC::now().timestamp();
+    for node in &nodes {
+        let result = match results.remove(&node.name) {
+            Some(result) => result,
+            None if skipped.contains(&node.name) => Err("previous probe still waiting for an answer".to_string()),
+            None => Err(format!("no answer within {} seconds", probe.timeout_in_seconds)),
+        };
+        match result {
+            Ok(_) => {
+                if !node.reachable {
+                    log_info!("node reachable"; "node" => node.name, "address" => probe_address(node));
+                }
+            }
+            Err(ref error_msg) => {
+                if node.reachable || node.last_probed_at.is_none() {
+                    log_warn!("node unreachable"; "node" => node.name, "address" => probe_address(node), "error" => error_msg);
+                }
+            }
+        }
+        state_manager.send_update_node_reachability(node.name.clone(), result.is_ok(), probed_at);
+    }
+}
+
+fn probe_address(node: &Node) -> String {
+    match node.management_ip.is_empty() {
+        true => node.ip.clone(),
+        false => node.management_ip.clone(),
+    }
+}
+
+fn probe_node(address: &String, probe: &NodeProbeConfig) -> Result<(), String> {
+    if probe.path.is_empty() {
+        return match TcpStream::connect((&**address, probe.port)) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(error.to_string()),
+        };
+    }
+
+    let mut client = Client::new();
+    client.set_read_timeout(Some(Duration::from_secs(probe.timeout_in_seconds)));
+    let url = format!("http://{}:{}{}", address, probe.port, probe.path);
+    match client.get(&url).header(Connection::close()).send() {
+        Ok(ref response) if response.status.is_success() => Ok(()),
+        Ok(response) => Err(format!("{} answered {}", url, response.status)),
+        Err(error) => Err(error.to_string()),
+    }
+}
@@ -22,6 +22,8 @@
 
 pub use self::scheduler_impl::TorcScheduler;
 pub use self::run_scheduler::run_scheduler;
+pub use self::placement::{NodeSimulation, Placement, Rejection, TaskSimulation, check_placement, simulate_placement};
 
 mod scheduler_impl;
 mod run_scheduler;
+mod placement;
@@ -0,0 +1,303 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::BTreeMap;
+use chrono::{NaiveDateTime, UTC};
+use mesos::proto::{Offer, Value_Type};
+use state::{DependencyCondition, Node, NodeOffer, StateManager, Task, TaskState};
+use utils::Constraint;
+
+/// Arguments and parameters of a task that fits an offer, with all references resolved.
+pub struct Placement {
+    pub arguments: String,
+    pub parameters: String,
+}
+
+/// The check that keeps a task off a node, in the order they are made.
+#[derive(Clone, Debug)]
+pub enum Rejection {
+    /// wanted node, offering node
+    NodeName(String, String),
+    /// wanted type, offered type
+    NodeType(String, String),
+    /// wanted function, offered function
+    NodeFunction(String, String),
+    Constraint(String),
+    /// service, condition it has to meet, what it is instead
+    Dependency(String, DependencyCondition, String),
+    /// wanted cpus, offered cpus
+    Cpu(f64, f64),
+    /// wanted memory, offered memory
+    Mem(f64, f64),
+    UnresolvedReference(Vec<String>),
+}
+
+impl Rejection {
+    pub fn label(&self) -> &'static str {
+        match *self {
+            Rejection::NodeName(..) => "node_name",
+            Rejection::NodeType(..) => "node_type",
+            Rejection::NodeFunction(..) => "node_function",
+            Rejection::Constraint(..) => "constraint",
+            Rejection::Dependency(..) => "dependency",
+            Rejection::Cpu(..) => "cpu",
+            Rejection::Mem(..) => "mem",
+            Rejection::UnresolvedReference(..) => "unresolved_reference",
+        }
+    }
+
+    /// How far a task got through the checks, the furthest one is reported
+    /// as the reason an offer got declined.
+    pub fn rank(&self) -> u8 {
+        match *self {
+            Rejection::NodeName(..) => 1,
+            Rejection::NodeType(..) => 2,
+            Rejection::NodeFunction(..) => 3,
+            Rejection::Constraint(..) => 4,
+            Rejection::Dependency(..) => 5,
+            Rejection::Cpu(..) => 6,
+            Rejection::Mem(..) => 7,
+            Rejection::UnresolvedReference(..) => 8,
+        }
+    }
+
+    pub fn detail(&self) -> String {
+        match *self {
+            Rejection::NodeName(ref wanted, ref offered) => format!("needs node {}, offer is from {}", wanted, offered),
+            Rejection::NodeType(ref wanted, ref offered) => {
+                format!("needs node type {}, node has type {}", wanted, offered)
+            }
+            Rejection::NodeFunction(ref wanted, ref offered) => {
+                format!("needs node function {}, node has function {}", wanted, offered)
+            }
+            Rejection::Constraint(ref constraint) => format!("constraint {} not met", constraint),
+            Rejection::Dependency(ref service, ref condition, ref current) => {
+                format!("depends on {} being {}, it is {}", service, condition.name(), current)
+            }
+            Rejection::Cpu(wanted, offered) => format!("needs {} cpus, offer has {}", wanted, offered),
+            Rejection::Mem(wanted, offered) => format!("needs {} MB memory, offer has {}", wanted, offered),
+            Rejection::UnresolvedReference(ref unresolved) => {
+                format!("waits for references {}", unresolved.join(", "))
+            }
+        }
+    }
+}
+
+/// Outcome of the placement checks for one task on one node.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct NodeSimulation {
+    pub node: String,
+    pub offer_received_at: Option<i64>,
+    pub fits: bool,
+    pub rejected_by: String,
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct TaskSimulation {
+    pub task: String,
+    pub fits_on: Vec<String>,
+    pub nodes: Vec<NodeSimulation>,
+}
+
+/// The attributes and resources of a mesos offer, the node is the one named
+/// by the `machine-name` attribute.
+pub fn offer_snapshot(offer: &Offer) -> NodeOffer {
+    let mut attributes = BTreeMap::new();
+    for attribute in offer.get_attributes() {
+        let value = match attribute.get_field_type() {
+            Value_Type::TEXT => attribute.get_text().get_value().to_string(),
+            Value_Type::SCALAR => attribute.get_scalar().get_value().to_string(),
+            _ => continue,
+        };
+        attributes.insert(attribute.get_name().to_string(), value);
+    }
+
+    let mut cpus: f64 = 0.0;
+    let mut mem: f64 = 0.0;
+    for resource in offer.get_resources() {
+        match resource.get_name() {
+            "mem" => mem = resource.get_scalar().get_value(),
+            "cpus" => cpus = resource.get_scalar().get_value(),
+            _ => {}
+        }
+    }
+
+    let attribute = |name: &str| attributes.get(name).cloned().unwrap_or("".to_string());
+
+    // agents started without a machine-name attribute go by their hostname
+    let hostname = offer.get_hostname().to_string();
+    let mut node_name = attribute("machine-name");
+    if node_name.is_empty() {
+        node_name = hostname.clone();
+    }
+
+    NodeOffer {
+        offer_id: offer.get_id().get_value().to_string(),
+        slave_id: offer.get_slave_id().get_value().to_string(),
+        hostname: hostname,
+        node_name: node_name,
+        node_type: attribute("machine-type"),
+        node_function: attribute("machine-function"),
+        attributes: attributes.clone(),
+        cpus: cpus,
+        mem: mem,
+        received_at: UTC::now().timestamp(),
+    }
+}
+
+/// Runs the checks the scheduler makes before it launches `task` on `offer`,
+/// `node` is the node the offer came from and is used to resolve references.
+pub fn check_placement(state_manager: &StateManager,
+                       task: &Task,
+                       offer: &NodeOffer,
+                       node: Option<&Node>)
+                       -> Result<Placement, Rejection> {
+    if task.node_name.len() > 0 && task.node_name != offer.node_name {
+        return Err(Rejection::NodeName(task.node_name.clone(), offer.node_name.clone()));
+    }
+
+    if task.node_type.len() > 0 && task.node_type != offer.node_type {
+        return Err(Rejection::NodeType(task.node_type.clone(), offer.node_type.clone()));
+    }
+
+    if task.node_function.len() > 0 && task.node_function != offer.node_function {
+        return Err(Rejection::NodeFunction(task.node_function.clone(), offer.node_function.clone()));
+    }
+
+    for constraint in &task.constraints {
+        // checked when the config got loaded
+        if let Ok(parsed) = Constraint::parse(constraint) {
+            if !parsed.is_met(&offer.attributes) {
+                return Err(Rejection::Constraint(constraint.clone()));
+            }
+        }
+    }
+
+    for dependency in &task.dependencies {
+        let current = match state_manager.request_task(dependency.service.clone()) {
+            Some(ref service) if dependency.condition.is_met_by(service) => continue,
+            Some(service) => describe_task(&service),
+            None => "not started".to_string(),
+        };
+        return Err(Rejection::Dependency(dependency.service.clone(), dependency.condition, current));
+    }
+
+    if offer.cpus < task.cpu {
+        return Err(Rejection::Cpu(task.cpu, offer.cpus));
+    }
+
+    if offer.mem < task.memory {
+        return Err(Rejection::Mem(task.memory, offer.mem));
+    }
+
+    // references are resolved at launch time, a task waits until all of them are available
+    let arguments = state_manager.resolve_template(&task.arguments, task, node);
+    let parameters = state_manager.resolve_template(&task.parameters, task, node);
+    match (arguments, parameters) {
+        (Ok(arguments), Ok(parameters)) => {
+            Ok(Placement {
+                arguments: arguments,
+                parameters: parameters,
+            })
+        }
+        (arguments, parameters) => {
+            let mut unresolved = arguments.err().unwrap_or(vec![]);
+            unresolved.extend(parameters.err().unwrap_or(vec![]));
+            Err(Rejection::UnresolvedReference(unresolved))
+        }
+    }
+}
+
+fn describe_task(task: &Task) -> String {
+    match (&task.state, task.healthy) {
+        (&TaskState::Running, Some(true)) => "Running and healthy".to_string(),
+        (&TaskState::Running, Some(false)) => "Running but unhealthy".to_string(),
+        (&TaskState::Running, None) if task.health_check.is_some() => "Running, not health checked yet".to_string(),
+        (state, _) => format!("{:?}", state),
+    }
+}
+
+/// Why nothing is placed on a node that didn't answer its probes.
+pub fn unreachable_detail(node: Option<&Node>) -> String {
+    match node.and_then(|node| node.last_seen_at) {
+        Some(last_seen_at) => {
+            format!("node doesn't answer probes, last seen {}",
+                    NaiveDateTime::from_timestamp(last_seen_at, 0).format("%Y-%m-%d %H:%M:%S UTC"))
+        }
+        None => "node didn't answer a probe yet".to_string(),
+    }
+}
+
+/// Checks each task against the latest offer seen from every node without
+/// launching anything. Offers are only a snapshot, the resources may have been
+/// taken by another task since.
+pub fn simulate_placement(state_manager: &StateManager, tasks: &Vec<Task>) -> Vec<TaskSimulation> {
+    let mut nodes = state_manager.request_list_nodes();
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+    let probing = state_manager.get_config().node_probe.is_enabled();
+
+    tasks.iter()
+        .map(|task| {
+            let results: Vec<NodeSimulation> =
+                nodes.iter().map(|node| simulate_on_node(state_manager, task, node, probing)).collect();
+            TaskSimulation {
+                task: task.name.clone(),
+                fits_on: results.iter().filter(|result| result.fits).map(|result| result.node.clone()).collect(),
+                nodes: results,
+            }
+        })
+        .collect()
+}
+
+fn simulate_on_node(state_manager: &StateManager, task: &Task, node: &Node, probing: bool) -> NodeSimulation {
+    let offer = match node.last_offer {
+        Some(ref offer) => offer,
+        None => {
+            return NodeSimulation {
+                node: node.name.clone(),
+                offer_received_at: None,
+                fits: false,
+                rejected_by: "no_offer".to_string(),
+                detail: "no offer received from this node yet".to_string(),
+            }
+        }
+    };
+
+    // the scheduler declines offers from unreachable nodes before looking at any task
+    let (fits, rejected_by, detail) = if probing && !node.is_reachable() {
+        (false, "node_unreachable".to_string(), unreachable_detail(Some(node)))
+    } else {
+        match check_placement(state_manager, task, offer, Some(node)) {
+            Ok(_) => (true, "".to_string(), "".to_string()),
+            Err(rejection) => (false, rejection.label().to_string(), rejection.detail()),
+        }
+    };
+
+    NodeSimulation {
+        node: node.name.clone(),
+        offer_received_at: Some(offer.received_at),
+        fits: fits,
+        rejected_by: rejected_by,
+        detail: detail,
+    }
+}
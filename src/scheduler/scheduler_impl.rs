@@ -24,7 +24,7 @@
 
 use mesos::{Scheduler, SchedulerClient};
 use mesos::proto::{CommandInfo, ContainerInfo, ContainerInfo_DockerInfo, ContainerInfo_DockerInfo_Network, ContainerInfo_Type,
-                   ExecutorID, FrameworkID, InverseOffer, Offer, OfferID, Parameter, SlaveID, TaskInfo, TaskStatus, Volume,
+                   ExecutorID, FrameworkID, HealthCheck, InverseOffer, Offer, OfferID, Parameter, SlaveID, TaskInfo, TaskStatus, Volume,
                    Volume_Mode};
 use protobuf;
 use mesos::proto::TaskState as MesosTaskState;
@@ -32,6 +32,8 @@ use mesos::util;
 use state::{StateManager, TaskState};
 use utils;
 use collaborator::set_mesos_client;
+use metrics;
+use super::placement::{Rejection, check_placement, offer_snapshot, unreachable_detail};
 
 
 pub struct TorcScheduler<'lifetime> {
@@ -40,7 +42,7 @@ pub struct TorcScheduler<'lifetime> {
 
 impl<'lifetime> Scheduler for TorcScheduler<'lifetime> {
     fn subscribed(&mut self, client: &SchedulerClient, framework_id: &FrameworkID, heartbeat_interval_seconds: Option<f64>) {
-        println!("received subscribed with id: {}", framework_id.get_value());
+        log_info!("subscribed"; "framework_id" => framework_id.get_value());
 
         set_mesos_client(Some(client.clone()));
 
@@ -48,7 +50,7 @@ impl<'lifetime> Scheduler for TorcScheduler<'lifetime> {
     }
 
     fn inverse_offers(&mut self, client: &SchedulerClient, inverse_offers: Vec<&InverseOffer>) {
-        println!("received inverse offers");
+        log_debug!("received inverse offers"; "count" => inverse_offers.len());
 
         // this never lets go willingly
         let offer_ids = inverse_offers.iter()
@@ -61,95 +63,116 @@ impl<'lifetime> Scheduler for TorcScheduler<'lifetime> {
         // Offers are guaranteed to be for the same slave, and
         // there will be at least one.
         let slave_id = offers[0].get_slave_id();
+        metrics::record_offers_received(offers.len());
 
         let requested_tasks = self.state_manager.request_list_requested_tasks();
+        let config = self.state_manager.get_config();
 
         let mut tasks_to_start: Vec<TaskInfo> = vec![];
         let mut offers_to_decline: Vec<OfferID> = vec![];
         let mut offers_to_accept: Vec<OfferID> = vec![];
 
-        let mut attribute_host = "";
-        let mut attribute_node_name = "";
-        let mut attribute_node_type = "";
-        let mut attribute_node_function = "";
-
-        let mut offer_cpus: f64 = 0.0;
-        let mut offer_mem: f64 = 0.0;
-
         for offer in &offers {
             let mut found_match = false;
-
-            for attribute in &mut offer.get_attributes().into_iter() {
-                match attribute.get_name() {
-                    "host" => attribute_host = attribute.get_text().get_value(),
-                    "machine-name" => attribute_node_name = attribute.get_text().get_value(),
-                    "machine-type" => attribute_node_type = attribute.get_text().get_value(),
-                    "machine-function" => attribute_node_function = attribute.get_text().get_value(),
-                    _ => {}
-                }
+            // none while there are no pending tasks
+            let mut decline_reason: Option<Rejection> = None;
+            // (task, rejected by, detail) for every task this offer didn't fit
+            let mut rejections: Vec<(String, String, String)> = vec![];
+
+            let snapshot = offer_snapshot(offer);
+
+            log_debug!("received offer";
+                       "offer_id" => snapshot.offer_id,
+                       "host" => snapshot.attributes.get("host").cloned().unwrap_or("".to_string()),
+                       "node" => snapshot.node_name,
+                       "type" => snapshot.node_type,
+                       "function" => snapshot.node_function,
+                       "cpus" => snapshot.cpus,
+                       "mem" => snapshot.mem);
+
+            if !config.node_discovery.allows(&snapshot.node_name) {
+                log_debug!("offer from node not allowed"; "node" => snapshot.node_name, "offer_id" => snapshot.offer_id);
+                metrics::record_offer_declined("node_not_allowed");
+                offers_to_decline.push(offer.get_id().clone());
+                continue;
             }
 
-            for resource in &mut offer.get_resources().into_iter() {
-                match resource.get_name() {
-                    "mem" => offer_mem = resource.get_scalar().get_value(),
-                    "cpus" => offer_cpus = resource.get_scalar().get_value(),
-                    _ => {}
+            if !self.state_manager.request_is_node_active(snapshot.node_name.clone()) {
+                if config.node_discovery.auto_register {
+                    self.state_manager.send_register_node(snapshot.clone());
                 }
+                self.state_manager.send_update_node(snapshot.node_name.clone(),
+                                                    snapshot.node_type.clone(),
+                                                    snapshot.node_function.clone(),
+                                                    snapshot.slave_id.clone())
             }
-
-            println!("received offer from host: {}, name: {}, type: {}, function: {}",
-                     attribute_host,
-                     attribute_node_name,
-                     attribute_node_type,
-                     attribute_node_function);
-
-            if !self.state_manager.request_is_node_active(attribute_node_name.to_string()) {
-                self.state_manager.send_update_node(attribute_node_name.to_string(),
-                                                    attribute_node_type.to_string(),
-                                                    attribute_node_function.to_string(),
-                                                    offer.get_slave_id().get_value().to_string())
+            self.state_manager.send_record_offer(snapshot.clone());
+
+            let attribute_node_name = &*snapshot.node_name;
+            let node = self.state_manager.request_node(snapshot.node_name.clone());
+
+            if config.node_probe.is_enabled() && !node.as_ref().map(|node| node.is_reachable()).unwrap_or(false) {
+                log_debug!("offer from unreachable node"; "node" => snapshot.node_name, "offer_id" => snapshot.offer_id);
+                let rejections = requested_tasks.iter()
+                    .map(|task| (task.name.clone(), "node_unreachable".to_string(), unreachable_detail(node.as_ref())))
+                    .collect::<Vec<(String, String, String)>>();
+                if !rejections.is_empty() {
+                    self.state_manager.send_record_pending_reasons(snapshot.node_name.clone(), rejections);
+                }
+                metrics::record_offer_declined("node_unreachable");
+                offers_to_decline.push(offer.get_id().clone());
+                continue;
             }
 
             for task_immutable in &requested_tasks {
                 let mut task = task_immutable.clone();
-                if task.node_name.len() > 0 && task.node_name != attribute_node_name {
-                    continue;
-                }
-
-                if task.node_type.len() > 0 && task.node_type != attribute_node_type {
-                    continue;
-                }
-
-                if task.node_function.len() > 0 && task.node_function != attribute_node_function {
-                    continue;
-                }
 
-                if task.dependent_service.len() > 0 {
-                    match self.state_manager.request_task_state(task.dependent_service.to_string()) {
-                        TaskState::Running => {}
-                        _ => continue,
+                let placement = match check_placement(self.state_manager, &task, &snapshot, node.as_ref()) {
+                    Ok(placement) => placement,
+                    Err(rejection) => {
+                        if let Rejection::UnresolvedReference(ref unresolved) = rejection {
+                            log_debug!("task waits for references";
+                                       "task" => task.name,
+                                       "node" => attribute_node_name,
+                                       "unresolved" => unresolved.join(", "));
+                        }
+                        rejections.push((task.name.clone(), rejection.label().to_string(), rejection.detail()));
+                        if decline_reason.as_ref().map(|reason| reason.rank() < rejection.rank()).unwrap_or(true) {
+                            decline_reason = Some(rejection);
+                        }
+                        continue;
                     }
-                }
+                };
+                let arguments = placement.arguments;
+                task.parameters = placement.parameters;
 
-                if offer_cpus < task.cpu || offer_mem < task.memory {
-                    continue;
-                }
-
-                println!("Starting {}, arguments: {:?}", task.name, task);
-                self.state_manager.send_update_task_state(task.name.clone(), TaskState::Accepted);
+                log_info!("starting task";
+                          "task" => task.name,
+                          "node" => attribute_node_name,
+                          "offer_id" => offer.get_id().get_value(),
+                          "image" => task.image,
+                          "arguments" => arguments);
 
                 if task.node_type.len() > 0 || task.node_function.len() > 0 {
                     self.state_manager.send_update_task_node_name(task.name.clone(), attribute_node_name.to_string())
                 }
 
+                self.state_manager.send_update_task_state(task.name.clone(), TaskState::Accepted);
+                // counts against the node's capacity from now on, not only once it runs
+                self.state_manager.send_update_task_info(task.name.clone(),
+                                                         "".to_string(),
+                                                         "".to_string(),
+                                                         vec![],
+                                                         snapshot.slave_id.clone());
+
                 let name = &*format!("{}", task.name);
                 let task_id = util::task_id(name);
 
                 let mut command = CommandInfo::new();
                 command.set_shell(false);
 
-                if task.arguments.len() > 0 {
-                    let elmts: Vec<&str> = task.arguments
+                if arguments.len() > 0 {
+                    let elmts: Vec<&str> = arguments
                         .split(|c: char| c == ' ')
                         .filter(|s| !s.is_empty())
                         .collect();
@@ -229,7 +252,22 @@ impl<'lifetime> Scheduler for TorcScheduler<'lifetime> {
                 let cpus = util::scalar("cpus", "*", task.cpu);
                 let resources = vec![mem, cpus];
 
-                let task_info = util::task_info_for_container(name, &task_id, slave_id, &command, &container, resources);
+                let mut task_info = util::task_info_for_container(name, &task_id, slave_id, &command, &container, resources);
+
+                if let Some(ref definition) = task.health_check {
+                    let mut health_command = CommandInfo::new();
+                    health_command.set_shell(true);
+                    health_command.set_value(definition.command.clone());
+
+                    let mut health_check = HealthCheck::new();
+                    health_check.set_command(health_command);
+                    health_check.set_interval_seconds(definition.interval_in_seconds as f64);
+                    health_check.set_timeout_seconds(definition.timeout_in_seconds as f64);
+                    health_check.set_grace_period_seconds(definition.grace_period_in_seconds as f64);
+                    health_check.set_consecutive_failures(definition.consecutive_failures as u32);
+                    task_info.set_health_check(health_check);
+                }
+
                 tasks_to_start.push(task_info);
                 offers_to_accept.push(offer.get_id().clone());
 
@@ -239,7 +277,15 @@ impl<'lifetime> Scheduler for TorcScheduler<'lifetime> {
                 break;
             }
 
-            if !found_match {
+            if !rejections.is_empty() {
+                self.state_manager.send_record_pending_reasons(snapshot.node_name.clone(), rejections);
+            }
+
+            if found_match {
+                metrics::record_offer_accepted();
+            } else {
+                let reason = decline_reason.as_ref().map(|reason| reason.label()).unwrap_or("no_pending_tasks");
+                metrics::record_offer_declined(reason);
                 offers_to_decline.push(offer.get_id().clone());
             }
 
@@ -255,17 +301,24 @@ impl<'lifetime> Scheduler for TorcScheduler<'lifetime> {
     }
 
     fn rescind(&mut self, client: &SchedulerClient, offer_id: &OfferID) {
-        println!("received rescind");
+        log_debug!("received rescind"; "offer_id" => offer_id.get_value());
     }
 
     fn update(&mut self, client: &SchedulerClient, status: &TaskStatus) {
-        println!("received update {:?} from {}",
-                 status.get_state(),
-                 status.get_task_id().get_value());
+        log_info!("received update";
+                  "task" => status.get_task_id().get_value(),
+                  "state" => format!("{:?}", status.get_state()),
+                  "slave_id" => status.get_slave_id().get_value());
 
         let task_name = status.get_task_id().get_value().to_string();
 
         match status.get_state() {
+            MesosTaskState::TASK_RUNNING if status.has_healthy() &&
+                                             self.state_manager.request_task_state(task_name.clone()) ==
+                                             TaskState::Running => {
+                // health check results arrive as further running updates
+                self.state_manager.send_update_task_health(task_name, status.get_healthy());
+            }
             MesosTaskState::TASK_RUNNING => {
                 let raw_data: Vec<u8> = Vec::from(status.get_data());
                 let docker_inspect = String::from_utf8(raw_data).unwrap();
@@ -276,18 +329,39 @@ impl<'lifetime> Scheduler for TorcScheduler<'lifetime> {
                 self.state_manager.send_update_task_state(task_name, TaskState::Running);
             }
             MesosTaskState::TASK_FINISHED => {
-                // should only be valid for system-services .. change it
-                // self.state_manager.send_update_task_state(task_name, TaskState::Requested);
-            }            
+                match self.state_manager.request_task(task_name.clone()) {
+                    Some(ref task) if task.is_job => {
+                        self.state_manager.send_finish_job(task_name, "finished", Some(0), status.get_message().to_string())
+                    }
+                    // a service isn't supposed to end, the health checker restarts system services
+                    Some(_) => self.state_manager.send_fail_task_by_name(task_name),
+                    None => {}
+                }
+            }
             MesosTaskState::TASK_KILLED => {
                 self.state_manager.send_remove_task_by_name(task_name);
-            }            
+            }
+            MesosTaskState::TASK_FAILED | MesosTaskState::TASK_LOST | MesosTaskState::TASK_ERROR => {
+                match self.state_manager.request_task(task_name.clone()) {
+                    Some(ref task) if task.is_job => {
+                        let result = match status.get_state() {
+                            MesosTaskState::TASK_LOST => "lost",
+                            _ => "failed",
+                        };
+                        self.state_manager.send_finish_job(task_name,
+                                                           result,
+                                                           exit_status(status.get_message()),
+                                                           status.get_message().to_string())
+                    }
+                    _ => self.state_manager.send_fail_task_by_name(task_name),
+                }
+            }
             _ => {}
         }
     }
 
     fn message(&mut self, client: &SchedulerClient, slave_id: &SlaveID, executor_id: &ExecutorID, data: Vec<u8>) {
-        println!("received message");
+        log_debug!("received message"; "slave_id" => slave_id.get_value(), "executor_id" => executor_id.get_value());
     }
 
     fn failure(&mut self,
@@ -295,18 +369,43 @@ impl<'lifetime> Scheduler for TorcScheduler<'lifetime> {
                slave_id: Option<&SlaveID>,
                executor_id: Option<&ExecutorID>,
                status: Option<i32>) {
-        println!("received failure");
+        log_warn!("received failure";
+                  "slave_id" => slave_id.map(|id| id.get_value()).unwrap_or(""),
+                  "executor_id" => executor_id.map(|id| id.get_value()).unwrap_or(""),
+                  "status" => status.map(|s| s.to_string()).unwrap_or("".to_string()));
+
+        // without an executor the whole agent is gone, its tasks won't report anymore
+        if let (Some(slave_id), None) = (slave_id, executor_id) {
+            for task in self.state_manager.request_agent_lost(slave_id.get_value().to_string()) {
+                log_info!("task lost with its agent"; "task" => task.name, "slave_id" => slave_id.get_value());
+                match task.is_job {
+                    true => self.state_manager.send_finish_job(task.name, "lost", None, "agent lost".to_string()),
+                    // not running anymore, the health checker or the next group start launches it elsewhere
+                    false => self.state_manager.send_fail_task_by_name(task.name),
+                }
+            }
+        }
     }
 
     fn error(&mut self, client: &SchedulerClient, message: String) {
-        println!("received error");
+        log_error!("received error"; "message" => message);
     }
 
     fn heartbeat(&mut self, client: &SchedulerClient) {
-        println!("received heartbeat");
+        log_trace!("received heartbeat");
     }
 
     fn disconnected(&mut self) {
-        println!("disconnected from scheduler");
+        log_warn!("disconnected from scheduler");
     }
 }
+
+// the executors report the exit code only in the message, e.g. "Container exited with status 1"
+fn exit_status(message: &str) -> Option<i32> {
+    message.find("exited with status ").and_then(|pos| {
+        message[pos + "exited with status ".len()..]
+            .split(|c: char| !c.is_digit(10) && c != '-')
+            .next()
+            .and_then(|status| status.parse().ok())
+    })
+}
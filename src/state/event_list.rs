@@ -0,0 +1,165 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use chrono::UTC;
+
+const MAX_EVENT_HISTORY: usize = 1000;
+
+pub struct EventList {
+    next_id: Mutex<u64>,
+    history: Mutex<VecDeque<Event>>,
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+#[derive(Clone, Hash, Eq, PartialEq, Debug, RustcEncodable)]
+pub enum EventKind {
+    TaskRequested,
+    TaskAccepted,
+    TaskRunning,
+    TaskKilled,
+    TaskFailed,
+    TaskHealthy,
+    TaskUnhealthy,
+    JobFinished,
+    NodeRegistered,
+    NodeUpdated,
+    NodeRemoved,
+    NodeExpired,
+    NodeReachable,
+    NodeUnreachable,
+    NodeLost,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct Event {
+    pub id: u64,
+    pub kind: EventKind,
+    pub service: String,
+    pub node: String,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct EventFilter {
+    pub service: Option<String>,
+    pub node: Option<String>,
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    sender: Sender<Event>,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EventKind::TaskRequested => "task_requested",
+            EventKind::TaskAccepted => "task_accepted",
+            EventKind::TaskRunning => "task_running",
+            EventKind::TaskKilled => "task_killed",
+            EventKind::TaskFailed => "task_failed",
+            EventKind::TaskHealthy => "task_healthy",
+            EventKind::TaskUnhealthy => "task_unhealthy",
+            EventKind::JobFinished => "job_finished",
+            EventKind::NodeRegistered => "node_registered",
+            EventKind::NodeUpdated => "node_updated",
+            EventKind::NodeRemoved => "node_removed",
+            EventKind::NodeExpired => "node_expired",
+            EventKind::NodeReachable => "node_reachable",
+            EventKind::NodeUnreachable => "node_unreachable",
+            EventKind::NodeLost => "node_lost",
+        }
+    }
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        if let Some(ref service) = self.service {
+            if *service != event.service {
+                return false;
+            }
+        }
+
+        if let Some(ref node) = self.node {
+            if *node != event.node {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl EventList {
+    pub fn new() -> EventList {
+        EventList {
+            next_id: Mutex::new(1),
+            history: Mutex::new(VecDeque::new()),
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn publish(&self, kind: EventKind, service: String, node: String) {
+        let mut next_id = self.next_id.lock().unwrap();
+        let event = Event {
+            id: *next_id,
+            kind: kind,
+            service: service,
+            node: node,
+            timestamp: UTC::now().timestamp(),
+        };
+        *next_id += 1;
+
+        let mut history = self.history.lock().unwrap();
+        if history.len() >= MAX_EVENT_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(event.clone());
+
+        // subscribers whose stream went away are dropped on the next publish
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| !subscriber.filter.matches(&event) || subscriber.sender.send(event.clone()).is_ok());
+    }
+
+    pub fn subscribe(&self, filter: EventFilter, last_event_id: Option<u64>) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+
+        if let Some(last_id) = last_event_id {
+            let history = self.history.lock().unwrap();
+            for event in history.iter().filter(|event| event.id > last_id && filter.matches(event)) {
+                sender.send(event.clone()).unwrap();
+            }
+        }
+
+        self.subscribers.lock().unwrap().push(Subscriber {
+            filter: filter,
+            sender: sender,
+        });
+
+        receiver
+    }
+}
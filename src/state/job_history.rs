@@ -0,0 +1,72 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// One run of a job, a job that gets retried has a run for every attempt.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct JobRun {
+    pub job: String,
+    pub attempt: i64,
+    pub node: String,
+    /// finished, failed or lost
+    pub result: String,
+    pub exit_status: Option<i32>,
+    pub message: String,
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub duration_in_seconds: i64,
+    pub retried: bool,
+}
+
+pub struct JobHistory {
+    runs: Mutex<VecDeque<JobRun>>,
+}
+
+impl JobHistory {
+    pub fn new() -> JobHistory {
+        JobHistory { runs: Mutex::new(VecDeque::new()) }
+    }
+
+    /// Adds the run and drops the oldest ones beyond `history_size`.
+    pub fn record(&self, run: JobRun, history_size: usize) {
+        let mut runs = self.runs.lock().unwrap();
+        runs.push_back(run);
+        while runs.len() > history_size {
+            runs.pop_front();
+        }
+    }
+
+    /// The latest runs first, optionally of a single job.
+    pub fn get_runs(&self, job: Option<String>, limit: usize) -> Vec<JobRun> {
+        self.runs
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|run| job.as_ref().map(|job| run.job == *job).unwrap_or(true))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
@@ -21,9 +21,13 @@
 // THE SOFTWARE.
 
 pub use self::state::{StateManager, TaskState};
-pub use self::task_list::{SLA, Task, Volume};
-pub use self::node_list::Node;
+pub use self::task_list::{Dependency, DependencyCondition, HealthCheck, PendingReason, PortMapping, SLA, Task, Volume};
+pub use self::node_list::{Node, NodeOffer, ResourceCapacity};
+pub use self::event_list::{Event, EventFilter, EventKind};
+pub use self::job_history::JobRun;
 
 mod state;
 mod task_list;
 mod node_list;
+mod event_list;
+mod job_history;
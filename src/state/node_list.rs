@@ -20,7 +20,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Mutex;
 
 pub struct NodeList {
@@ -39,6 +39,68 @@ pub struct Node {
     pub slave_id: String,
     pub port_id: i64,
     pub reachable: bool,
+    /// when the node last answered a probe
+    pub last_seen_at: Option<i64>,
+    pub last_probed_at: Option<i64>,
+    /// added because its agent offered, not listed in the config
+    pub discovered: bool,
+    pub last_offer: Option<NodeOffer>,
+    /// empty until the node sent an offer
+    pub capacity: Vec<ResourceCapacity>,
+    /// the accepted and running tasks on the node
+    pub tasks: Vec<String>,
+}
+
+/// How much of a resource a node has for this sub-scheduler, what other
+/// frameworks use on the node isn't part of the total.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct ResourceCapacity {
+    pub resource: String,
+    pub total: f64,
+    pub allocated: f64,
+    pub free: f64,
+}
+
+/// What the latest mesos offer from a node contained, kept to explain where
+/// requested tasks can and can't be placed.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct NodeOffer {
+    pub offer_id: String,
+    pub slave_id: String,
+    pub hostname: String,
+    pub node_name: String,
+    pub node_type: String,
+    pub node_function: String,
+    pub attributes: BTreeMap<String, String>,
+    pub cpus: f64,
+    pub mem: f64,
+    pub received_at: i64,
+}
+
+impl Node {
+    /// Until its first probe a node counts as reachable, its offers would
+    /// otherwise be declined for up to a poll interval after it registers.
+    pub fn is_reachable(&self) -> bool {
+        self.reachable || self.last_probed_at.is_none()
+    }
+}
+
+impl ResourceCapacity {
+    /// `offered` is what was left over when the offer was made, `allocated`
+    /// what our tasks held on the node at that time.
+    pub fn new(resource: &str, offered: f64, allocated: f64) -> ResourceCapacity {
+        ResourceCapacity {
+            resource: resource.to_string(),
+            total: offered + allocated,
+            allocated: allocated,
+            free: offered,
+        }
+    }
+
+    pub fn set_allocated(&mut self, allocated: f64) {
+        self.allocated = allocated;
+        self.free = (self.total - allocated).max(0.0);
+    }
 }
 
 impl NodeList {
@@ -47,7 +109,7 @@ impl NodeList {
     }
 
     pub fn add_new_node(&self, node: &Node) {
-        println!("insert new node {}", node.name);
+        log_info!("insert new node"; "node" => node.name, "ip" => node.ip, "type" => node.node_type);
         self.node_list.lock().unwrap().insert(node.name.to_string(), node.clone());
     }
 
@@ -58,10 +120,10 @@ impl NodeList {
         }
     }
 
-    pub fn update_node(&self, node_name: String, node_type: String, node_function: String, slave_id: String) {
+    pub fn update_node(&self, node_name: String, node_type: String, node_function: String, slave_id: String) -> bool {
         let exists;
 
-        println!("upate node {}", node_name);
+        log_debug!("update node"; "node" => node_name, "slave_id" => slave_id);
 
         match self.node_list.lock().unwrap().get_mut(&node_name) {
             Some(node) => {
@@ -75,8 +137,87 @@ impl NodeList {
         }
 
         if exists == false {
-            println!("no node entry found for {}", node_name);
+            log_warn!("no node entry found"; "node" => node_name);
         }
+
+        exists
+    }
+
+    /// Replaces the configured fields of a node and keeps what was learned
+    /// from mesos, returns false if the node isn't known.
+    pub fn reconfigure_node(&self, node: &Node) -> bool {
+        log_info!("reconfigure node"; "node" => node.name, "ip" => node.ip, "type" => node.node_type);
+
+        match self.node_list.lock().unwrap().get_mut(&node.name) {
+            Some(existing) => {
+                existing.ip = node.ip.clone();
+                existing.external_ip = node.external_ip.clone();
+                existing.management_ip = node.management_ip.clone();
+                existing.port_id = node.port_id;
+                existing.node_type = node.node_type.clone();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn record_offer(&self, node_name: String, offer: NodeOffer, capacity: Vec<ResourceCapacity>) -> bool {
+        match self.node_list.lock().unwrap().get_mut(&node_name) {
+            Some(node) => {
+                node.last_offer = Some(offer);
+                node.capacity = capacity;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records the outcome of a probe, returns true if the node wasn't probed
+    /// before or its reachability changed.
+    pub fn set_node_reachable(&self, node_name: String, reachable: bool, probed_at: i64) -> bool {
+        match self.node_list.lock().unwrap().get_mut(&node_name) {
+            Some(node) => {
+                let changed = node.last_probed_at.is_none() || node.reachable != reachable;
+                node.reachable = reachable;
+                node.last_probed_at = Some(probed_at);
+                if reachable {
+                    node.last_seen_at = Some(probed_at);
+                }
+                changed
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the node inactive until its agent offers again, returns false
+    /// if the node isn't known or wasn't active.
+    pub fn deactivate_node(&self, node_name: String) -> bool {
+        match self.node_list.lock().unwrap().get_mut(&node_name) {
+            Some(node) => {
+                let was_active = node.active;
+                node.active = false;
+                was_active
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the node of the agent inactive, returns its name if the agent
+    /// belongs to a known node.
+    pub fn deactivate_agent(&self, slave_id: String) -> Option<String> {
+        let mut node_list = self.node_list.lock().unwrap();
+        match node_list.values_mut().find(|node| node.slave_id == slave_id) {
+            Some(node) => {
+                node.active = false;
+                Some(node.name.clone())
+            }
+            None => None,
+        }
+    }
+
+    pub fn remove_node(&self, node_name: String) -> bool {
+        log_info!("remove node"; "node" => node_name);
+        self.node_list.lock().unwrap().remove(&node_name).is_some()
     }
 
     pub fn get_node(&self, node_name: String) -> Result<Node, &'static str> {
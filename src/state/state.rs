@@ -20,27 +20,37 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use std::fs;
+use std::io::{self, Write};
+use std::process;
 use std::thread;
-use std::fs::File;
-use std::io::Read;
+use std::sync::{Arc, RwLock};
 use std::sync::mpsc::{Receiver, Sender, channel};
-use std::time::Duration;
-use yaml_rust::{Yaml, YamlLoader};
+use std::time::{Duration, Instant, SystemTime};
+use yaml_rust::Yaml;
 use collaborator::{kill_task, register_running_task};
-use utils::{read_int, read_string};
-use super::task_list::{SLA, Task, TaskList, Volume};
-use super::node_list::{Node, NodeList};
+use config::{Config, ConfigChanges, ConfigErrors, diff_config, load_config};
+use std::env;
+use utils::{Reference, lookup_config_value, on_sighup, render_template};
+use super::task_list::{Dependency, HealthCheck, PendingReason, PortMapping, SLA, Task, TaskList, Volume};
+use super::node_list::{Node, NodeList, NodeOffer, ResourceCapacity};
+use super::event_list::{Event, EventFilter, EventKind, EventList};
+use super::job_history::{JobHistory, JobRun};
 use uuid::Uuid;
 use chrono::UTC;
+use metrics;
+use logging;
 
 #[derive (Clone)]
 pub struct StateManager {
-    sender: Sender<StateRequestMsg>,
+    sender: Sender<StateRequest>,
     master_ip: String,
     my_name: String,
     controller_ip: String,
     my_framework_id: String,
-    config: Yaml,
+    config_file: String,
+    config_overlays: Vec<String>,
+    config: Arc<RwLock<Config>>,
 }
 
 #[derive(Clone, Hash, Eq, PartialEq, Debug, RustcEncodable)]
@@ -49,25 +59,46 @@ pub enum TaskState {
     Requested,
     Accepted,
     Running,
+    /// a job that completed, kept until `jobs.retention_in_seconds` passed
+    Finished,
+    /// a job that failed its last attempt
+    Failed,
+}
+
+impl TaskState {
+    pub fn all() -> Vec<TaskState> {
+        vec![TaskState::NotRunning,
+             TaskState::Requested,
+             TaskState::Accepted,
+             TaskState::Running,
+             TaskState::Finished,
+             TaskState::Failed]
+    }
 }
 
 
 impl StateManager {
-    pub fn new(master_ip: String, controller_ip: String, config_file: String) -> StateManager {
+    pub fn new(master_ip: String, controller_ip: String, config_file: String, config_overlays: Vec<String>) -> StateManager {
         let (tx, rx) = channel();
-        let config = StateManager::read_config_file(config_file);
-        let my_name = config["name"].as_str().unwrap_or("torc-controller").to_string();
+        let config = StateManager::read_config_file(&config_file, &config_overlays);
+        logging::configure_from_config(&config.logging);
+        let my_name = config.name.clone();
         let statemanager = StateManager {
             sender: tx,
             master_ip: master_ip,
             my_name: my_name.clone(),
             controller_ip: controller_ip,
             my_framework_id: format!("{}-{}", my_name.clone(), Uuid::new_v4().to_simple_string()),
-            config: config,
+            config_file: config_file,
+            config_overlays: config_overlays,
+            config: Arc::new(RwLock::new(config)),
         };
         statemanager.start_serving(rx);
         statemanager.load_node_list();
         statemanager.start_syncing();
+        statemanager.start_config_watch();
+        statemanager.start_job_retention();
+        statemanager.start_node_expiry();
         statemanager
     }
 
@@ -87,15 +118,79 @@ impl StateManager {
         self.controller_ip.clone()
     }
 
+    pub fn get_config(&self) -> Config {
+        self.config.read().unwrap().clone()
+    }
+
     pub fn get_yaml(&self) -> Yaml {
-        self.config.clone()
+        self.config.read().unwrap().raw.clone()
+    }
+
+    /// Reads the config file again and applies the difference to the node
+    /// list. System services and service groups are picked up from the new
+    /// config by the health checker and the api. A config that doesn't load
+    /// is rejected as a whole and the current one stays in place.
+    pub fn reload_config(&self) -> Result<ConfigChanges, ConfigErrors> {
+        // held for the whole reload so two reloads can't interleave
+        let mut config = self.config.write().unwrap();
+
+        let new_config = match load_config(&self.config_file, &self.config_overlays) {
+            Ok(new_config) => new_config,
+            Err(errors) => {
+                log_warn!("config reload rejected"; "file" => self.config_file, "errors" => errors.errors.len());
+                return Err(errors);
+            }
+        };
+
+        logging::configure_from_config(&new_config.logging);
+
+        let changes = diff_config(&config, &new_config);
+
+        for node_name in &changes.nodes_removed {
+            self.send_remove_node(node_name.clone());
+        }
+        for node in &new_config.nodes {
+            if changes.nodes_added.contains(&node.name) {
+                self.send_add_node(node.name.clone(),
+                                   node.ip.clone(),
+                                   node.external_ip.clone(),
+                                   node.management_ip.clone(),
+                                   node.port,
+                                   node.node_type.clone());
+            } else if changes.nodes_updated.contains(&node.name) {
+                self.send_reconfigure_node(node.name.clone(),
+                                           node.ip.clone(),
+                                           node.external_ip.clone(),
+                                           node.management_ip.clone(),
+                                           node.port,
+                                           node.node_type.clone());
+            }
+        }
+
+        *config = new_config;
+
+        log_info!("config reloaded";
+                  "file" => self.config_file,
+                  "nodes_added" => changes.nodes_added.len(),
+                  "nodes_removed" => changes.nodes_removed.len(),
+                  "nodes_updated" => changes.nodes_updated.len(),
+                  "system_services_added" => changes.system_services_added.len(),
+                  "system_services_removed" => changes.system_services_removed.len(),
+                  "system_services_updated" => changes.system_services_updated.len(),
+                  "service_groups_changed" => changes.service_groups_added.len() +
+                                              changes.service_groups_removed.len() +
+                                              changes.service_groups_updated.len(),
+                  "workflows_changed" => changes.workflows_added.len() + changes.workflows_removed.len() +
+                                         changes.workflows_updated.len());
+
+        Ok(changes)
     }
 
     pub fn send_ping(&self) {
         let (sender, receiver) = channel();
 
         let msg = StateRequestMsg::Ping { sender: sender };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
         receiver.recv().unwrap();
     }
 
@@ -106,7 +201,7 @@ impl StateManager {
             sender: sender,
             task_name: task_name,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
 
         let state = match receiver.recv().unwrap() {
             StateResponseMsg::TasksState { tasks_state } => tasks_state,
@@ -123,7 +218,7 @@ impl StateManager {
             sender: sender,
             id_prefix: id_prefix,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
 
         let task_name: String = match receiver.recv().unwrap() {
             StateResponseMsg::TaskName { task_name } => task_name,
@@ -133,6 +228,59 @@ impl StateManager {
         task_name.clone()
     }
 
+    pub fn request_task(&self, task_name: String) -> Option<Task> {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::GetTask {
+            sender: sender,
+            task_name: task_name,
+        };
+        self.send_request(msg);
+
+        match receiver.recv().unwrap() {
+            StateResponseMsg::GetTask { task } => task,
+            _ => None,
+        }
+    }
+
+    /// Fills in the `${...}` references in a task's arguments or parameters
+    /// for a launch on `node`, see `utils::render_template`. Services are only
+    /// referenced once they are running.
+    pub fn resolve_template(&self, template: &String, task: &Task, node: Option<&Node>) -> Result<String, Vec<String>> {
+        render_template(template, |reference| {
+            match *reference {
+                Reference::MasterIp => Some(self.master_ip.clone()),
+                Reference::ServiceIp(ref name) => self.request_running_task(name).map(|service| service.ip),
+                Reference::ServiceNode(ref name) => self.request_running_task(name).map(|service| service.node_name),
+                Reference::ServicePort(ref name, container_port) => {
+                    self.request_running_task(name).and_then(|service| find_host_port(&service, container_port))
+                }
+                Reference::Node(None, ref attribute) => node.and_then(|node| node_attribute(node, attribute)),
+                Reference::Node(Some(ref name), ref attribute) => {
+                    self.request_node(name.clone()).and_then(|node| node_attribute(&node, attribute))
+                }
+                Reference::TaskName => Some(task.name.clone()),
+                Reference::TaskInstance => Some(task.instance.to_string()),
+                Reference::Env(ref name) => env::var(name).ok(),
+                Reference::Config(ref path) => lookup_config_value(&self.get_yaml(), path),
+                // filled in by the workflow before the step gets started
+                Reference::StepOutput(..) => None,
+            }
+        })
+    }
+
+    fn request_running_task(&self, task_name: &String) -> Option<Task> {
+        match self.request_task(task_name.clone()) {
+            Some(task) => {
+                match task.state {
+                    TaskState::Running => Some(task),
+                    _ => None,
+                }
+            }
+            None => None,
+        }
+    }
+
     pub fn request_task_ip_by_name(&self, name: String) -> String {
         let (sender, receiver) = channel();
 
@@ -140,7 +288,7 @@ impl StateManager {
             sender: sender,
             name: name,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
 
         let task_ip: String = match receiver.recv().unwrap() {
             StateResponseMsg::TaskIP { task_ip } => task_ip,
@@ -158,7 +306,7 @@ impl StateManager {
             task_name: task_name,
             task_state: task_state,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
         receiver.recv().unwrap();
     }
 
@@ -170,11 +318,16 @@ impl StateManager {
             task_name: task_name,
             node_name: node_name,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
         receiver.recv().unwrap();
     }
 
-    pub fn send_update_task_info(&self, task_name: String, id: String, ip: String, slave_id: String) {
+    pub fn send_update_task_info(&self,
+                                 task_name: String,
+                                 id: String,
+                                 ip: String,
+                                 ports: Vec<PortMapping>,
+                                 slave_id: String) {
         let (sender, receiver) = channel();
 
         let msg = StateRequestMsg::UpdateTaskInfo {
@@ -182,19 +335,23 @@ impl StateManager {
             task_name: task_name,
             id: id,
             ip: ip,
+            ports: ports,
             slave_id: slave_id,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
         receiver.recv().unwrap();
     }
 
     pub fn send_start_task(&self,
                            name: &String,
+                           instance: &i64,
                            image: &String,
                            node_name: &String,
                            node_type: &String,
                            node_function: &String,
                            dependent_service: &String,
+                           dependencies: &Vec<Dependency>,
+                           health_check: &Option<HealthCheck>,
                            arguments: &String,
                            parameters: &String,
                            memory: &f64,
@@ -205,12 +362,12 @@ impl StateManager {
                            is_metered: &bool,
                            is_system_service: &bool,
                            is_job: &bool,
-                           network_type: &String) {
+                           max_retries: &i64,
+                           network_type: &String,
+                           constraints: &Vec<String>) {
 
         let (sender, receiver) = channel();
 
-        let resolved_arguments = self.resolve_arguments(&arguments);
-
         let new_task = Task {
             name: name.clone(),
             controller: self.get_my_name(),
@@ -220,7 +377,9 @@ impl StateManager {
             node_type: node_type.clone(),
             node_function: node_function.clone(),
             dependent_service: dependent_service.clone(),
-            arguments: resolved_arguments.clone(),
+            dependencies: dependencies.clone(),
+            health_check: health_check.clone(),
+            arguments: arguments.clone(),
             parameters: parameters.clone(),
             memory: memory.clone(),
             cpu: cpu.clone(),
@@ -229,12 +388,22 @@ impl StateManager {
             is_metered: is_metered.clone(),
             is_system_service: is_system_service.clone(),
             is_job: is_job.clone(),
+            max_retries: max_retries.clone(),
+            attempt: 0,
             volumes: volumes.clone(),
             network_type: network_type.clone(),
+            constraints: constraints.clone(),
+            instance: instance.clone(),
             ip: "".to_string(),
+            ports: vec![],
             slave_id: "".to_string(),
             state: TaskState::Requested,
+            healthy: None,
+            started_at: 0,
+            finished_at: 0,
+            exit_status: None,
             last_update: UTC::now().timestamp(),
+            pending_reasons: vec![],
         };
 
         let msg = StateRequestMsg::StartTask {
@@ -242,7 +411,68 @@ impl StateManager {
             task: new_task,
         };
 
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
+    /// Records a completed run of a job. A failed run is retried until the
+    /// job's `max_retries` are used up, the job then stays Finished or Failed
+    /// until `jobs.retention_in_seconds` passed.
+    pub fn send_finish_job(&self, task_name: String, result: &str, exit_status: Option<i32>, message: String) {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::FinishJob {
+            sender: sender,
+            task_name: task_name,
+            result: result.to_string(),
+            exit_status: exit_status,
+            message: message,
+            history_size: self.get_config().jobs.history_size as usize,
+        };
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
+    pub fn request_job_runs(&self, job: Option<String>, limit: usize) -> Vec<JobRun> {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::GetJobRuns {
+            sender: sender,
+            job: job,
+            limit: limit,
+        };
+        self.send_request(msg);
+
+        match receiver.recv().unwrap() {
+            StateResponseMsg::GetJobRuns { runs } => runs,
+            _ => vec![],
+        }
+    }
+
+    /// Records the result of the latest health check mesos ran for the task.
+    pub fn send_update_task_health(&self, task_name: String, healthy: bool) {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::UpdateTaskHealth {
+            sender: sender,
+            task_name: task_name,
+            healthy: healthy,
+        };
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
+    /// Records why an offer from `node_name` didn't fit each of the tasks,
+    /// given as (task name, rejected by, detail).
+    pub fn send_record_pending_reasons(&self, node_name: String, rejections: Vec<(String, String, String)>) {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::RecordPendingReasons {
+            sender: sender,
+            node_name: node_name,
+            rejections: rejections,
+        };
+        self.send_request(msg);
         receiver.recv().unwrap();
     }
 
@@ -250,6 +480,17 @@ impl StateManager {
         kill_task(&task_name);
     }
 
+    pub fn send_fail_task_by_name(&self, task_name: String) {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::FailTask {
+            sender: sender,
+            task_name: task_name,
+        };
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
     pub fn send_remove_task_by_name(&self, task_name: String) {
         let (sender, receiver) = channel();
 
@@ -257,7 +498,7 @@ impl StateManager {
             sender: sender,
             task_name: task_name,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
         receiver.recv().unwrap();
     }
 
@@ -265,7 +506,7 @@ impl StateManager {
         let (sender, receiver) = channel();
 
         let msg = StateRequestMsg::GetRequestedTasks { sender: sender };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
 
         let result: Vec<Task> = match receiver.recv().unwrap() {
             StateResponseMsg::GetRequestedTasks { requested_tasks } => requested_tasks,
@@ -279,7 +520,7 @@ impl StateManager {
         let (sender, receiver) = channel();
 
         let msg = StateRequestMsg::GetRunningTasks { sender: sender };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
 
         let result: Vec<Task> = match receiver.recv().unwrap() {
             StateResponseMsg::GetRunningTasks { running_tasks } => running_tasks,
@@ -309,6 +550,12 @@ impl StateManager {
             slave_id: "".to_string(),
             port_id: port_id,
             reachable: false,
+            last_seen_at: None,
+            last_probed_at: None,
+            discovered: false,
+            last_offer: None,
+            capacity: vec![],
+            tasks: vec![],
         };
 
         let msg = StateRequestMsg::AddNode {
@@ -316,7 +563,7 @@ impl StateManager {
             node: new_node,
         };
 
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
         receiver.recv().unwrap();
     }
 
@@ -327,7 +574,7 @@ impl StateManager {
             sender: sender,
             node_name: node_name,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
 
         let is_active = match receiver.recv().unwrap() {
             StateResponseMsg::GetIsNodeActive { is_active } => is_active,
@@ -347,7 +594,112 @@ impl StateManager {
             node_function: node_function,
             slave_id: slave_id,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
+    /// Keeps the offer as the latest one seen from its node, offers from
+    /// nodes that aren't in the node list are ignored.
+    pub fn send_record_offer(&self, offer: NodeOffer) {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::RecordOffer {
+            sender: sender,
+            offer: offer,
+        };
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
+    /// Adds a node for an agent that offered without being in the node list,
+    /// does nothing if the node is known already.
+    pub fn send_register_node(&self, offer: NodeOffer) {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::RegisterNode {
+            sender: sender,
+            offer: offer,
+        };
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
+    pub fn send_update_node_reachability(&self, node_name: String, reachable: bool, probed_at: i64) {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::UpdateNodeReachability {
+            sender: sender,
+            node_name: node_name,
+            reachable: reachable,
+            probed_at: probed_at,
+        };
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
+    /// Marks the node of a mesos agent that went away inactive, returns the
+    /// tasks that were accepted or running on it.
+    pub fn request_agent_lost(&self, slave_id: String) -> Vec<Task> {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::AgentLost {
+            sender: sender,
+            slave_id: slave_id,
+        };
+        self.send_request(msg);
+
+        let result: Vec<Task> = match receiver.recv().unwrap() {
+            StateResponseMsg::AgentLost { tasks } => tasks,
+            _ => vec![],
+        };
+
+        result
+    }
+
+    pub fn send_reconfigure_node(&self,
+                                 name: String,
+                                 ip: String,
+                                 external_ip: String,
+                                 management_ip: String,
+                                 port_id: i64,
+                                 node_type: String) {
+        let (sender, receiver) = channel();
+
+        let node = Node {
+            name: name,
+            ip: ip,
+            external_ip: external_ip,
+            management_ip: management_ip,
+            node_type: node_type,
+            node_function: "none".to_string(),
+            active: false,
+            slave_id: "".to_string(),
+            port_id: port_id,
+            reachable: false,
+            last_seen_at: None,
+            last_probed_at: None,
+            discovered: false,
+            last_offer: None,
+            capacity: vec![],
+            tasks: vec![],
+        };
+
+        let msg = StateRequestMsg::ReconfigureNode {
+            sender: sender,
+            node: node,
+        };
+        self.send_request(msg);
+        receiver.recv().unwrap();
+    }
+
+    pub fn send_remove_node(&self, node_name: String) {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::RemoveNode {
+            sender: sender,
+            node_name: node_name,
+        };
+        self.send_request(msg);
         receiver.recv().unwrap();
     }
 
@@ -358,10 +710,10 @@ impl StateManager {
             sender: sender,
             node_name: node_name,
         };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
 
         let result = match receiver.recv().unwrap() {
-            StateResponseMsg::GetNode { node } => Some(node),
+            StateResponseMsg::GetNode { node } => node,
             _ => None,
         };
 
@@ -372,7 +724,7 @@ impl StateManager {
         let (sender, receiver) = channel();
 
         let msg = StateRequestMsg::GetNodes { sender: sender };
-        self.sender.send(msg).unwrap();
+        self.send_request(msg);
 
         let result: Vec<Node> = match receiver.recv().unwrap() {
             StateResponseMsg::GetNodes { nodes } => nodes,
@@ -381,6 +733,41 @@ impl StateManager {
 
         result
     }
+
+    pub fn request_task_state_counts(&self) -> Vec<(TaskState, usize)> {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::GetTaskStateCounts { sender: sender };
+        self.send_request(msg);
+
+        let result: Vec<(TaskState, usize)> = match receiver.recv().unwrap() {
+            StateResponseMsg::GetTaskStateCounts { counts } => counts,
+            _ => vec![],
+        };
+
+        result
+    }
+
+    pub fn request_event_subscription(&self, filter: EventFilter, last_event_id: Option<u64>) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+
+        let msg = StateRequestMsg::SubscribeEvents {
+            sender: sender,
+            filter: filter,
+            last_event_id: last_event_id,
+        };
+        self.send_request(msg);
+
+        match receiver.recv().unwrap() {
+            StateResponseMsg::SubscribeEvents { events } => events,
+            _ => channel().1,
+        }
+    }
+}
+
+struct StateRequest {
+    queued_at: Instant,
+    msg: StateRequestMsg,
 }
 
 struct State {
@@ -388,6 +775,8 @@ struct State {
     controller_ip: String,
     task_list: TaskList,
     node_list: NodeList,
+    event_list: EventList,
+    job_history: JobHistory,
 }
 
 enum StateRequestMsg {
@@ -402,6 +791,10 @@ enum StateRequestMsg {
         sender: Sender<StateResponseMsg>,
         id_prefix: String,
     },
+    GetTask {
+        sender: Sender<StateResponseMsg>,
+        task_name: String,
+    },
     GetTaskIPByName {
         sender: Sender<StateResponseMsg>,
         name: String,
@@ -416,21 +809,53 @@ enum StateRequestMsg {
         task_name: String,
         node_name: String,
     },
+    UpdateTaskHealth {
+        sender: Sender<StateResponseMsg>,
+        task_name: String,
+        healthy: bool,
+    },
     UpdateTaskInfo {
         sender: Sender<StateResponseMsg>,
         task_name: String,
         id: String,
         ip: String,
+        ports: Vec<PortMapping>,
         slave_id: String,
     },
     StartTask {
         sender: Sender<StateResponseMsg>,
         task: Task,
     },
+    FinishJob {
+        sender: Sender<StateResponseMsg>,
+        task_name: String,
+        result: String,
+        exit_status: Option<i32>,
+        message: String,
+        history_size: usize,
+    },
+    ExpireJobs {
+        sender: Sender<StateResponseMsg>,
+        cutoff: i64,
+    },
+    GetJobRuns {
+        sender: Sender<StateResponseMsg>,
+        job: Option<String>,
+        limit: usize,
+    },
+    RecordPendingReasons {
+        sender: Sender<StateResponseMsg>,
+        node_name: String,
+        rejections: Vec<(String, String, String)>,
+    },
     RemoveTask {
         sender: Sender<StateResponseMsg>,
         task_name: String,
     },
+    FailTask {
+        sender: Sender<StateResponseMsg>,
+        task_name: String,
+    },
     GetRequestedTasks {
         sender: Sender<StateResponseMsg>,
     },
@@ -452,6 +877,36 @@ enum StateRequestMsg {
         node_function: String,
         slave_id: String,
     },
+    RecordOffer {
+        sender: Sender<StateResponseMsg>,
+        offer: NodeOffer,
+    },
+    RegisterNode {
+        sender: Sender<StateResponseMsg>,
+        offer: NodeOffer,
+    },
+    ExpireNodes {
+        sender: Sender<StateResponseMsg>,
+        cutoff: i64,
+    },
+    UpdateNodeReachability {
+        sender: Sender<StateResponseMsg>,
+        node_name: String,
+        reachable: bool,
+        probed_at: i64,
+    },
+    AgentLost {
+        sender: Sender<StateResponseMsg>,
+        slave_id: String,
+    },
+    ReconfigureNode {
+        sender: Sender<StateResponseMsg>,
+        node: Node,
+    },
+    RemoveNode {
+        sender: Sender<StateResponseMsg>,
+        node_name: String,
+    },
     GetNode {
         sender: Sender<StateResponseMsg>,
         node_name: String,
@@ -459,6 +914,14 @@ enum StateRequestMsg {
     GetNodes {
         sender: Sender<StateResponseMsg>,
     },
+    GetTaskStateCounts {
+        sender: Sender<StateResponseMsg>,
+    },
+    SubscribeEvents {
+        sender: Sender<StateResponseMsg>,
+        filter: EventFilter,
+        last_event_id: Option<u64>,
+    },
 }
 
 enum StateResponseMsg {
@@ -469,14 +932,25 @@ enum StateResponseMsg {
     TaskName {
         task_name: String,
     },
+    GetTask {
+        task: Option<Task>,
+    },
     TaskIP {
         task_ip: String,
     },
     UpdateTaskState,
     UpdateTaskInfo,
+    UpdateTaskHealth,
     UpdateTaskNodeName,
     StartTask,
+    FinishJob,
+    ExpireJobs,
+    GetJobRuns {
+        runs: Vec<JobRun>,
+    },
+    RecordPendingReasons,
     RemoveTask,
+    FailTask,
     GetRequestedTasks {
         requested_tasks: Vec<Task>,
     },
@@ -488,30 +962,90 @@ enum StateResponseMsg {
         is_active: bool,
     },
     UpdateNode,
+    RecordOffer,
+    RegisterNode,
+    ExpireNodes,
+    UpdateNodeReachability,
+    AgentLost {
+        tasks: Vec<Task>,
+    },
+    ReconfigureNode,
+    RemoveNode,
     GetNodes {
         nodes: Vec<Node>,
     },
     GetNode {
-        node: Node,
+        node: Option<Node>,
+    },
+    GetTaskStateCounts {
+        counts: Vec<(TaskState, usize)>,
+    },
+    SubscribeEvents {
+        events: Receiver<Event>,
     },
 }
 
+impl StateRequestMsg {
+    fn name(&self) -> &'static str {
+        match *self {
+            StateRequestMsg::Ping { .. } => "Ping",
+            StateRequestMsg::GetTaskState { .. } => "GetTaskState",
+            StateRequestMsg::GetTaskNameById { .. } => "GetTaskNameById",
+            StateRequestMsg::GetTask { .. } => "GetTask",
+            StateRequestMsg::GetTaskIPByName { .. } => "GetTaskIPByName",
+            StateRequestMsg::UpdateTaskState { .. } => "UpdateTaskState",
+            StateRequestMsg::UpdateTaskNodeName { .. } => "UpdateTaskNodeName",
+            StateRequestMsg::UpdateTaskInfo { .. } => "UpdateTaskInfo",
+            StateRequestMsg::UpdateTaskHealth { .. } => "UpdateTaskHealth",
+            StateRequestMsg::StartTask { .. } => "StartTask",
+            StateRequestMsg::FinishJob { .. } => "FinishJob",
+            StateRequestMsg::ExpireJobs { .. } => "ExpireJobs",
+            StateRequestMsg::GetJobRuns { .. } => "GetJobRuns",
+            StateRequestMsg::RecordPendingReasons { .. } => "RecordPendingReasons",
+            StateRequestMsg::RemoveTask { .. } => "RemoveTask",
+            StateRequestMsg::FailTask { .. } => "FailTask",
+            StateRequestMsg::GetRequestedTasks { .. } => "GetRequestedTasks",
+            StateRequestMsg::GetRunningTasks { .. } => "GetRunningTasks",
+            StateRequestMsg::AddNode { .. } => "AddNode",
+            StateRequestMsg::GetIsNodeActive { .. } => "GetIsNodeActive",
+            StateRequestMsg::UpdateNode { .. } => "UpdateNode",
+            StateRequestMsg::RecordOffer { .. } => "RecordOffer",
+            StateRequestMsg::RegisterNode { .. } => "RegisterNode",
+            StateRequestMsg::ExpireNodes { .. } => "ExpireNodes",
+            StateRequestMsg::UpdateNodeReachability { .. } => "UpdateNodeReachability",
+            StateRequestMsg::AgentLost { .. } => "AgentLost",
+            StateRequestMsg::ReconfigureNode { .. } => "ReconfigureNode",
+            StateRequestMsg::RemoveNode { .. } => "RemoveNode",
+            StateRequestMsg::GetNode { .. } => "GetNode",
+            StateRequestMsg::GetNodes { .. } => "GetNodes",
+            StateRequestMsg::GetTaskStateCounts { .. } => "GetTaskStateCounts",
+            StateRequestMsg::SubscribeEvents { .. } => "SubscribeEvents",
+        }
+    }
+}
+
 
 impl StateManager {
-    fn read_config_file(config_file: String) -> Yaml {
-        let mut file = match File::open(config_file) {
-            Ok(file) => file,
-            Err(err) => panic!(err.to_string()),
+    fn send_request(&self, msg: StateRequestMsg) {
+        metrics::record_state_request_queued();
+        let request = StateRequest {
+            queued_at: Instant::now(),
+            msg: msg,
         };
+        self.sender.send(request).unwrap();
+    }
 
-        let mut content = String::new();
-        file.read_to_string(&mut content).unwrap();
-        let config = YamlLoader::load_from_str(&content).unwrap();
-        // Multi document support, doc is a yaml::Yaml
-        config[0].clone()
+    fn read_config_file(config_file: &String, config_overlays: &Vec<String>) -> Config {
+        match load_config(config_file, config_overlays) {
+            Ok(config) => config,
+            Err(errors) => {
+                let _ = writeln!(io::stderr(), "invalid config:\n{}", errors);
+                process::exit(1);
+            }
+        }
     }
 
-    fn start_serving(&self, rx: Receiver<StateRequestMsg>) {
+    fn start_serving(&self, rx: Receiver<StateRequest>) {
         let controller_ip = self.controller_ip.clone();
         thread::Builder::new()
             .name("state-serve".to_string())
@@ -521,11 +1055,17 @@ impl StateManager {
                     controller_ip: controller_ip,
                     task_list: TaskList::new(),
                     node_list: NodeList::new(),
+                    event_list: EventList::new(),
+                    job_history: JobHistory::new(),
                 };
                 state.initialized = true;
 
                 loop {
-                    match rx.recv().unwrap() {
+                    let request = rx.recv().unwrap();
+                    metrics::record_state_request_dequeued();
+                    let request_name = request.msg.name();
+
+                    match request.msg {
                         StateRequestMsg::Ping { sender } => StateManager::ping(sender),
                         StateRequestMsg::GetTaskState { sender, task_name } => {
                             StateManager::get_task_state(sender, &state, task_name)
@@ -533,6 +1073,9 @@ impl StateManager {
                         StateRequestMsg::GetTaskNameById { sender, id_prefix } => {
                             StateManager::get_task_name_by_id(sender, &state, id_prefix)
                         }
+                        StateRequestMsg::GetTask { sender, task_name } => {
+                            StateManager::get_task(sender, &state, task_name)
+                        }
                         StateRequestMsg::GetTaskIPByName { sender, name } => {
                             StateManager::get_task_ip_by_name(sender, &state, name)
                         }
@@ -542,13 +1085,35 @@ impl StateManager {
                         StateRequestMsg::UpdateTaskNodeName { sender, task_name, node_name } => {
                             StateManager::update_task_node_name(sender, &state, task_name, node_name)
                         }
-                        StateRequestMsg::UpdateTaskInfo { sender, task_name, id, ip, slave_id } => {
-                            StateManager::update_task_info(sender, &state, task_name, id, ip, slave_id)
+                        StateRequestMsg::UpdateTaskInfo { sender, task_name, id, ip, ports, slave_id } => {
+                            StateManager::update_task_info(sender, &state, task_name, id, ip, ports, slave_id)
+                        }
+                        StateRequestMsg::UpdateTaskHealth { sender, task_name, healthy } => {
+                            StateManager::update_task_health(sender, &state, task_name, healthy)
                         }
                         StateRequestMsg::StartTask { sender, task } => StateManager::start_task(sender, &state, &task),
+                        StateRequestMsg::FinishJob { sender, task_name, result, exit_status, message, history_size } => {
+                            StateManager::finish_job(sender,
+                                                     &state,
+                                                     task_name,
+                                                     result,
+                                                     exit_status,
+                                                     message,
+                                                     history_size)
+                        }
+                        StateRequestMsg::ExpireJobs { sender, cutoff } => StateManager::expire_jobs(sender, &state, cutoff),
+                        StateRequestMsg::GetJobRuns { sender, job, limit } => {
+                            StateManager::get_job_runs(sender, &state, job, limit)
+                        }
+                        StateRequestMsg::RecordPendingReasons { sender, node_name, rejections } => {
+                            StateManager::record_pending_reasons(sender, &state, node_name, rejections)
+                        }
                         StateRequestMsg::RemoveTask { sender, task_name } => {
                             StateManager::remove_task_by_name(sender, &state, task_name)
                         }
+                        StateRequestMsg::FailTask { sender, task_name } => {
+                            StateManager::fail_task_by_name(sender, &state, task_name)
+                        }
                         StateRequestMsg::GetRequestedTasks { sender } => StateManager::get_requested_tasks(sender, &state),
                         StateRequestMsg::GetRunningTasks { sender } => StateManager::get_running_tasks(sender, &state),
                         StateRequestMsg::AddNode { sender, node } => StateManager::add_node(sender, &state, &node),
@@ -563,25 +1128,48 @@ impl StateManager {
                                                       node_function,
                                                       slave_id)
                         }
+                        StateRequestMsg::RecordOffer { sender, offer } => {
+                            StateManager::record_offer(sender, &state, offer)
+                        }
+                        StateRequestMsg::RegisterNode { sender, offer } => {
+                            StateManager::register_node(sender, &state, offer)
+                        }
+                        StateRequestMsg::ExpireNodes { sender, cutoff } => StateManager::expire_nodes(sender, &state, cutoff),
+                        StateRequestMsg::UpdateNodeReachability { sender, node_name, reachable, probed_at } => {
+                            StateManager::update_node_reachability(sender, &state, node_name, reachable, probed_at)
+                        }
+                        StateRequestMsg::AgentLost { sender, slave_id } => StateManager::agent_lost(sender, &state, slave_id),
+                        StateRequestMsg::ReconfigureNode { sender, node } => {
+                            StateManager::reconfigure_node(sender, &state, &node)
+                        }
+                        StateRequestMsg::RemoveNode { sender, node_name } => {
+                            StateManager::remove_node(sender, &state, node_name)
+                        }
                         StateRequestMsg::GetNode { sender, node_name } => StateManager::get_node(sender, &state, node_name),
                         StateRequestMsg::GetNodes { sender } => StateManager::get_nodes(sender, &state),
+                        StateRequestMsg::GetTaskStateCounts { sender } => StateManager::get_task_state_counts(sender, &state),
+                        StateRequestMsg::SubscribeEvents { sender, filter, last_event_id } => {
+                            StateManager::subscribe_events(sender, &state, filter, last_event_id)
+                        }
                     }
+
+                    metrics::record_state_request_done(request_name, request.queued_at);
                 }
             })
             .unwrap();
     }
 
     fn start_syncing(&self) {
-        let config = self.get_yaml();
-        let wait_time = config["statesync"]["poll_interval_in_seconds"].as_i64().unwrap() as u64;
         let state_manager = self.clone();
 
         thread::Builder::new()
             .name("state-sync".to_string())
             .spawn(move || {
                 loop {
+                    let wait_time = state_manager.get_config().statesync.poll_interval_in_seconds;
                     thread::sleep(Duration::from_secs(wait_time));
-                    println!("syncing ....");
+                    log_debug!("syncing running tasks with controller";
+                               "controller" => state_manager.get_controller_ip());
                     let running_tasks = state_manager.request_list_running_tasks();
                     for task in &running_tasks {
                         register_running_task(&state_manager.get_controller_ip(), &task)
@@ -591,21 +1179,97 @@ impl StateManager {
             .unwrap();
     }
 
+    fn start_config_watch(&self) {
+        let sighup_state_manager = self.clone();
+        on_sighup(move || {
+            let _ = sighup_state_manager.reload_config();
+        });
+
+        let state_manager = self.clone();
+        thread::Builder::new()
+            .name("config-watch".to_string())
+            .spawn(move || {
+                let mut last_modified = config_modified(&state_manager.get_config().files);
+                loop {
+                    let wait_time = state_manager.get_config().reload.watch_interval_in_seconds;
+                    if wait_time == 0 {
+                        // watch turned off, look again in case a reload turns it on
+                        thread::sleep(Duration::from_secs(10));
+                        last_modified = config_modified(&state_manager.get_config().files);
+                        continue;
+                    }
+
+                    thread::sleep(Duration::from_secs(wait_time));
+                    let modified = config_modified(&state_manager.get_config().files);
+                    if modified.is_some() && modified != last_modified {
+                        log_info!("config file changed"; "file" => state_manager.config_file);
+                        last_modified = modified;
+                        let _ = state_manager.reload_config();
+                    }
+                }
+            })
+            .unwrap();
+    }
+
+    fn start_job_retention(&self) {
+        let state_manager = self.clone();
+
+        thread::Builder::new()
+            .name("job-retention".to_string())
+            .spawn(move || {
+                loop {
+                    thread::sleep(Duration::from_secs(60));
+                    let retention = state_manager.get_config().jobs.retention_in_seconds as i64;
+                    let (sender, receiver) = channel();
+                    let msg = StateRequestMsg::ExpireJobs {
+                        sender: sender,
+                        cutoff: UTC::now().timestamp() - retention,
+                    };
+                    state_manager.send_request(msg);
+                    receiver.recv().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
+    fn start_node_expiry(&self) {
+        let state_manager = self.clone();
+
+        thread::Builder::new()
+            .name("node-expiry".to_string())
+            .spawn(move || {
+                loop {
+                    thread::sleep(Duration::from_secs(60));
+                    let expire_after = state_manager.get_config().node_discovery.expire_after_in_seconds as i64;
+                    if expire_after == 0 {
+                        continue;
+                    }
+                    let (sender, receiver) = channel();
+                    let msg = StateRequestMsg::ExpireNodes {
+                        sender: sender,
+                        cutoff: UTC::now().timestamp() - expire_after,
+                    };
+                    state_manager.send_request(msg);
+                    receiver.recv().unwrap();
+                }
+            })
+            .unwrap();
+    }
+
     fn load_node_list(&self) {
-        let config = self.get_yaml();
-        let nodes = config["nodes"].as_vec().unwrap();
-        for node in nodes {
-            self.send_add_node(read_string(node, "name".to_string()),
-                               read_string(node, "ip".to_string()),
-                               read_string(node, "external_ip".to_string()),
-                               read_string(node, "management_ip".to_string()),
-                               read_int(node, "port".to_string(), 0),
-                               read_string(node, "type".to_string()))
+        let config = self.get_config();
+        for node in &config.nodes {
+            self.send_add_node(node.name.clone(),
+                               node.ip.clone(),
+                               node.external_ip.clone(),
+                               node.management_ip.clone(),
+                               node.port,
+                               node.node_type.clone())
         }
     }
 
     fn ping(sender: Sender<StateResponseMsg>) {
-        println!("got ping");
+        log_trace!("got ping");
         let msg = StateResponseMsg::Pong;
         sender.send(msg).unwrap();
     }
@@ -622,6 +1286,12 @@ impl StateManager {
         sender.send(msg).unwrap();
     }
 
+    fn get_task(sender: Sender<StateResponseMsg>, state: &State, task_name: String) {
+        let task = state.task_list.get_task(task_name).ok();
+        let msg = StateResponseMsg::GetTask { task: task };
+        sender.send(msg).unwrap();
+    }
+
     fn get_task_ip_by_name(sender: Sender<StateResponseMsg>, state: &State, name: String) {
         let task_ip = state.task_list.get_task_ip_by_name(name);
         let msg = StateResponseMsg::TaskIP { task_ip: task_ip };
@@ -632,14 +1302,20 @@ impl StateManager {
         state.task_list.set_task_state(task_name.to_string(), task_state.clone());
 
         match task_state {
+            TaskState::Accepted => {
+                let node_name = state.task_list.get_task_node_name(task_name.clone());
+                state.event_list.publish(EventKind::TaskAccepted, task_name.clone(), node_name);
+            }
             TaskState::Running => {
                 let result = state.task_list.get_task(task_name.clone());
                 match result {
-                    Ok(task) => register_running_task(&state.controller_ip.clone(), &task),
+                    Ok(task) => {
+                        metrics::record_task_running(&task_name);
+                        register_running_task(&state.controller_ip.clone(), &task);
+                        state.event_list.publish(EventKind::TaskRunning, task_name.clone(), task.node_name.clone());
+                    }
                     Err(error_msg) => {
-                        println!("error [{:?}] while retrieving {}",
-                                 error_msg,
-                                 task_name.clone())
+                        log_error!("can't retrieve task"; "task" => task_name, "error" => error_msg)
                     }
                 }
 
@@ -663,29 +1339,169 @@ impl StateManager {
                         task_name: String,
                         id: String,
                         ip: String,
+                        ports: Vec<PortMapping>,
                         slave_id: String) {
-        state.task_list.set_task_info(task_name.to_string(), id, ip, slave_id);
+        state.task_list.set_task_info(task_name.to_string(), id, ip, ports, slave_id);
 
         let msg = StateResponseMsg::UpdateTaskInfo;
         sender.send(msg).unwrap();
     }
 
+    fn update_task_health(sender: Sender<StateResponseMsg>, state: &State, task_name: String, healthy: bool) {
+        if state.task_list.set_task_health(task_name.clone(), healthy) {
+            log_info!("task health changed"; "task" => task_name, "healthy" => healthy);
+            let node_name = state.task_list.get_task_node_name(task_name.clone());
+            let kind = match healthy {
+                true => EventKind::TaskHealthy,
+                false => EventKind::TaskUnhealthy,
+            };
+            state.event_list.publish(kind, task_name, node_name);
+        }
+
+        let msg = StateResponseMsg::UpdateTaskHealth;
+        sender.send(msg).unwrap();
+    }
+
     fn start_task(sender: Sender<StateResponseMsg>, state: &State, task: &Task) {
-        println!("start task {}", task.name);
+        log_info!("start task"; "task" => task.name, "node" => task.node_name, "image" => task.image);
 
         state.task_list.add_new_task(&task);
+        metrics::record_task_requested(&task.name);
+        state.event_list.publish(EventKind::TaskRequested, task.name.clone(), task.node_name.clone());
         let msg = StateResponseMsg::StartTask;
         sender.send(msg).unwrap();
     }
 
+    fn finish_job(sender: Sender<StateResponseMsg>,
+                  state: &State,
+                  task_name: String,
+                  result: String,
+                  exit_status: Option<i32>,
+                  message: String,
+                  history_size: usize) {
+        let task = state.task_list
+            .get_task(task_name.clone())
+            .ok()
+            // status updates can arrive more than once, e.g. after a reconcile or
+            // after the run was given up on because its agent got lost
+            .and_then(|task| match task.state {
+                TaskState::Finished | TaskState::Failed | TaskState::Requested => None,
+                _ => Some(task),
+            });
+
+        if let Some(task) = task {
+            let finished_at = UTC::now().timestamp();
+            let succeeded = result == "finished";
+            let retry = !succeeded && task.attempt < task.max_retries;
+
+            log_info!("job completed";
+                      "task" => task_name,
+                      "result" => result,
+                      "attempt" => task.attempt,
+                      "exit_status" => exit_status.map(|status| status.to_string()).unwrap_or("".to_string()),
+                      "retry" => retry);
+
+            let run = JobRun {
+                job: task_name.clone(),
+                attempt: task.attempt,
+                node: task.node_name.clone(),
+                result: result.clone(),
+                exit_status: exit_status,
+                message: message,
+                started_at: task.started_at,
+                finished_at: finished_at,
+                duration_in_seconds: match task.started_at {
+                    0 => 0,
+                    started_at => finished_at - started_at,
+                },
+                retried: retry,
+            };
+            state.job_history.record(run, history_size);
+            metrics::record_job_run(&result);
+
+            if succeeded {
+                state.task_list.finish_job(task_name.clone(), TaskState::Finished, exit_status, finished_at);
+                state.event_list.publish(EventKind::JobFinished, task_name, task.node_name);
+            } else {
+                state.event_list.publish(EventKind::TaskFailed, task_name.clone(), task.node_name);
+                if retry {
+                    state.task_list.retry_job(task_name.clone());
+                    metrics::record_task_requested(&task_name);
+                    state.event_list.publish(EventKind::TaskRequested, task_name, "".to_string());
+                } else {
+                    state.task_list.finish_job(task_name, TaskState::Failed, exit_status, finished_at);
+                }
+            }
+        }
+
+        let msg = StateResponseMsg::FinishJob;
+        sender.send(msg).unwrap();
+    }
+
+    fn expire_jobs(sender: Sender<StateResponseMsg>, state: &State, cutoff: i64) {
+        for task_name in state.task_list.remove_expired_jobs(cutoff) {
+            log_debug!("job expired"; "task" => task_name);
+            metrics::record_task_forgotten(&task_name);
+        }
+
+        let msg = StateResponseMsg::ExpireJobs;
+        sender.send(msg).unwrap();
+    }
+
+    fn get_job_runs(sender: Sender<StateResponseMsg>, state: &State, job: Option<String>, limit: usize) {
+        let runs = state.job_history.get_runs(job, limit);
+        let msg = StateResponseMsg::GetJobRuns { runs: runs };
+        sender.send(msg).unwrap();
+    }
+
+    fn record_pending_reasons(sender: Sender<StateResponseMsg>,
+                              state: &State,
+                              node_name: String,
+                              rejections: Vec<(String, String, String)>) {
+        let recorded_at = UTC::now().timestamp();
+        for (task_name, rejected_by, detail) in rejections {
+            let reason = PendingReason {
+                node: node_name.clone(),
+                rejected_by: rejected_by,
+                detail: detail,
+                recorded_at: recorded_at,
+            };
+            state.task_list.record_pending_reason(task_name, reason);
+        }
+
+        let msg = StateResponseMsg::RecordPendingReasons;
+        sender.send(msg).unwrap();
+    }
+
     fn remove_task_by_name(sender: Sender<StateResponseMsg>, state: &State, task_name: String) {
-        println!("remove task {}", task_name);
+        log_info!("remove task"; "task" => task_name);
 
+        let node_name = state.task_list.get_task_node_name(task_name.clone());
         state.task_list.remove_task_by_name(task_name.to_string());
+        metrics::record_task_forgotten(&task_name);
+        state.event_list.publish(EventKind::TaskKilled, task_name, node_name);
         let msg = StateResponseMsg::RemoveTask;
         sender.send(msg).unwrap();
     }
 
+    fn fail_task_by_name(sender: Sender<StateResponseMsg>, state: &State, task_name: String) {
+        // a lost agent is followed by a TASK_LOST for each of its tasks, only the first one counts
+        match state.task_list.get_task(task_name.clone()) {
+            Ok(ref task) if task.state != TaskState::NotRunning => {
+                log_warn!("task failed"; "task" => task_name, "node" => task.node_name);
+
+                // kept so /services/{name} shows it, the health checker or the next group start launches it again
+                state.task_list.set_task_state(task_name.clone(), TaskState::NotRunning);
+                metrics::record_task_forgotten(&task_name);
+                state.event_list.publish(EventKind::TaskFailed, task_name, task.node_name.clone());
+            }
+            _ => {}
+        }
+
+        let msg = StateResponseMsg::FailTask;
+        sender.send(msg).unwrap();
+    }
+
     fn get_requested_tasks(sender: Sender<StateResponseMsg>, state: &State) {
         let result: Vec<Task> = state.task_list.get_tasks_with_state(TaskState::Requested);
         let msg = StateResponseMsg::GetRequestedTasks { requested_tasks: result };
@@ -698,27 +1514,9 @@ impl StateManager {
         sender.send(msg).unwrap();
     }
 
-    fn resolve_arguments(&self, argument: &String) -> String {
-        if argument.len() == 0 {
-            return argument.clone();
-        }
-
-        let mut resolved_arguments = str::replace(&argument, "$MASTER_IP", &self.master_ip);
-
-        resolved_arguments = match resolved_arguments.contains("$IP_DNS_SL1") {
-            true => {
-                resolved_arguments.replace("$IP_DNS_SL1",
-                             &self.request_task_ip_by_name("dns-sl1".to_string()))
-                    .clone()
-            }
-            false => resolved_arguments.clone(),
-        };
-        println!("arguments: {}", resolved_arguments);
-        resolved_arguments.clone()
-    }
-
     fn add_node(sender: Sender<StateResponseMsg>, state: &State, node: &Node) {
         state.node_list.add_new_node(&node);
+        state.event_list.publish(EventKind::NodeRegistered, "".to_string(), node.name.clone());
         let msg = StateResponseMsg::AddNode;
         sender.send(msg).unwrap();
     }
@@ -735,23 +1533,227 @@ impl StateManager {
                    node_type: String,
                    node_function: String,
                    slave_id: String) {
-        state.node_list.update_node(node_name.clone(),
-                                    node_type.clone(),
-                                    node_function.clone(),
-                                    slave_id.clone());
+        let updated = state.node_list.update_node(node_name.clone(),
+                                                  node_type.clone(),
+                                                  node_function.clone(),
+                                                  slave_id.clone());
+        if updated {
+            state.event_list.publish(EventKind::NodeUpdated, "".to_string(), node_name);
+        }
         let msg = StateResponseMsg::UpdateNode;
         sender.send(msg).unwrap();
     }
 
+    fn record_offer(sender: Sender<StateResponseMsg>, state: &State, offer: NodeOffer) {
+        // the offer leaves out what our tasks on the node already hold
+        let tasks = StateManager::tasks_on_node(state, &offer.node_name, &offer.slave_id);
+        let capacity = vec![ResourceCapacity::new("cpus", offer.cpus, tasks.iter().fold(0.0, |sum, task| sum + task.cpu)),
+                            ResourceCapacity::new("mem", offer.mem, tasks.iter().fold(0.0, |sum, task| sum + task.memory))];
+        state.node_list.record_offer(offer.node_name.clone(), offer, capacity);
+        let msg = StateResponseMsg::RecordOffer;
+        sender.send(msg).unwrap();
+    }
+
+    fn register_node(sender: Sender<StateResponseMsg>, state: &State, offer: NodeOffer) {
+        if state.node_list.get_node(offer.node_name.clone()).is_err() {
+            let attribute = |name: &str| offer.attributes.get(name).cloned().unwrap_or("".to_string());
+            let mut ip = attribute("machine-ip");
+            if ip.is_empty() {
+                ip = offer.hostname.clone();
+            }
+
+            let node = Node {
+                name: offer.node_name.clone(),
+                ip: ip,
+                external_ip: attribute("machine-external-ip"),
+                management_ip: attribute("machine-management-ip"),
+                node_type: offer.node_type.clone(),
+                node_function: offer.node_function.clone(),
+                active: false,
+                slave_id: offer.slave_id.clone(),
+                port_id: 0,
+                reachable: false,
+                last_seen_at: None,
+                last_probed_at: None,
+                discovered: true,
+                last_offer: None,
+                capacity: vec![],
+                tasks: vec![],
+            };
+            state.node_list.add_new_node(&node);
+            state.event_list.publish(EventKind::NodeRegistered, "".to_string(), node.name.clone());
+        }
+        let msg = StateResponseMsg::RegisterNode;
+        sender.send(msg).unwrap();
+    }
+
+    /// Nodes that didn't offer since `cutoff` are removed if they were discovered
+    /// and marked inactive if they are listed in the config. A node that runs
+    /// tasks is kept, a fully used agent has nothing left to offer.
+    fn expire_nodes(sender: Sender<StateResponseMsg>, state: &State, cutoff: i64) {
+        for node in state.node_list.get_nodes() {
+            let last_offer_at = match node.last_offer {
+                Some(ref offer) => offer.received_at,
+                None => continue,
+            };
+            if last_offer_at >= cutoff || !StateManager::tasks_on_node(state, &node.name, &node.slave_id).is_empty() {
+                continue;
+            }
+
+            let expired = match node.discovered {
+                true => state.node_list.remove_node(node.name.clone()),
+                false => state.node_list.deactivate_node(node.name.clone()),
+            };
+            if expired {
+                log_info!("node expired"; "node" => node.name, "last_offer_at" => last_offer_at);
+                state.event_list.publish(EventKind::NodeExpired, "".to_string(), node.name.clone());
+            }
+        }
+
+        let msg = StateResponseMsg::ExpireNodes;
+        sender.send(msg).unwrap();
+    }
+
+    fn update_node_reachability(sender: Sender<StateResponseMsg>,
+                                state: &State,
+                                node_name: String,
+                                reachable: bool,
+                                probed_at: i64) {
+        if state.node_list.set_node_reachable(node_name.clone(), reachable, probed_at) {
+            let kind = match reachable {
+                true => EventKind::NodeReachable,
+                false => EventKind::NodeUnreachable,
+            };
+            state.event_list.publish(kind, "".to_string(), node_name);
+        }
+        let msg = StateResponseMsg::UpdateNodeReachability;
+        sender.send(msg).unwrap();
+    }
+
+    fn agent_lost(sender: Sender<StateResponseMsg>, state: &State, slave_id: String) {
+        let node = state.node_list.deactivate_agent(slave_id.clone()).unwrap_or("".to_string());
+        let tasks = StateManager::tasks_on_node(state, &node, &slave_id);
+
+        log_warn!("agent lost"; "slave_id" => slave_id, "node" => node, "tasks" => tasks.len());
+        if !node.is_empty() {
+            state.event_list.publish(EventKind::NodeLost, "".to_string(), node);
+        }
+
+        let msg = StateResponseMsg::AgentLost { tasks: tasks };
+        sender.send(msg).unwrap();
+    }
+
+    fn reconfigure_node(sender: Sender<StateResponseMsg>, state: &State, node: &Node) {
+        if state.node_list.reconfigure_node(&node) {
+            state.event_list.publish(EventKind::NodeUpdated, "".to_string(), node.name.clone());
+        }
+        let msg = StateResponseMsg::ReconfigureNode;
+        sender.send(msg).unwrap();
+    }
+
+    fn remove_node(sender: Sender<StateResponseMsg>, state: &State, node_name: String) {
+        if state.node_list.remove_node(node_name.clone()) {
+            state.event_list.publish(EventKind::NodeRemoved, "".to_string(), node_name);
+        }
+        let msg = StateResponseMsg::RemoveNode;
+        sender.send(msg).unwrap();
+    }
+
     fn get_node(sender: Sender<StateResponseMsg>, state: &State, node_name: String) {
-        let result: Node = state.node_list.get_node(node_name.clone()).unwrap();
+        let result = state.node_list
+            .get_node(node_name.clone())
+            .ok()
+            .map(|node| StateManager::with_usage(state, node));
         let msg = StateResponseMsg::GetNode { node: result };
         sender.send(msg).unwrap();
     }
 
     fn get_nodes(sender: Sender<StateResponseMsg>, state: &State) {
-        let result: Vec<Node> = state.node_list.get_nodes();
+        let result: Vec<Node> = state.node_list
+            .get_nodes()
+            .into_iter()
+            .map(|node| StateManager::with_usage(state, node))
+            .collect();
         let msg = StateResponseMsg::GetNodes { nodes: result };
         sender.send(msg).unwrap();
     }
+
+    /// The accepted and running tasks on a node, found by the agent they
+    /// were launched on or by the node name they were placed with.
+    fn tasks_on_node(state: &State, node_name: &str, slave_id: &str) -> Vec<Task> {
+        let mut tasks = vec![];
+        for task_state in vec![TaskState::Accepted, TaskState::Running] {
+            for task in state.task_list.get_tasks_with_state(task_state) {
+                let on_agent = !task.slave_id.is_empty() && task.slave_id == slave_id;
+                let on_node = !task.node_name.is_empty() && task.node_name == node_name;
+                if on_agent || on_node {
+                    tasks.push(task);
+                }
+            }
+        }
+        tasks
+    }
+
+    // the totals are from the last offer, what is allocated and free changes
+    // with every task that starts or ends on the node
+    fn with_usage(state: &State, mut node: Node) -> Node {
+        let tasks = StateManager::tasks_on_node(state, &node.name, &node.slave_id);
+        for capacity in node.capacity.iter_mut() {
+            let allocated = match &*capacity.resource {
+                "cpus" => tasks.iter().fold(0.0, |sum, task| sum + task.cpu),
+                _ => tasks.iter().fold(0.0, |sum, task| sum + task.memory),
+            };
+            capacity.set_allocated(allocated);
+        }
+        node.tasks = tasks.into_iter().map(|task| task.name).collect();
+        node
+    }
+
+    fn get_task_state_counts(sender: Sender<StateResponseMsg>, state: &State) {
+        let counts = state.task_list.get_task_state_counts();
+        let msg = StateResponseMsg::GetTaskStateCounts { counts: counts };
+        sender.send(msg).unwrap();
+    }
+
+    fn subscribe_events(sender: Sender<StateResponseMsg>,
+                        state: &State,
+                        filter: EventFilter,
+                        last_event_id: Option<u64>) {
+        let events = state.event_list.subscribe(filter, last_event_id);
+        let msg = StateResponseMsg::SubscribeEvents { events: events };
+        sender.send(msg).unwrap();
+    }
+}
+
+// the latest change to any of the files the config was read from
+fn config_modified(config_files: &Vec<String>) -> Option<SystemTime> {
+    config_files.iter()
+        .filter_map(|config_file| fs::metadata(config_file).and_then(|metadata| metadata.modified()).ok())
+        .max()
+}
+
+fn find_host_port(task: &Task, container_port: Option<u16>) -> Option<String> {
+    match container_port {
+        None => task.ports.first().map(|port| port.host_port.to_string()),
+        Some(container_port) => {
+            match task.ports.iter().find(|port| port.container_port == container_port) {
+                Some(port) => Some(port.host_port.to_string()),
+                // nothing gets published with host networking, the ports are the same
+                None if task.network_type == "host" => Some(container_port.to_string()),
+                None => None,
+            }
+        }
+    }
+}
+
+fn node_attribute(node: &Node, attribute: &str) -> Option<String> {
+    match attribute {
+        "name" => Some(node.name.clone()),
+        "ip" => Some(node.ip.clone()),
+        "external_ip" => Some(node.external_ip.clone()),
+        "management_ip" => Some(node.management_ip.clone()),
+        "type" => Some(node.node_type.clone()),
+        "function" => Some(node.node_function.clone()),
+        _ => None,
+    }
 }
@@ -23,6 +23,7 @@
 use super::state::TaskState;
 use std::collections::HashMap;
 use std::sync::Mutex;
+use chrono::UTC;
 
 pub struct TaskList {
     task_list: Mutex<HashMap<String, Task>>,
@@ -38,6 +39,8 @@ pub struct Task {
     pub node_type: String,
     pub node_function: String,
     pub dependent_service: String,
+    pub dependencies: Vec<Dependency>,
+    pub health_check: Option<HealthCheck>,
     pub arguments: String,
     pub parameters: String,
     pub memory: f64,
@@ -48,11 +51,31 @@ pub struct Task {
     pub is_metered: bool,
     pub is_system_service: bool,
     pub is_job: bool,
+    pub max_retries: i64,
+    /// 0 for the first run of a job, counts up with every retry
+    pub attempt: i64,
     pub network_type: String,
+    pub constraints: Vec<String>,
+    pub instance: i64,
     pub ip: String,
+    pub ports: Vec<PortMapping>,
     pub slave_id: String,
     pub state: TaskState,
+    pub healthy: Option<bool>,
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub exit_status: Option<i32>,
     pub last_update: i64,
+    pub pending_reasons: Vec<PendingReason>,
+}
+
+/// Why the latest offer from a node didn't get a Requested task placed.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct PendingReason {
+    pub node: String,
+    pub rejected_by: String,
+    pub detail: String,
+    pub recorded_at: i64,
 }
 
 #[derive(Clone, Debug, RustcEncodable)]
@@ -62,6 +85,40 @@ pub struct Volume {
     pub read_only_mode: bool,
 }
 
+/// A service that has to reach `condition` before a task depending on it gets placed.
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+pub struct Dependency {
+    pub service: String,
+    pub condition: DependencyCondition,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable)]
+pub enum DependencyCondition {
+    /// the task is Running
+    Running,
+    /// Running and its health check passes, needs a service with a health check
+    Healthy,
+    /// Running and, if it has a health check, passing it
+    Ready,
+}
+
+/// A command mesos runs inside the container to find out if the task is healthy.
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+pub struct HealthCheck {
+    pub command: String,
+    pub interval_in_seconds: i64,
+    pub timeout_in_seconds: i64,
+    pub grace_period_in_seconds: i64,
+    pub consecutive_failures: i64,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct PortMapping {
+    pub container_port: u16,
+    pub protocol: String,
+    pub host_port: u16,
+}
+
 #[derive(Clone, Hash, Eq, PartialEq, Debug, RustcEncodable)]
 pub enum SLA {
     None,
@@ -70,6 +127,37 @@ pub enum SLA {
 }
 
 
+impl DependencyCondition {
+    pub fn parse(name: &str) -> Option<DependencyCondition> {
+        match name {
+            "running" => Some(DependencyCondition::Running),
+            "healthy" => Some(DependencyCondition::Healthy),
+            "ready" => Some(DependencyCondition::Ready),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DependencyCondition::Running => "running",
+            DependencyCondition::Healthy => "healthy",
+            DependencyCondition::Ready => "ready",
+        }
+    }
+
+    pub fn is_met_by(&self, task: &Task) -> bool {
+        if task.state != TaskState::Running {
+            return false;
+        }
+
+        match *self {
+            DependencyCondition::Running => true,
+            DependencyCondition::Healthy => task.healthy == Some(true),
+            DependencyCondition::Ready => task.health_check.is_none() || task.healthy == Some(true),
+        }
+    }
+}
+
 impl TaskList {
     pub fn new() -> TaskList {
         TaskList { task_list: Mutex::new(HashMap::new()) }
@@ -86,6 +174,12 @@ impl TaskList {
     pub fn set_task_state(&self, task_name: String, task_state: TaskState) {
         match self.task_list.lock().unwrap().get_mut(&task_name) {
             Some(task) => {
+                if task_state != TaskState::Requested {
+                    task.pending_reasons.clear();
+                }
+                if task_state == TaskState::Running && task.state != TaskState::Running {
+                    task.started_at = UTC::now().timestamp();
+                }
                 task.state = task_state.clone();
             }
             None => {}
@@ -101,7 +195,86 @@ impl TaskList {
         }
     }
 
-    pub fn set_task_info(&self, task_name: String, task_id: String, task_ip: String, slave_id: String) {
+    /// Marks the job Finished or Failed, it stays in the list until it expires.
+    pub fn finish_job(&self, task_name: String, task_state: TaskState, exit_status: Option<i32>, finished_at: i64) {
+        match self.task_list.lock().unwrap().get_mut(&task_name) {
+            Some(task) => {
+                task.state = task_state;
+                task.exit_status = exit_status;
+                task.finished_at = finished_at;
+            }
+            None => {}
+        }
+    }
+
+    /// Requests the job again for its next attempt.
+    pub fn retry_job(&self, task_name: String) {
+        match self.task_list.lock().unwrap().get_mut(&task_name) {
+            Some(task) => {
+                task.attempt += 1;
+                task.state = TaskState::Requested;
+                task.id = "".to_string();
+                task.ip = "".to_string();
+                task.ports = vec![];
+                task.slave_id = "".to_string();
+                task.healthy = None;
+                task.started_at = 0;
+                task.last_update = UTC::now().timestamp();
+            }
+            None => {}
+        }
+    }
+
+    /// Removes the jobs that finished or failed before `cutoff`, returns their names.
+    pub fn remove_expired_jobs(&self, cutoff: i64) -> Vec<String> {
+        let mut map = self.task_list.lock().unwrap();
+        let expired: Vec<String> = map.values()
+            .filter(|task| task.is_job && (task.state == TaskState::Finished || task.state == TaskState::Failed))
+            .filter(|task| task.finished_at <= cutoff)
+            .map(|task| task.name.clone())
+            .collect();
+
+        for task_name in &expired {
+            map.remove(task_name);
+        }
+
+        expired
+    }
+
+    /// Returns true if the health changed.
+    pub fn set_task_health(&self, task_name: String, healthy: bool) -> bool {
+        match self.task_list.lock().unwrap().get_mut(&task_name) {
+            Some(task) => {
+                let changed = task.healthy != Some(healthy);
+                task.healthy = Some(healthy);
+                changed
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces the reason recorded for the node, only tasks still waiting for
+    /// an offer keep them.
+    pub fn record_pending_reason(&self, task_name: String, reason: PendingReason) {
+        match self.task_list.lock().unwrap().get_mut(&task_name) {
+            Some(task) => {
+                if task.state != TaskState::Requested {
+                    return;
+                }
+                task.pending_reasons.retain(|existing| existing.node != reason.node);
+                task.pending_reasons.push(reason);
+                task.pending_reasons.sort_by(|a, b| a.node.cmp(&b.node));
+            }
+            None => {}
+        }
+    }
+
+    pub fn set_task_info(&self,
+                         task_name: String,
+                         task_id: String,
+                         task_ip: String,
+                         ports: Vec<PortMapping>,
+                         slave_id: String) {
         match self.task_list.lock().unwrap().get_mut(&task_name) {
             Some(task) => {
                 if task_id.len() > 0 {
@@ -113,7 +286,10 @@ impl TaskList {
                 if slave_id.len() > 0 {
                     task.slave_id = slave_id.clone();
                 }
-                println!("task changed {:?}", task);
+                if ports.len() > 0 {
+                    task.ports = ports;
+                }
+                log_debug!("task changed"; "task" => task.name, "id" => task.id, "ip" => task.ip, "slave_id" => task.slave_id);
             }
             None => {}
         }
@@ -140,6 +316,13 @@ impl TaskList {
         result.clone()
     }
 
+    pub fn get_task_node_name(&self, task_name: String) -> String {
+        match self.task_list.lock().unwrap().get(&task_name) {
+            Some(task) => task.node_name.clone(),
+            None => "".to_string(),
+        }
+    }
+
     pub fn get_task_ip_by_name(&self, name: String) -> String {
         match self.task_list.lock().unwrap().get(&name) {
             Some(task) => task.ip.clone(),
@@ -164,4 +347,19 @@ impl TaskList {
 
         result
     }
+
+    pub fn get_task_state_counts(&self) -> Vec<(TaskState, usize)> {
+        let mut counts: Vec<(TaskState, usize)> = TaskState::all().into_iter().map(|task_state| (task_state, 0)).collect();
+
+        let map = self.task_list.lock().unwrap();
+        for value in map.values() {
+            for &mut (ref task_state, ref mut count) in counts.iter_mut() {
+                if *task_state == value.state {
+                    *count += 1;
+                }
+            }
+        }
+
+        counts
+    }
 }
@@ -20,8 +20,14 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use std::collections::BTreeMap;
 use yaml_rust::yaml::Yaml;
-use state::{SLA, Volume};
+use state::{Dependency, DependencyCondition, HealthCheck, SLA, Volume};
+use config::{ConfigReader, Templates};
+use super::constraint::Constraint;
+use super::cron::{ConcurrencyPolicy, CronExpression, MissedRuns, Schedule};
+use super::timezone::TimeZone;
+use super::template::check_template;
 
 #[derive(Clone, Debug)]
 pub struct Task {
@@ -32,6 +38,8 @@ pub struct Task {
     pub node_function: String,
     pub number_of_instances: i64,
     pub dependent_service: String,
+    pub dependencies: Vec<Dependency>,
+    pub health_check: Option<HealthCheck>,
     pub arguments: String,
     pub parameters: String,
     pub memory: f64,
@@ -41,93 +49,340 @@ pub struct Task {
     pub sla: SLA,
     pub is_metered: bool,
     pub is_job: bool,
+    pub max_retries: i64,
+    pub schedule: Option<Schedule>,
     pub network_type: String,
+    pub constraints: Vec<String>,
 }
 
-pub fn read_task(service: &Yaml) -> Task {
+/// Reads one service definition after applying the template it extends,
+/// problems are recorded in `reader` under `path` and defaults are used so the
+/// remaining services still get checked.
+pub fn read_task(reader: &mut ConfigReader, templates: &Templates, service: &Yaml, path: &str) -> Task {
+    let service = &templates.apply(reader, service, path);
+
+    let dependent_service = reader.string(service, path, "dependent_service");
+    let mut dependencies = read_dependencies(reader, service, path);
+    if !dependent_service.is_empty() {
+        // the single dependency of older configs
+        dependencies.insert(0,
+                            Dependency {
+                                service: dependent_service.clone(),
+                                condition: DependencyCondition::Running,
+                            });
+    }
+
     let new_task = Task {
-        name: service["name"].as_str().unwrap().to_string(),
-        image: service["image_name"].as_str().unwrap().to_string(),
-        node_name: read_string(service, "node_name".to_string()),
-        node_type: read_string(service, "node_type".to_string()),
-        node_function: read_string(service, "node_function".to_string()),
-        number_of_instances: read_int(service, "number_of_instances".to_string(), 1),
-        dependent_service: read_string(service, "dependent_service".to_string()),
-        arguments: read_string(service, "arguments".to_string()),
-        parameters: read_string(service, "parameters".to_string()),
-        memory: read_float(service, "memory".to_string(), super::DEFAULT_MEMORY),
-        cpu: read_float(service, "cpu".to_string(), super::DEFAULT_CPU),
-        volumes: read_volumes_for_service(service),
-        privileged: read_bool(service, "privileged".to_string()),
-        sla: read_sla(service),
-        is_metered: read_bool(service, "is_metered".to_string()),
-        is_job: read_bool(service, "is_job".to_string()),
-        network_type: service["network_type"].as_str().unwrap().to_string(),
+        name: reader.required_string(service, path, "name"),
+        image: reader.required_string(service, path, "image_name"),
+        node_name: reader.string(service, path, "node_name"),
+        node_type: reader.string(service, path, "node_type"),
+        node_function: reader.string(service, path, "node_function"),
+        number_of_instances: reader.positive_int(service, path, "number_of_instances", Some(1)),
+        dependent_service: dependent_service,
+        dependencies: dependencies,
+        health_check: read_health_check(reader, service, path),
+        arguments: reader.string(service, path, "arguments"),
+        parameters: reader.string(service, path, "parameters"),
+        memory: reader.float(service, path, "memory", super::DEFAULT_MEMORY),
+        cpu: reader.float(service, path, "cpu", super::DEFAULT_CPU),
+        volumes: read_volumes_for_service(reader, service, path),
+        privileged: reader.bool(service, path, "privileged", false),
+        sla: read_sla(reader, service, path),
+        is_metered: reader.bool(service, path, "is_metered", false),
+        is_job: reader.bool(service, path, "is_job", false),
+        max_retries: reader.int(service, path, "max_retries", 0),
+        schedule: read_schedule(reader, service, path),
+        network_type: reader.required_string(service, path, "network_type"),
+        constraints: read_constraints(reader, service, path),
     };
+
+    if new_task.max_retries < 0 {
+        reader.error(&ConfigReader::child_path(path, "max_retries"), "must not be negative".to_string());
+    } else if new_task.max_retries > 0 && !new_task.is_job {
+        reader.error(&ConfigReader::child_path(path, "max_retries"),
+                     "only jobs are retried, set is_job: true".to_string());
+    }
+
+    if !service["schedule"].is_badvalue() && !service["schedule"].is_null() {
+        let schedule_path = ConfigReader::child_path(path, "schedule");
+        if !new_task.is_job {
+            reader.error(&schedule_path, "only jobs can be scheduled, set is_job: true".to_string());
+        }
+        // every run gets its own name, see cron::run_name
+        if new_task.number_of_instances != 1 || new_task.sla != SLA::None {
+            reader.error(&schedule_path,
+                         "scheduled jobs run a single instance, number_of_instances and sla can't be set".to_string());
+        }
+    }
+
+    for &(key, template) in &[("arguments", &new_task.arguments), ("parameters", &new_task.parameters)] {
+        for error_msg in check_template(template) {
+            reader.error(&ConfigReader::child_path(path, key), error_msg);
+        }
+    }
+
     new_task.clone()
 }
 
-pub fn read_string(element: &Yaml, key: String) -> String {
-    match element[key.as_ref()].is_badvalue() {
-        true => "".to_string(),
-        false => element[key.as_ref()].as_str().unwrap().to_string(),
+fn read_volumes_for_service(reader: &mut ConfigReader, service: &Yaml, path: &str) -> Vec<Volume> {
+    let mut result = Vec::new();
+    let volumes_path = ConfigReader::child_path(path, "volumes");
+
+    for (idx, volume) in reader.list(service, path, "volumes", false).iter().enumerate() {
+        let volume_path = ConfigReader::item_path(&volumes_path, idx);
+        if !reader.expect_hash(volume, &volume_path) {
+            continue;
+        }
+
+        let definition = Volume {
+            host_path: reader.required_string(volume, &volume_path, "host_path"),
+            container_path: reader.required_string(volume, &volume_path, "container_path"),
+            read_only_mode: reader.required_bool(volume, &volume_path, "read_only_mode"),
+        };
+        result.push(definition);
     }
+
+    result.clone()
 }
 
-pub fn read_bool(element: &Yaml, key: String) -> bool {
-    match element[key.as_ref()].is_badvalue() {
-        true => false,
-        false => element[key.as_ref()].as_bool().unwrap(),
+// each entry is a service name or `service:` with a `condition:`
+fn read_dependencies(reader: &mut ConfigReader, service: &Yaml, path: &str) -> Vec<Dependency> {
+    let mut result = vec![];
+    let dependencies_path = ConfigReader::child_path(path, "dependencies");
+
+    for (idx, dependency) in reader.list(service, path, "dependencies", false).iter().enumerate() {
+        let dependency_path = ConfigReader::item_path(&dependencies_path, idx);
+        let dependency = match *dependency {
+            Yaml::String(ref name) => {
+                Dependency {
+                    service: name.clone(),
+                    condition: DependencyCondition::Running,
+                }
+            }
+            Yaml::Hash(_) => {
+                let condition = reader.string_or(dependency, &dependency_path, "condition", "running");
+                Dependency {
+                    service: reader.required_string(dependency, &dependency_path, "service"),
+                    condition: match DependencyCondition::parse(&condition) {
+                        Some(condition) => condition,
+                        None => {
+                            reader.error(&ConfigReader::child_path(&dependency_path, "condition"),
+                                         format!("unknown condition {}, expected running, healthy or ready",
+                                                 condition));
+                            DependencyCondition::Running
+                        }
+                    },
+                }
+            }
+            _ => {
+                reader.error(&dependency_path,
+                             "must be a service name or a mapping with service and condition".to_string());
+                continue;
+            }
+        };
+
+        if result.iter().any(|existing: &Dependency| existing.service == dependency.service) {
+            reader.error(&dependency_path,
+                         format!("{} is listed more than once", dependency.service));
+        }
+        result.push(dependency);
     }
+
+    result
 }
 
-pub fn read_float(element: &Yaml, key: String, default: f64) -> f64 {
-    match element[key.as_ref()].is_badvalue() {
-        true => default,
-        false => element[key.as_ref()].as_f64().unwrap(),
+fn read_health_check(reader: &mut ConfigReader, service: &Yaml, path: &str) -> Option<HealthCheck> {
+    let health_check = &service["health_check"];
+    let health_check_path = ConfigReader::child_path(path, "health_check");
+    // print-config writes `~` for a service without one
+    if health_check.is_null() || !reader.expect_hash(health_check, &health_check_path) {
+        return None;
+    }
+
+    let grace_period_in_seconds = reader.int(health_check, &health_check_path, "grace_period_in_seconds", 10);
+    if grace_period_in_seconds < 0 {
+        reader.error(&ConfigReader::child_path(&health_check_path, "grace_period_in_seconds"),
+                     "must not be negative".to_string());
     }
+
+    Some(HealthCheck {
+        command: reader.required_string(health_check, &health_check_path, "command"),
+        interval_in_seconds: reader.positive_int(health_check, &health_check_path, "interval_in_seconds", Some(10)),
+        timeout_in_seconds: reader.positive_int(health_check, &health_check_path, "timeout_in_seconds", Some(20)),
+        grace_period_in_seconds: grace_period_in_seconds,
+        consecutive_failures: reader.positive_int(health_check, &health_check_path, "consecutive_failures", Some(3)),
+    })
 }
 
-pub fn read_int(element: &Yaml, key: String, default: i64) -> i64 {
-    match element[key.as_ref()].is_badvalue() {
-        true => default,
-        false => element[key.as_ref()].as_i64().unwrap(),
+fn read_schedule(reader: &mut ConfigReader, service: &Yaml, path: &str) -> Option<Schedule> {
+    let schedule = &service["schedule"];
+    let schedule_path = ConfigReader::child_path(path, "schedule");
+    // print-config writes `~` for a service without one
+    if schedule.is_null() || !reader.expect_hash(schedule, &schedule_path) {
+        return None;
     }
+
+    let cron = reader.required_string(schedule, &schedule_path, "cron");
+    let timezone = reader.string_or(schedule, &schedule_path, "timezone", "UTC");
+
+    let concurrency_policy = reader.string_or(schedule, &schedule_path, "concurrency_policy", "forbid");
+    let concurrency_policy = match ConcurrencyPolicy::parse(&concurrency_policy) {
+        Some(concurrency_policy) => concurrency_policy,
+        None => {
+            reader.error(&ConfigReader::child_path(&schedule_path, "concurrency_policy"),
+                         format!("unknown policy {}, expected allow, forbid or replace", concurrency_policy));
+            ConcurrencyPolicy::Forbid
+        }
+    };
+
+    let missed_runs = reader.string_or(schedule, &schedule_path, "missed_runs", "run_once");
+    let missed_runs = match MissedRuns::parse(&missed_runs) {
+        Some(missed_runs) => missed_runs,
+        None => {
+            reader.error(&ConfigReader::child_path(&schedule_path, "missed_runs"),
+                         format!("unknown policy {}, expected skip or run_once", missed_runs));
+            MissedRuns::RunOnce
+        }
+    };
+
+    let starting_deadline_in_seconds = reader.int(schedule, &schedule_path, "starting_deadline_in_seconds", 0);
+    if starting_deadline_in_seconds < 0 {
+        reader.error(&ConfigReader::child_path(&schedule_path, "starting_deadline_in_seconds"),
+                     "must not be negative".to_string());
+    }
+
+    let expression = CronExpression::parse(&cron);
+    if let Err(ref error_msg) = expression {
+        if !cron.is_empty() {
+            reader.error(&ConfigReader::child_path(&schedule_path, "cron"), error_msg.clone());
+        }
+    }
+    let zone = TimeZone::parse(&timezone);
+    if let Err(ref error_msg) = zone {
+        reader.error(&ConfigReader::child_path(&schedule_path, "timezone"), error_msg.clone());
+    }
+    if expression.is_err() || zone.is_err() {
+        return None;
+    }
+
+    let schedule = Schedule::new(&cron, &timezone, concurrency_policy, missed_runs, starting_deadline_in_seconds)
+        .unwrap();
+    if schedule.next_after(0).is_none() {
+        reader.error(&ConfigReader::child_path(&schedule_path, "cron"),
+                     format!("{} never matches a date", cron));
+    }
+    Some(schedule)
 }
 
-fn read_volumes_for_service(service: &Yaml) -> Vec<Volume> {
-    let mut result = Vec::new();
+fn read_constraints(reader: &mut ConfigReader, service: &Yaml, path: &str) -> Vec<String> {
+    let mut result = vec![];
+    let constraints_path = ConfigReader::child_path(path, "constraints");
 
-    match service["volumes"].is_badvalue() {
-        true => {}
-        false => {
-            let volumes = service["volumes"].as_vec().unwrap();
-            for volume in volumes {
-                let definition = Volume {
-                    host_path: volume["host_path"].as_str().unwrap().to_string(),
-                    container_path: volume["container_path"].as_str().unwrap().to_string(),
-                    read_only_mode: volume["read_only_mode"].as_bool().unwrap(),
-                };
-                result.push(definition);
+    for (idx, constraint) in reader.list(service, path, "constraints", false).iter().enumerate() {
+        let constraint_path = ConfigReader::item_path(&constraints_path, idx);
+        match constraint.as_str() {
+            Some(constraint) => {
+                if let Err(error_msg) = Constraint::parse(constraint) {
+                    reader.error(&constraint_path, error_msg);
+                }
+                result.push(constraint.to_string());
             }
+            None => reader.error(&constraint_path, "must be a string".to_string()),
         }
     }
 
-    result.clone()
+    result
 }
 
-fn read_sla(service: &Yaml) -> SLA {
-    let sla: SLA;
-    sla = match service["sla"].is_badvalue() {
-        true => SLA::None,
-        false => {
-            match &service["sla"].as_str().unwrap().to_string() as &str {
-                "singleton_each_node" => SLA::SingletonEachNode,
-                "singleton_each_slave" => SLA::SingletonEachSlave,
-                _ => SLA::None,
-            }
+fn read_sla(reader: &mut ConfigReader, service: &Yaml, path: &str) -> SLA {
+    match &reader.string_or(service, path, "sla", "none") as &str {
+        "none" => SLA::None,
+        "singleton_each_node" => SLA::SingletonEachNode,
+        "singleton_each_slave" => SLA::SingletonEachSlave,
+        other => {
+            reader.error(&ConfigReader::child_path(path, "sla"),
+                         format!("unknown sla {}, expected none, singleton_each_node or singleton_each_slave",
+                                 other));
+            SLA::None
         }
+    }
+}
+
+pub fn task_to_yaml(task: &Task) -> Yaml {
+    let volumes = task.volumes
+        .iter()
+        .map(|volume| {
+            yaml_hash(vec![("host_path", Yaml::String(volume.host_path.clone())),
+                           ("container_path", Yaml::String(volume.container_path.clone())),
+                           ("read_only_mode", Yaml::Boolean(volume.read_only_mode))])
+        })
+        .collect();
+
+    let dependencies = task.dependencies
+        .iter()
+        .map(|dependency| {
+            yaml_hash(vec![("service", Yaml::String(dependency.service.clone())),
+                           ("condition", Yaml::String(dependency.condition.name().to_string()))])
+        })
+        .collect();
+
+    let health_check = match task.health_check {
+        Some(ref health_check) => {
+            yaml_hash(vec![("command", Yaml::String(health_check.command.clone())),
+                           ("interval_in_seconds", Yaml::Integer(health_check.interval_in_seconds)),
+                           ("timeout_in_seconds", Yaml::Integer(health_check.timeout_in_seconds)),
+                           ("grace_period_in_seconds", Yaml::Integer(health_check.grace_period_in_seconds)),
+                           ("consecutive_failures", Yaml::Integer(health_check.consecutive_failures))])
+        }
+        None => Yaml::Null,
     };
 
-    sla.clone()
-}
\ No newline at end of file
+    let schedule = match task.schedule {
+        Some(ref schedule) => {
+            yaml_hash(vec![("cron", Yaml::String(schedule.cron.clone())),
+                           ("timezone", Yaml::String(schedule.timezone.clone())),
+                           ("concurrency_policy", Yaml::String(schedule.concurrency_policy.name().to_string())),
+                           ("missed_runs", Yaml::String(schedule.missed_runs.name().to_string())),
+                           ("starting_deadline_in_seconds", Yaml::Integer(schedule.starting_deadline_in_seconds))])
+        }
+        None => Yaml::Null,
+    };
+
+    let sla = match task.sla {
+        SLA::None => "none",
+        SLA::SingletonEachNode => "singleton_each_node",
+        SLA::SingletonEachSlave => "singleton_each_slave",
+    };
+
+    yaml_hash(vec![("name", Yaml::String(task.name.clone())),
+                   ("image_name", Yaml::String(task.image.clone())),
+                   ("node_name", Yaml::String(task.node_name.clone())),
+                   ("node_type", Yaml::String(task.node_type.clone())),
+                   ("node_function", Yaml::String(task.node_function.clone())),
+                   ("number_of_instances", Yaml::Integer(task.number_of_instances)),
+                   ("dependencies", Yaml::Array(dependencies)),
+                   ("health_check", health_check),
+                   ("arguments", Yaml::String(task.arguments.clone())),
+                   ("parameters", Yaml::String(task.parameters.clone())),
+                   ("memory", Yaml::Real(format!("{:?}", task.memory))),
+                   ("cpu", Yaml::Real(format!("{:?}", task.cpu))),
+                   ("volumes", Yaml::Array(volumes)),
+                   ("privileged", Yaml::Boolean(task.privileged)),
+                   ("sla", Yaml::String(sla.to_string())),
+                   ("is_metered", Yaml::Boolean(task.is_metered)),
+                   ("is_job", Yaml::Boolean(task.is_job)),
+                   ("max_retries", Yaml::Integer(task.max_retries)),
+                   ("schedule", schedule),
+                   ("network_type", Yaml::String(task.network_type.clone())),
+                   ("constraints",
+                    Yaml::Array(task.constraints.iter().map(|constraint| Yaml::String(constraint.clone())).collect()))])
+}
+
+pub fn yaml_hash(entries: Vec<(&str, Yaml)>) -> Yaml {
+    let mut hash = BTreeMap::new();
+    for (key, value) in entries {
+        hash.insert(Yaml::String(key.to_string()), value);
+    }
+    Yaml::Hash(hash)
+}
@@ -0,0 +1,136 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A placement constraint on an offer attribute. `rack==r1,r2` needs the node
+/// to offer one of the values, `disk!=hdd` none of them. A node that doesn't
+/// offer the attribute at all only passes `!=` constraints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constraint {
+    pub attribute: String,
+    pub negated: bool,
+    pub values: Vec<String>,
+}
+
+impl Constraint {
+    pub fn parse(text: &str) -> Result<Constraint, String> {
+        let (attribute, negated, values) = match text.find("!=") {
+            Some(pos) => (&text[..pos], true, &text[pos + 2..]),
+            None => {
+                match text.find("==") {
+                    Some(pos) => (&text[..pos], false, &text[pos + 2..]),
+                    None => {
+                        return Err(format!("{}: expected ATTRIBUTE==VALUE[,VALUE] or ATTRIBUTE!=VALUE[,VALUE]", text))
+                    }
+                }
+            }
+        };
+
+        let attribute = attribute.trim();
+        if attribute.is_empty() {
+            return Err(format!("{}: the attribute name is missing", text));
+        }
+
+        let values: Vec<String> = values.split(',').map(|value| value.trim().to_string()).collect();
+        if values.iter().any(|value| value.is_empty()) {
+            return Err(format!("{}: values must not be empty", text));
+        }
+
+        Ok(Constraint {
+            attribute: attribute.to_string(),
+            negated: negated,
+            values: values,
+        })
+    }
+
+    pub fn is_met(&self, attributes: &BTreeMap<String, String>) -> bool {
+        let matches = match attributes.get(&self.attribute) {
+            Some(value) => self.values.contains(value),
+            None => false,
+        };
+
+        matches != self.negated
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = match self.negated {
+            true => "!=",
+            false => "==",
+        };
+        write!(f, "{}{}{}", self.attribute, operator, self.values.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::Constraint;
+
+    fn constraint(attribute: &str, negated: bool, values: &[&str]) -> Constraint {
+        Constraint {
+            attribute: attribute.to_string(),
+            negated: negated,
+            values: values.iter().map(|value| value.to_string()).collect(),
+        }
+    }
+
+    fn attributes(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parse_reads_both_operators() {
+        assert_eq!(Constraint::parse("rack==r1,r2"), Ok(constraint("rack", false, &["r1", "r2"])));
+        assert_eq!(Constraint::parse(" disk != hdd , nfs"), Ok(constraint("disk", true, &["hdd", "nfs"])));
+        assert_eq!(Constraint::parse("rack!=r1,r2").unwrap().to_string(), "rack!=r1,r2");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_constraints() {
+        assert_eq!(Constraint::parse("rack=r1"),
+                   Err("rack=r1: expected ATTRIBUTE==VALUE[,VALUE] or ATTRIBUTE!=VALUE[,VALUE]".to_string()));
+        assert_eq!(Constraint::parse("==r1"), Err("==r1: the attribute name is missing".to_string()));
+        assert_eq!(Constraint::parse("rack=="), Err("rack==: values must not be empty".to_string()));
+        assert_eq!(Constraint::parse("rack==r1,,r2"), Err("rack==r1,,r2: values must not be empty".to_string()));
+    }
+
+    #[test]
+    fn equal_needs_one_of_the_values() {
+        let constraint = constraint("rack", false, &["r1", "r2"]);
+        assert!(constraint.is_met(&attributes(&[("rack", "r2")])));
+        assert!(!constraint.is_met(&attributes(&[("rack", "r3")])));
+        assert!(!constraint.is_met(&attributes(&[("disk", "ssd")])));
+    }
+
+    #[test]
+    fn not_equal_needs_none_of_the_values() {
+        let constraint = constraint("disk", true, &["hdd"]);
+        assert!(constraint.is_met(&attributes(&[("disk", "ssd")])));
+        assert!(!constraint.is_met(&attributes(&[("disk", "hdd")])));
+        // a node without the attribute passes
+        assert!(constraint.is_met(&attributes(&[])));
+    }
+}
@@ -0,0 +1,360 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use super::timezone::TimeZone;
+
+const MONTH_NAMES: [&'static str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov",
+                                         "dec"];
+const WEEKDAY_NAMES: [&'static str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// long enough to find the next February 29, which can be 8 years away
+const SEARCH_LIMIT_IN_SECONDS: i64 = 10 * 366 * 24 * 3600;
+
+/// When a job runs, see `Schedule::next_after`.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub cron: String,
+    pub timezone: String,
+    pub concurrency_policy: ConcurrencyPolicy,
+    pub missed_runs: MissedRuns,
+    /// How late a missed run may still be started, 0 means no limit.
+    pub starting_deadline_in_seconds: i64,
+    expression: CronExpression,
+    zone: TimeZone,
+}
+
+/// What happens when a run is due while the previous one is still active.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConcurrencyPolicy {
+    Allow,
+    Forbid,
+    Replace,
+}
+
+/// What happens to runs that were due while the sub-scheduler was down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissedRuns {
+    Skip,
+    RunOnce,
+}
+
+/// A standard five field cron expression: minute, hour, day of month, month
+/// and day of week, with lists, ranges, steps, month and weekday names and the
+/// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` shortcuts.
+#[derive(Clone, Debug)]
+pub struct CronExpression {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+    // like cron, a day matches either field when both are restricted
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl Schedule {
+    pub fn new(cron: &str,
+               timezone: &str,
+               concurrency_policy: ConcurrencyPolicy,
+               missed_runs: MissedRuns,
+               starting_deadline_in_seconds: i64)
+               -> Result<Schedule, String> {
+        Ok(Schedule {
+            cron: cron.to_string(),
+            timezone: timezone.to_string(),
+            concurrency_policy: concurrency_policy,
+            missed_runs: missed_runs,
+            starting_deadline_in_seconds: starting_deadline_in_seconds,
+            expression: try!(CronExpression::parse(cron)),
+            zone: try!(TimeZone::parse(timezone)),
+        })
+    }
+
+    /// The first run strictly after `timestamp`, None if the expression never
+    /// matches. Local times skipped by a daylight saving change don't run and
+    /// repeated ones run twice.
+    pub fn next_after(&self, timestamp: i64) -> Option<i64> {
+        let limit = timestamp + SEARCH_LIMIT_IN_SECONDS;
+        let mut candidate = (timestamp / 60 + 1) * 60;
+
+        while candidate < limit {
+            let local = NaiveDateTime::from_timestamp(candidate + self.zone.offset_at(candidate), 0);
+            // hour steps rather than day steps so a daylight saving change can't be jumped over
+            if !self.expression.matches_day(&local) || !self.expression.hours[local.hour() as usize] {
+                candidate += 3600 - local.minute() as i64 * 60;
+            } else if !self.expression.minutes[local.minute() as usize] {
+                candidate += 60;
+            } else {
+                return Some(candidate);
+            }
+        }
+
+        None
+    }
+}
+
+impl ConcurrencyPolicy {
+    pub fn parse(name: &str) -> Option<ConcurrencyPolicy> {
+        match name {
+            "allow" => Some(ConcurrencyPolicy::Allow),
+            "forbid" => Some(ConcurrencyPolicy::Forbid),
+            "replace" => Some(ConcurrencyPolicy::Replace),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ConcurrencyPolicy::Allow => "allow",
+            ConcurrencyPolicy::Forbid => "forbid",
+            ConcurrencyPolicy::Replace => "replace",
+        }
+    }
+}
+
+impl MissedRuns {
+    pub fn parse(name: &str) -> Option<MissedRuns> {
+        match name {
+            "skip" => Some(MissedRuns::Skip),
+            "run_once" => Some(MissedRuns::RunOnce),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            MissedRuns::Skip => "skip",
+            MissedRuns::RunOnce => "run_once",
+        }
+    }
+}
+
+impl CronExpression {
+    pub fn parse(expression: &str) -> Result<CronExpression, String> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields (minute hour day-of-month month day-of-week), got {}",
+                               fields.len()));
+        }
+
+        let mut days_of_week = try!(parse_field(fields[4], "day of week", 0, 7, &WEEKDAY_NAMES, 0));
+        // 7 is another name for Sunday
+        if days_of_week[7] {
+            days_of_week[0] = true;
+        }
+        days_of_week.truncate(7);
+
+        Ok(CronExpression {
+            minutes: try!(parse_field(fields[0], "minute", 0, 59, &[], 0)),
+            hours: try!(parse_field(fields[1], "hour", 0, 23, &[], 0)),
+            days_of_month: try!(parse_field(fields[2], "day of month", 1, 31, &[], 0)),
+            months: try!(parse_field(fields[3], "month", 1, 12, &MONTH_NAMES, 1)),
+            days_of_week: days_of_week,
+            any_day_of_month: fields[2].starts_with('*'),
+            any_day_of_week: fields[4].starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, local: &NaiveDateTime) -> bool {
+        if !self.months[local.month() as usize] {
+            return false;
+        }
+
+        let day_of_month = self.days_of_month[local.day() as usize];
+        let day_of_week = self.days_of_week[local.weekday().num_days_from_sunday() as usize];
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+}
+
+// a list of `*`, `value` or `first-last`, each optionally followed by `/step`,
+// returns a flag for every value from 0 to `max`
+fn parse_field(field: &str,
+               label: &str,
+               min: u32,
+               max: u32,
+               names: &[&str],
+               first_name_value: u32)
+               -> Result<Vec<bool>, String> {
+    let mut result = vec![false; max as usize + 1];
+
+    for item in field.split(',') {
+        let (range, step) = match item.find('/') {
+            Some(idx) => {
+                match item[idx + 1..].parse::<u32>() {
+                    Ok(step) if step > 0 => (&item[..idx], step),
+                    _ => return Err(format!("invalid step in {} {}", label, item)),
+                }
+            }
+            None => (item, 1),
+        };
+
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else {
+            match range.find('-') {
+                Some(idx) => {
+                    (try!(parse_value(&range[..idx], label, min, max, names, first_name_value)),
+                     try!(parse_value(&range[idx + 1..], label, min, max, names, first_name_value)))
+                }
+                // a single value with a step runs from there to the end, like cron
+                None => {
+                    let value = try!(parse_value(range, label, min, max, names, first_name_value));
+                    (value, if step > 1 { max } else { value })
+                }
+            }
+        };
+        if first > last {
+            return Err(format!("invalid range in {} {}", label, item));
+        }
+
+        let mut value = first;
+        while value <= last {
+            result[value as usize] = true;
+            value += step;
+        }
+    }
+
+    Ok(result)
+}
+
+fn parse_value(text: &str,
+               label: &str,
+               min: u32,
+               max: u32,
+               names: &[&str],
+               first_name_value: u32)
+               -> Result<u32, String> {
+    let lower = text.to_lowercase();
+    if let Some(idx) = names.iter().position(|name| *name == lower) {
+        return Ok(idx as u32 + first_name_value);
+    }
+
+    match text.parse::<u32>() {
+        Ok(value) if value >= min && value <= max => Ok(value),
+        _ => Err(format!("invalid {} {}, expected {} to {}", label, text, min, max)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::{Schedule, CronExpression, ConcurrencyPolicy, MissedRuns};
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        NaiveDate::from_ymd(year, month, day).and_hms(hour, minute, 0).timestamp()
+    }
+
+    fn schedule(cron: &str, timezone: &str) -> Schedule {
+        Schedule::new(cron, timezone, ConcurrencyPolicy::Allow, MissedRuns::Skip, 0).unwrap()
+    }
+
+    #[test]
+    fn next_after_is_strictly_later() {
+        let schedule = schedule("*/15 * * * *", "UTC");
+        assert_eq!(schedule.next_after(utc(2016, 5, 1, 10, 0)), Some(utc(2016, 5, 1, 10, 15)));
+        assert_eq!(schedule.next_after(utc(2016, 5, 1, 10, 0) + 1), Some(utc(2016, 5, 1, 10, 15)));
+        assert_eq!(schedule.next_after(utc(2016, 5, 1, 23, 50)), Some(utc(2016, 5, 2, 0, 0)));
+    }
+
+    #[test]
+    fn skipped_local_times_do_not_run() {
+        // 02:30 doesn't exist in Berlin on March 27 2016
+        let berlin = schedule("30 2 * * *", "Europe/Berlin");
+        assert_eq!(berlin.next_after(utc(2016, 3, 26, 0, 0)), Some(utc(2016, 3, 26, 1, 30)));
+        assert_eq!(berlin.next_after(utc(2016, 3, 26, 1, 30)), Some(utc(2016, 3, 28, 0, 30)));
+
+        // nor in Sydney on October 2 2016
+        let sydney = schedule("30 2 * * *", "Australia/Sydney");
+        assert_eq!(sydney.next_after(utc(2016, 9, 30, 16, 30)), Some(utc(2016, 10, 2, 15, 30)));
+    }
+
+    #[test]
+    fn repeated_local_times_run_twice() {
+        // 02:30 happens twice in Berlin on October 30 2016
+        let berlin = schedule("30 2 * * *", "Europe/Berlin");
+        assert_eq!(berlin.next_after(utc(2016, 10, 29, 23, 0)), Some(utc(2016, 10, 30, 0, 30)));
+        assert_eq!(berlin.next_after(utc(2016, 10, 30, 0, 30)), Some(utc(2016, 10, 30, 1, 30)));
+        assert_eq!(berlin.next_after(utc(2016, 10, 30, 1, 30)), Some(utc(2016, 10, 31, 1, 30)));
+
+        // and in Sydney on April 3 2016
+        let sydney = schedule("30 2 * * *", "Australia/Sydney");
+        assert_eq!(sydney.next_after(utc(2016, 4, 2, 15, 0)), Some(utc(2016, 4, 2, 15, 30)));
+        assert_eq!(sydney.next_after(utc(2016, 4, 2, 15, 30)), Some(utc(2016, 4, 2, 16, 30)));
+    }
+
+    #[test]
+    fn yearly_runs_at_local_midnight() {
+        assert_eq!(schedule("@yearly", "UTC").next_after(utc(2016, 6, 1, 0, 0)),
+                   Some(utc(2017, 1, 1, 0, 0)));
+        assert_eq!(schedule("@yearly", "Europe/Berlin").next_after(utc(2016, 6, 1, 0, 0)),
+                   Some(utc(2016, 12, 31, 23, 0)));
+        assert_eq!(schedule("@annually", "Australia/Sydney").next_after(utc(2016, 6, 1, 0, 0)),
+                   Some(utc(2016, 12, 31, 13, 0)));
+    }
+
+    #[test]
+    fn february_29_finds_the_next_leap_year() {
+        let leap_day = schedule("0 0 29 2 *", "UTC");
+        assert_eq!(leap_day.next_after(utc(2016, 3, 1, 0, 0)), Some(utc(2020, 2, 29, 0, 0)));
+        // 2100 isn't a leap year
+        assert_eq!(leap_day.next_after(utc(2097, 3, 1, 0, 0)), Some(utc(2104, 2, 29, 0, 0)));
+
+        assert_eq!(schedule("0 0 30 2 *", "UTC").next_after(utc(2016, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // the 13th or any Friday
+        let schedule = schedule("0 12 13 * fri", "UTC");
+        assert_eq!(schedule.next_after(utc(2016, 5, 1, 0, 0)), Some(utc(2016, 5, 6, 12, 0)));
+        assert_eq!(schedule.next_after(utc(2016, 5, 6, 12, 0)), Some(utc(2016, 5, 13, 12, 0)));
+        assert_eq!(schedule.next_after(utc(2016, 5, 13, 12, 0)), Some(utc(2016, 5, 20, 12, 0)));
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!(CronExpression::parse("* * * *").is_err());
+        assert!(CronExpression::parse("60 * * * *").is_err());
+        assert!(CronExpression::parse("* * 0 * *").is_err());
+        assert!(CronExpression::parse("* * * * 8").is_err());
+        assert!(CronExpression::parse("5-1 * * * *").is_err());
+        assert!(CronExpression::parse("*/0 * * * *").is_err());
+        assert!(CronExpression::parse("* * * foo *").is_err());
+        assert!(CronExpression::parse("0 0 * jan-mar mon-fri").is_ok());
+        assert!(Schedule::new("@daily", "Nowhere/Atlantis", ConcurrencyPolicy::Allow, MissedRuns::Skip, 0).is_err());
+    }
+}
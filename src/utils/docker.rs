@@ -20,7 +20,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use state::StateManager;
+use state::{PortMapping, StateManager};
 use rustc_serialize::json::Json;
 
 pub fn handle_inspect_data(state_manager: &StateManager, task_name: &String, inspect_data: &String, slave_id: &String) {
@@ -42,8 +42,44 @@ pub fn handle_inspect_data(state_manager: &StateManager, task_name: &String, ins
         }
     }
 
+    let ports = match json.as_array().unwrap()[0].find_path(&["NetworkSettings", "Ports"]) {
+        Some(ports) => read_port_mappings(ports),
+        None => vec![],
+    };
+
     state_manager.send_update_task_info(task_name.to_string(),
                                         id.to_string(),
                                         new_ip.clone(),
+                                        ports,
                                         slave_id.clone());
+}
+
+// "Ports": {"80/tcp": [{"HostIp": "0.0.0.0", "HostPort": "32768"}], "53/udp": null}
+fn read_port_mappings(ports: &Json) -> Vec<PortMapping> {
+    let mut result = vec![];
+
+    if let Some(ports) = ports.as_object() {
+        for (container_port, bindings) in ports {
+            let mut parts = container_port.split('/');
+            let port = parts.next().and_then(|port| port.parse().ok());
+            let protocol = parts.next().unwrap_or("tcp");
+
+            let host_port = bindings.as_array()
+                .and_then(|bindings| bindings.first())
+                .and_then(|binding| binding.find("HostPort"))
+                .and_then(|host_port| host_port.as_string())
+                .and_then(|host_port| host_port.parse().ok());
+
+            if let (Some(port), Some(host_port)) = (port, host_port) {
+                result.push(PortMapping {
+                    container_port: port,
+                    protocol: protocol.to_string(),
+                    host_port: host_port,
+                });
+            }
+        }
+    }
+
+    result.sort_by(|a, b| a.container_port.cmp(&b.container_port));
+    result
 }
\ No newline at end of file
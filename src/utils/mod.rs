@@ -20,12 +20,23 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-pub use self::config::{read_bool, read_int, read_string, read_task};
+pub use self::config::{read_task, task_to_yaml, yaml_hash};
 pub use self::config::Task;
+pub use self::constraint::Constraint;
+pub use self::cron::{ConcurrencyPolicy, CronExpression, MissedRuns, Schedule};
 pub use self::docker::handle_inspect_data;
+pub use self::signal::on_sighup;
+pub use self::template::{Reference, check_template, lookup_config_value, render_template, substitute_references,
+                         template_references};
+pub use self::timezone::TimeZone;
 
 mod config;
+mod constraint;
+mod cron;
 mod docker;
+mod signal;
+mod template;
+mod timezone;
 
 pub const DEFAULT_MEMORY: f64 = 128.0;
 pub const DEFAULT_CPU: f64 = 0.2;
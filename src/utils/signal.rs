@@ -0,0 +1,70 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::sync::Mutex;
+use std::sync::atomic::{ATOMIC_BOOL_INIT, AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use libc;
+
+static SIGHUP_RECEIVED: AtomicBool = ATOMIC_BOOL_INIT;
+
+lazy_static! {
+    static ref SIGHUP_CALLBACKS: Mutex<Vec<Box<Fn() + Send>>> = {
+        Mutex::new(vec![])
+    };
+}
+
+extern "C" fn handle_sighup(_signal: libc::c_int) {
+    // only async-signal-safe work in here, the callbacks run on the watcher thread
+    SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+/// Runs `callback` on a background thread every time the process receives SIGHUP.
+pub fn on_sighup<F>(callback: F)
+    where F: Fn() + Send + 'static
+{
+    let mut callbacks = SIGHUP_CALLBACKS.lock().unwrap();
+    let first = callbacks.is_empty();
+    callbacks.push(Box::new(callback));
+
+    if first {
+        unsafe {
+            libc::signal(libc::SIGHUP, handle_sighup as libc::sighandler_t);
+        }
+
+        thread::Builder::new()
+            .name("sighup-watch".to_string())
+            .spawn(move || {
+                loop {
+                    thread::sleep(Duration::from_secs(1));
+                    if SIGHUP_RECEIVED.swap(false, Ordering::SeqCst) {
+                        log_info!("received SIGHUP");
+                        for callback in SIGHUP_CALLBACKS.lock().unwrap().iter() {
+                            callback();
+                        }
+                    }
+                }
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,426 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use yaml_rust::Yaml;
+
+/// A `${...}` reference inside service arguments or parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reference {
+    /// `$MASTER_IP`, kept for existing configs
+    MasterIp,
+    /// `${service:NAME:ip}`
+    ServiceIp(String),
+    /// `${service:NAME:node}`
+    ServiceNode(String),
+    /// `${service:NAME:port}` is the first published port, `${service:NAME:port:N}`
+    /// the host port container port N is published on
+    ServicePort(String, Option<u16>),
+    /// `${node:ATTRIBUTE}` for the node the task gets placed on, `${node:NAME:ATTRIBUTE}`
+    /// for any configured node
+    Node(Option<String>, String),
+    /// `${task:name}`
+    TaskName,
+    /// `${task:instance}`
+    TaskInstance,
+    /// `${env:NAME}`
+    Env(String),
+    /// `${config:PATH}`, e.g. `${config:api.port}` or `${config:nodes[0].ip}`
+    Config(String),
+    /// `${step:NAME:OUTPUT}`, an output of an earlier step of the same workflow
+    StepOutput(String, String),
+}
+
+const NODE_ATTRIBUTES: &'static [&'static str] = &["name", "ip", "external_ip", "management_ip", "type", "function"];
+const STEP_OUTPUTS: &'static [&'static str] = &["exit_status", "ip", "node", "task"];
+
+impl Reference {
+    pub fn parse(text: &str) -> Result<Reference, String> {
+        let parts: Vec<&str> = text.split(':').collect();
+
+        let reference = match (parts[0], parts.len()) {
+            ("service", 3) | ("service", 4) => {
+                let name = parts[1].to_string();
+                match (parts[2], parts.len()) {
+                    ("ip", 3) => Reference::ServiceIp(name),
+                    ("node", 3) => Reference::ServiceNode(name),
+                    ("port", 3) => Reference::ServicePort(name, None),
+                    ("port", 4) => {
+                        match parts[3].parse() {
+                            Ok(port) => Reference::ServicePort(name, Some(port)),
+                            Err(_) => return Err(format!("${{{}}}: {} is not a port", text, parts[3])),
+                        }
+                    }
+                    _ => return Err(format!("${{{}}}: expected ip, node, port or port:N", text)),
+                }
+            }
+            ("node", 2) => Reference::Node(None, parts[1].to_string()),
+            ("node", 3) => Reference::Node(Some(parts[1].to_string()), parts[2].to_string()),
+            ("task", 2) => {
+                match parts[1] {
+                    "name" => Reference::TaskName,
+                    "instance" => Reference::TaskInstance,
+                    _ => return Err(format!("${{{}}}: expected task:name or task:instance", text)),
+                }
+            }
+            ("env", 2) => Reference::Env(parts[1].to_string()),
+            ("config", 2) => Reference::Config(parts[1].to_string()),
+            ("step", 3) => {
+                if !STEP_OUTPUTS.contains(&parts[2]) {
+                    return Err(format!("${{{}}}: unknown step output {}, expected one of {}",
+                                       text,
+                                       parts[2],
+                                       STEP_OUTPUTS.join(", ")));
+                }
+                Reference::StepOutput(parts[1].to_string(), parts[2].to_string())
+            }
+            _ => return Err(format!("${{{}}}: unknown reference", text)),
+        };
+
+        if let Reference::Node(_, ref attribute) = reference {
+            if !NODE_ATTRIBUTES.contains(&&**attribute) {
+                return Err(format!("${{{}}}: unknown node attribute {}, expected one of {}",
+                                   text,
+                                   attribute,
+                                   NODE_ATTRIBUTES.join(", ")));
+            }
+        }
+
+        let has_empty_part = parts.iter().any(|part| part.is_empty());
+        match has_empty_part {
+            true => Err(format!("${{{}}}: empty name", text)),
+            false => Ok(reference),
+        }
+    }
+}
+
+/// Returns every syntax problem in `template`, used when the config is loaded.
+pub fn check_template(template: &str) -> Vec<String> {
+    let mut errors = vec![];
+    for part in split_template(template) {
+        match part {
+            Part::Reference(text) => {
+                if let Err(error_msg) = Reference::parse(&text) {
+                    errors.push(error_msg);
+                }
+            }
+            Part::Invalid(error_msg) => errors.push(error_msg),
+            Part::Text(_) | Part::Legacy(..) => {}
+        }
+    }
+    errors
+}
+
+/// Replaces every reference in `template` with what `resolve` returns for it.
+/// If any reference can't be resolved the list of those is returned instead,
+/// a task must not be launched with half of its arguments missing.
+pub fn render_template<F>(template: &str, mut resolve: F) -> Result<String, Vec<String>>
+    where F: FnMut(&Reference) -> Option<String>
+{
+    let mut result = String::new();
+    let mut unresolved = vec![];
+
+    for part in split_template(template) {
+        match part {
+            Part::Text(text) => result.push_str(&text),
+            Part::Reference(text) => {
+                match Reference::parse(&text) {
+                    Ok(reference) => {
+                        match resolve(&reference) {
+                            Some(ref value) if !value.is_empty() => result.push_str(value),
+                            _ => unresolved.push(format!("${{{}}}", text)),
+                        }
+                    }
+                    Err(error_msg) => unresolved.push(error_msg),
+                }
+            }
+            Part::Legacy(text, reference) => {
+                match resolve(&reference) {
+                    Some(ref value) if !value.is_empty() => result.push_str(value),
+                    _ => unresolved.push(text.to_string()),
+                }
+            }
+            Part::Invalid(error_msg) => unresolved.push(error_msg),
+        }
+    }
+
+    match unresolved.is_empty() {
+        true => Ok(result),
+        false => Err(unresolved),
+    }
+}
+
+/// Every valid reference in `template`.
+pub fn template_references(template: &str) -> Vec<Reference> {
+    split_template(template)
+        .into_iter()
+        .filter_map(|part| {
+            match part {
+                Part::Reference(text) => Reference::parse(&text).ok(),
+                Part::Legacy(_, reference) => Some(reference),
+                Part::Text(_) | Part::Invalid(_) => None,
+            }
+        })
+        .collect()
+}
+
+/// Replaces the references `resolve` returns a value for and keeps the others
+/// as written, so the rest can still be rendered when the task gets placed.
+pub fn substitute_references<F>(template: &str, mut resolve: F) -> String
+    where F: FnMut(&Reference) -> Option<String>
+{
+    let mut result = String::new();
+
+    for part in split_template(template) {
+        match part {
+            Part::Text(text) => result.push_str(&text.replace("$", "$$")),
+            Part::Reference(text) => {
+                match Reference::parse(&text).ok().and_then(|reference| resolve(&reference)) {
+                    Some(value) => result.push_str(&value.replace("$", "$$")),
+                    None => result.push_str(&format!("${{{}}}", text)),
+                }
+            }
+            Part::Legacy(text, _) => result.push_str(text),
+            // rejected by check_template when the config is loaded
+            Part::Invalid(_) => {}
+        }
+    }
+
+    result
+}
+
+/// Looks up a scalar in the config by a path like `api.port` or `nodes[0].ip`.
+pub fn lookup_config_value(config: &Yaml, path: &str) -> Option<String> {
+    let mut current = config;
+
+    for segment in path.split('.') {
+        let (key, indexes) = match segment.find('[') {
+            Some(pos) => (&segment[..pos], &segment[pos..]),
+            None => (segment, ""),
+        };
+
+        if !key.is_empty() {
+            current = &current[key];
+        }
+
+        for index in indexes.split('[').filter(|index| !index.is_empty()) {
+            match index.trim_right_matches(']').parse::<usize>() {
+                Ok(index) => current = &current[index],
+                Err(_) => return None,
+            }
+        }
+    }
+
+    match *current {
+        Yaml::String(ref value) => Some(value.clone()),
+        Yaml::Integer(value) => Some(value.to_string()),
+        Yaml::Real(ref value) => Some(value.clone()),
+        Yaml::Boolean(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+enum Part {
+    Text(String),
+    // without the surrounding `${` and `}`
+    Reference(String),
+    Legacy(&'static str, Reference),
+    Invalid(String),
+}
+
+// `$$` is a literal `$`
+fn split_template(template: &str) -> Vec<Part> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut rest = template;
+
+    while let Some(pos) = rest.find('$') {
+        text.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if rest.starts_with("$$") {
+            text.push('$');
+            rest = &rest[2..];
+            continue;
+        }
+
+        let mut reference = None;
+        if rest.starts_with("${") {
+            match rest.find('}') {
+                Some(end) => {
+                    reference = Some(Part::Reference(rest[2..end].to_string()));
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    reference = Some(Part::Invalid(format!("{}: missing closing }}", rest)));
+                    rest = "";
+                }
+            }
+        } else {
+            if rest.starts_with("$MASTER_IP") {
+                reference = Some(Part::Legacy("$MASTER_IP", Reference::MasterIp));
+            } else if rest.starts_with("$IP_DNS_SL1") {
+                reference = Some(Part::Legacy("$IP_DNS_SL1", Reference::ServiceIp("dns-sl1".to_string())));
+            }
+            if let Some(Part::Legacy(text, _)) = reference {
+                rest = &rest[text.len()..];
+            }
+        }
+
+        match reference {
+            Some(reference) => {
+                if !text.is_empty() {
+                    parts.push(Part::Text(text.clone()));
+                    text.clear();
+                }
+                parts.push(reference);
+            }
+            None => {
+                text.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Part, Reference, render_template, split_template};
+
+    fn describe(parts: Vec<Part>) -> Vec<String> {
+        parts.into_iter()
+            .map(|part| {
+                match part {
+                    Part::Text(text) => format!("text {}", text),
+                    Part::Reference(text) => format!("reference {}", text),
+                    Part::Legacy(text, _) => format!("legacy {}", text),
+                    Part::Invalid(error_msg) => format!("invalid {}", error_msg),
+                }
+            })
+            .collect()
+    }
+
+    fn resolve(reference: &Reference) -> Option<String> {
+        match *reference {
+            Reference::MasterIp => Some("10.0.0.1".to_string()),
+            Reference::ServiceIp(ref name) if name == "dns-sl1" => Some("10.0.0.53".to_string()),
+            Reference::Env(ref name) if name == "HOME" => Some("/root".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn split_template_keeps_escaped_dollars_as_text() {
+        assert_eq!(describe(split_template("a $$b $${env:HOME}")),
+                   vec!["text a $b ${env:HOME}".to_string()]);
+        assert_eq!(describe(split_template("$$${env:HOME}")),
+                   vec!["text $".to_string(), "reference env:HOME".to_string()]);
+    }
+
+    #[test]
+    fn split_template_splits_references_from_text() {
+        assert_eq!(describe(split_template("--home ${env:HOME} --port ${service:db:port:5432}")),
+                   vec!["text --home ".to_string(),
+                        "reference env:HOME".to_string(),
+                        "text  --port ".to_string(),
+                        "reference service:db:port:5432".to_string()]);
+    }
+
+    #[test]
+    fn split_template_reports_unterminated_references() {
+        assert_eq!(describe(split_template("--home ${env:HOME")),
+                   vec!["text --home ".to_string(), "invalid ${env:HOME: missing closing }".to_string()]);
+    }
+
+    #[test]
+    fn split_template_recognizes_legacy_variables() {
+        assert_eq!(describe(split_template("$MASTER_IP:5050 $IP_DNS_SL1 $OTHER")),
+                   vec!["legacy $MASTER_IP".to_string(),
+                        "text :5050 ".to_string(),
+                        "legacy $IP_DNS_SL1".to_string(),
+                        "text  $OTHER".to_string()]);
+    }
+
+    #[test]
+    fn render_template_replaces_references() {
+        assert_eq!(render_template("--master $MASTER_IP --dns $IP_DNS_SL1 --home ${env:HOME} --cost $$5",
+                                   resolve),
+                   Ok("--master 10.0.0.1 --dns 10.0.0.53 --home /root --cost $5".to_string()));
+    }
+
+    #[test]
+    fn render_template_lists_every_unresolved_reference() {
+        assert_eq!(render_template("${env:HOME} ${service:db:ip} ${env:USER} ${bogus}", resolve),
+                   Err(vec!["${service:db:ip}".to_string(),
+                            "${env:USER}".to_string(),
+                            "${bogus}: unknown reference".to_string()]));
+    }
+
+    #[test]
+    fn render_template_rejects_unterminated_references() {
+        assert_eq!(render_template("--home ${env:HOME", resolve),
+                   Err(vec!["${env:HOME: missing closing }".to_string()]));
+    }
+
+    #[test]
+    fn parse_reads_every_kind_of_reference() {
+        assert_eq!(Reference::parse("service:db:ip"), Ok(Reference::ServiceIp("db".to_string())));
+        assert_eq!(Reference::parse("service:db:node"), Ok(Reference::ServiceNode("db".to_string())));
+        assert_eq!(Reference::parse("service:db:port"), Ok(Reference::ServicePort("db".to_string(), None)));
+        assert_eq!(Reference::parse("service:db:port:5432"),
+                   Ok(Reference::ServicePort("db".to_string(), Some(5432))));
+        assert_eq!(Reference::parse("node:ip"), Ok(Reference::Node(None, "ip".to_string())));
+        assert_eq!(Reference::parse("node:sl1:management_ip"),
+                   Ok(Reference::Node(Some("sl1".to_string()), "management_ip".to_string())));
+        assert_eq!(Reference::parse("task:name"), Ok(Reference::TaskName));
+        assert_eq!(Reference::parse("task:instance"), Ok(Reference::TaskInstance));
+        assert_eq!(Reference::parse("env:HOME"), Ok(Reference::Env("HOME".to_string())));
+        assert_eq!(Reference::parse("config:nodes[0].ip"), Ok(Reference::Config("nodes[0].ip".to_string())));
+        assert_eq!(Reference::parse("step:extract:exit_status"),
+                   Ok(Reference::StepOutput("extract".to_string(), "exit_status".to_string())));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_references() {
+        assert_eq!(Reference::parse("service:db:port:http"),
+                   Err("${service:db:port:http}: http is not a port".to_string()));
+        assert_eq!(Reference::parse("service:db:disk"),
+                   Err("${service:db:disk}: expected ip, node, port or port:N".to_string()));
+        assert_eq!(Reference::parse("node:rack"),
+                   Err("${node:rack}: unknown node attribute rack, expected one of name, ip, external_ip, \
+                        management_ip, type, function"
+                       .to_string()));
+        assert_eq!(Reference::parse("service::ip"), Err("${service::ip}: empty name".to_string()));
+        assert_eq!(Reference::parse("task:id"),
+                   Err("${task:id}: expected task:name or task:instance".to_string()));
+        assert_eq!(Reference::parse("step:extract:stdout"),
+                   Err("${step:extract:stdout}: unknown step output stdout, expected one of exit_status, ip, \
+                        node, task"
+                       .to_string()));
+    }
+}
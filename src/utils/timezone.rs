@@ -0,0 +1,508 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::fs::File;
+use std::io::Read;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+
+macro_rules! try_opt {
+    ($expr:expr) => (match $expr {
+        Some(value) => value,
+        None => return None,
+    })
+}
+
+const ZONEINFO_DIR: &'static str = "/usr/share/zoneinfo";
+
+/// A time zone schedules are evaluated in, `UTC`, a fixed offset like `+02:00` or
+/// a name from the zoneinfo database like `Europe/Berlin`.
+#[derive(Clone, Debug)]
+pub struct TimeZone {
+    pub name: String,
+    /// (UTC timestamp, offset in seconds from then on), sorted by timestamp
+    transitions: Vec<(i64, i64)>,
+    initial_offset: i64,
+    /// Applies after the last transition, zoneinfo files only list the
+    /// transitions up to a certain year.
+    rule: Option<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    std_offset: i64,
+    dst: Option<DstRule>,
+}
+
+#[derive(Clone, Debug)]
+struct DstRule {
+    offset: i64,
+    start: (RuleDate, i64),
+    end: (RuleDate, i64),
+}
+
+#[derive(Clone, Debug)]
+enum RuleDate {
+    /// Mm.w.d, day d (0 is Sunday) of week w (5 is the last one) of month m
+    MonthWeekDay(u32, u32, u32),
+    /// Jn, day 1 to 365 without ever counting February 29
+    Julian(i64),
+    /// n, day 0 to 365 counting February 29 in leap years
+    DayOfYear(i64),
+}
+
+impl TimeZone {
+    pub fn utc() -> TimeZone {
+        TimeZone::fixed("UTC", 0)
+    }
+
+    pub fn parse(name: &str) -> Result<TimeZone, String> {
+        if name.is_empty() || name == "UTC" || name == "Z" {
+            return Ok(TimeZone::utc());
+        }
+        if name.starts_with('+') || name.starts_with('-') {
+            return match parse_offset(name) {
+                Some(offset) if offset.abs() < 24 * 3600 => Ok(TimeZone::fixed(name, offset)),
+                _ => Err(format!("invalid offset {}, expected something like +02:00", name)),
+            };
+        }
+        if name.starts_with('/') || name.split('/').any(|part| part == ".." || part.is_empty()) {
+            return Err(format!("invalid time zone name {}", name));
+        }
+
+        let path = format!("{}/{}", ZONEINFO_DIR, name);
+        let mut data = vec![];
+        if let Err(error) = File::open(&path).and_then(|mut file| file.read_to_end(&mut data)) {
+            return Err(format!("unknown time zone {}: {}", name, error));
+        }
+        match parse_tzif(&data) {
+            Some((transitions, initial_offset, rule)) => {
+                Ok(TimeZone {
+                    name: name.to_string(),
+                    transitions: transitions,
+                    initial_offset: initial_offset,
+                    rule: rule,
+                })
+            }
+            None => Err(format!("{} is not a valid zoneinfo file", path)),
+        }
+    }
+
+    fn fixed(name: &str, offset: i64) -> TimeZone {
+        TimeZone {
+            name: name.to_string(),
+            transitions: vec![],
+            initial_offset: offset,
+            rule: None,
+        }
+    }
+
+    /// Seconds to add to the UTC `timestamp` to get the local time.
+    pub fn offset_at(&self, timestamp: i64) -> i64 {
+        let after_last = self.transitions.last().map(|&(at, _)| timestamp >= at).unwrap_or(true);
+        if after_last {
+            if let Some(ref rule) = self.rule {
+                return rule.offset_at(timestamp);
+            }
+        }
+
+        match self.transitions.binary_search_by(|&(at, _)| at.cmp(&timestamp)) {
+            Ok(idx) => self.transitions[idx].1,
+            Err(0) => self.initial_offset,
+            Err(idx) => self.transitions[idx - 1].1,
+        }
+    }
+}
+
+impl Rule {
+    fn offset_at(&self, timestamp: i64) -> i64 {
+        let dst = match self.dst {
+            Some(ref dst) => dst,
+            None => return self.std_offset,
+        };
+
+        let year = NaiveDateTime::from_timestamp(timestamp + self.std_offset, 0).year();
+        // the start is given in standard time and the end in daylight saving time
+        let start = day_timestamp(year, &dst.start.0) + dst.start.1 - self.std_offset;
+        let end = day_timestamp(year, &dst.end.0) + dst.end.1 - dst.offset;
+
+        let in_dst = if start < end {
+            timestamp >= start && timestamp < end
+        } else {
+            // southern hemisphere, daylight saving time spans the new year
+            timestamp < end || timestamp >= start
+        };
+        if in_dst { dst.offset } else { self.std_offset }
+    }
+}
+
+fn day_timestamp(year: i32, date: &RuleDate) -> i64 {
+    let date = match *date {
+        RuleDate::MonthWeekDay(month, week, weekday) => {
+            let first = NaiveDate::from_ymd(year, month, 1);
+            let mut day = 1 + (weekday + 7 - first.weekday().num_days_from_sunday()) % 7 + (week - 1) * 7;
+            while day > days_in_month(year, month) {
+                day -= 7;
+            }
+            NaiveDate::from_ymd(year, month, day)
+        }
+        RuleDate::Julian(day) => {
+            let is_leap_year = days_in_month(year, 2) == 29;
+            let day = if is_leap_year && day >= 60 { day + 1 } else { day };
+            NaiveDate::from_yo(year, day as u32)
+        }
+        RuleDate::DayOfYear(day) => NaiveDate::from_yo(year, day as u32 + 1),
+    };
+    date.and_hms(0, 0, 0).timestamp()
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let next_month = if month == 12 {
+        NaiveDate::from_ymd(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(year, month + 1, 1)
+    };
+    next_month.pred().day()
+}
+
+// +hh[:mm[:ss]], the sign is required
+fn parse_offset(text: &str) -> Option<i64> {
+    let (sign, rest) = match text.chars().next() {
+        Some('+') => (1, &text[1..]),
+        Some('-') => (-1, &text[1..]),
+        _ => return None,
+    };
+    parse_duration(rest).map(|seconds| sign * seconds)
+}
+
+// hh[:mm[:ss]]
+fn parse_duration(text: &str) -> Option<i64> {
+    let mut seconds = 0;
+    let mut parts = 0;
+    for (idx, part) in text.split(':').enumerate() {
+        if idx > 2 || part.is_empty() || part.len() > 3 || !part.chars().all(|c| c.is_digit(10)) {
+            return None;
+        }
+        let value = match part.parse::<i64>() {
+            Ok(value) => value,
+            Err(_) => return None,
+        };
+        if idx > 0 && value > 59 {
+            return None;
+        }
+        seconds += value * [3600, 60, 1][idx];
+        parts += 1;
+    }
+    if parts == 0 { None } else { Some(seconds) }
+}
+
+// RFC 8536, the 64 bit data of version 2 and later files is preferred
+fn parse_tzif(data: &[u8]) -> Option<(Vec<(i64, i64)>, i64, Option<Rule>)> {
+    if data.len() < 44 || &data[0..4] != b"TZif" {
+        return None;
+    }
+
+    let v1_counts = try_opt!(header_counts(data));
+    if data[4] == 0 {
+        return parse_tzif_data(&data[44..], &v1_counts, 4).map(|(transitions, initial_offset, _)| {
+            (transitions, initial_offset, None)
+        });
+    }
+
+    let v2_start = 44 + data_length(&v1_counts, 4);
+    if data.len() < v2_start + 44 || &data[v2_start..v2_start + 4] != b"TZif" {
+        return None;
+    }
+    let v2_counts = try_opt!(header_counts(&data[v2_start..]));
+    let (transitions, initial_offset, used) = try_opt!(parse_tzif_data(&data[v2_start + 44..], &v2_counts, 8));
+
+    // the footer is a POSIX TZ string between newlines, empty when there's no rule
+    let footer = &data[v2_start + 44 + used..];
+    let rule = if footer.len() > 2 && footer[0] == b'\n' {
+        let end = footer[1..].iter().position(|&byte| byte == b'\n').unwrap_or(footer.len() - 1);
+        match String::from_utf8(footer[1..end + 1].to_vec()) {
+            Ok(ref text) if !text.is_empty() => parse_rule(text),
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    Some((transitions, initial_offset, rule))
+}
+
+// isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+fn header_counts(data: &[u8]) -> Option<Vec<usize>> {
+    if data.len() < 44 {
+        return None;
+    }
+    Some((0..6).map(|idx| read_be(&data[20 + idx * 4..24 + idx * 4]) as usize).collect())
+}
+
+fn data_length(counts: &Vec<usize>, time_size: usize) -> usize {
+    counts[3] * time_size + counts[3] + counts[4] * 6 + counts[5] + counts[2] * (time_size + 4) + counts[1] + counts[0]
+}
+
+fn parse_tzif_data(data: &[u8], counts: &Vec<usize>, time_size: usize) -> Option<(Vec<(i64, i64)>, i64, usize)> {
+    let length = data_length(counts, time_size);
+    let (time_count, type_count) = (counts[3], counts[4]);
+    if data.len() < length || type_count == 0 {
+        return None;
+    }
+
+    let types_start = time_count * time_size + time_count;
+    let offsets: Vec<i64> = (0..type_count)
+        .map(|idx| read_be(&data[types_start + idx * 6..types_start + idx * 6 + 4]) as i32 as i64)
+        .collect();
+
+    let mut transitions = vec![];
+    for idx in 0..time_count {
+        let raw = read_be(&data[idx * time_size..(idx + 1) * time_size]);
+        let at = if time_size == 4 { raw as i32 as i64 } else { raw as i64 };
+        let type_idx = data[time_count * time_size + idx] as usize;
+        if type_idx >= type_count {
+            return None;
+        }
+        transitions.push((at, offsets[type_idx]));
+    }
+
+    Some((transitions, offsets[0], length))
+}
+
+fn read_be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+// std offset [dst [offset] [,start[/time],end[/time]]], offsets count west of UTC
+fn parse_rule(text: &str) -> Option<Rule> {
+    let mut rest = text;
+    try_opt!(take_zone_name(&mut rest));
+    let std_offset = -try_opt!(take_rule_offset(&mut rest));
+    if rest.is_empty() {
+        return Some(Rule {
+            std_offset: std_offset,
+            dst: None,
+        });
+    }
+
+    try_opt!(take_zone_name(&mut rest));
+    let dst_offset = if rest.starts_with(',') {
+        std_offset + 3600
+    } else {
+        -try_opt!(take_rule_offset(&mut rest))
+    };
+
+    let mut dates = rest.split(',').skip(1);
+    let start = try_opt!(dates.next().and_then(parse_rule_date));
+    let end = try_opt!(dates.next().and_then(parse_rule_date));
+
+    Some(Rule {
+        std_offset: std_offset,
+        dst: Some(DstRule {
+            offset: dst_offset,
+            start: start,
+            end: end,
+        }),
+    })
+}
+
+fn take_zone_name(rest: &mut &str) -> Option<()> {
+    let length = if rest.starts_with('<') {
+        try_opt!(rest.find('>')) + 1
+    } else {
+        rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len())
+    };
+    if length < 3 {
+        return None;
+    }
+    *rest = &rest[length..];
+    Some(())
+}
+
+fn take_rule_offset(rest: &mut &str) -> Option<i64> {
+    let length = rest.find(|c: char| !(c.is_digit(10) || c == ':' || c == '+' || c == '-'))
+        .unwrap_or(rest.len());
+    let text = &rest[..length];
+    *rest = &rest[length..];
+    if text.starts_with('+') || text.starts_with('-') {
+        parse_offset(text)
+    } else {
+        parse_duration(text)
+    }
+}
+
+fn parse_rule_date(text: &str) -> Option<(RuleDate, i64)> {
+    let mut parts = text.splitn(2, '/');
+    let date = try_opt!(parts.next());
+    let time = match parts.next() {
+        Some(time) if time.starts_with('-') => try_opt!(parse_offset(time)),
+        Some(time) => try_opt!(parse_duration(time)),
+        None => 2 * 3600,
+    };
+
+    let date = if date.starts_with('M') {
+        let fields: Vec<u32> = date[1..].split('.').filter_map(|field| field.parse().ok()).collect();
+        if fields.len() != 3 || fields[0] < 1 || fields[0] > 12 || fields[1] < 1 || fields[1] > 5 || fields[2] > 6 {
+            return None;
+        }
+        RuleDate::MonthWeekDay(fields[0], fields[1], fields[2])
+    } else if date.starts_with('J') {
+        match date[1..].parse() {
+            Ok(day) if day >= 1 && day <= 365 => RuleDate::Julian(day),
+            _ => return None,
+        }
+    } else {
+        match date.parse() {
+            Ok(day) if day >= 0 && day <= 365 => RuleDate::DayOfYear(day),
+            _ => return None,
+        }
+    };
+
+    Some((date, time))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::{TimeZone, RuleDate, day_timestamp, parse_rule, parse_rule_date};
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        NaiveDate::from_ymd(year, month, day).and_hms(hour, minute, 0).timestamp()
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> i64 {
+        utc(year, month, day, 0, 0)
+    }
+
+    #[test]
+    fn berlin_switches_on_the_last_sundays_of_march_and_october() {
+        let zone = TimeZone::parse("Europe/Berlin").unwrap();
+
+        // from the zoneinfo transitions
+        assert_eq!(zone.offset_at(utc(2016, 3, 27, 1, 0) - 1), 3600);
+        assert_eq!(zone.offset_at(utc(2016, 3, 27, 1, 0)), 7200);
+        assert_eq!(zone.offset_at(utc(2016, 10, 30, 1, 0) - 1), 7200);
+        assert_eq!(zone.offset_at(utc(2016, 10, 30, 1, 0)), 3600);
+
+        // from the rule after the last transition
+        assert_eq!(zone.offset_at(utc(2100, 3, 28, 1, 0) - 1), 3600);
+        assert_eq!(zone.offset_at(utc(2100, 3, 28, 1, 0)), 7200);
+        assert_eq!(zone.offset_at(utc(2100, 10, 31, 1, 0) - 1), 7200);
+        assert_eq!(zone.offset_at(utc(2100, 10, 31, 1, 0)), 3600);
+    }
+
+    #[test]
+    fn sydney_daylight_saving_time_spans_the_new_year() {
+        let zone = TimeZone::parse("Australia/Sydney").unwrap();
+
+        assert_eq!(zone.offset_at(utc(2016, 1, 15, 0, 0)), 11 * 3600);
+        assert_eq!(zone.offset_at(utc(2016, 4, 2, 16, 0) - 1), 11 * 3600);
+        assert_eq!(zone.offset_at(utc(2016, 4, 2, 16, 0)), 10 * 3600);
+        assert_eq!(zone.offset_at(utc(2016, 10, 1, 16, 0) - 1), 10 * 3600);
+        assert_eq!(zone.offset_at(utc(2016, 10, 1, 16, 0)), 11 * 3600);
+
+        assert_eq!(zone.offset_at(utc(2100, 1, 15, 0, 0)), 11 * 3600);
+        assert_eq!(zone.offset_at(utc(2100, 4, 3, 16, 0) - 1), 11 * 3600);
+        assert_eq!(zone.offset_at(utc(2100, 4, 3, 16, 0)), 10 * 3600);
+        assert_eq!(zone.offset_at(utc(2100, 10, 2, 16, 0) - 1), 10 * 3600);
+        assert_eq!(zone.offset_at(utc(2100, 10, 2, 16, 0)), 11 * 3600);
+        assert_eq!(zone.offset_at(utc(2100, 12, 31, 23, 0)), 11 * 3600);
+    }
+
+    #[test]
+    fn fixed_offsets_and_invalid_names() {
+        assert_eq!(TimeZone::parse("UTC").unwrap().offset_at(0), 0);
+        assert_eq!(TimeZone::parse("+05:30").unwrap().offset_at(0), 5 * 3600 + 1800);
+        assert_eq!(TimeZone::parse("-08").unwrap().offset_at(0), -8 * 3600);
+        assert!(TimeZone::parse("+24:00").is_err());
+        assert!(TimeZone::parse("../etc/passwd").is_err());
+        assert!(TimeZone::parse("/etc/localtime").is_err());
+        assert!(TimeZone::parse("Nowhere/Atlantis").is_err());
+    }
+
+    #[test]
+    fn month_week_day_dates() {
+        // last Sunday of March
+        assert_eq!(day_timestamp(2016, &RuleDate::MonthWeekDay(3, 5, 0)), date(2016, 3, 27));
+        // first Sunday of October
+        assert_eq!(day_timestamp(2016, &RuleDate::MonthWeekDay(10, 1, 0)), date(2016, 10, 2));
+        // the fifth Thursday of February only exists in 2024
+        assert_eq!(day_timestamp(2024, &RuleDate::MonthWeekDay(2, 5, 4)), date(2024, 2, 29));
+        assert_eq!(day_timestamp(2023, &RuleDate::MonthWeekDay(2, 5, 4)), date(2023, 2, 23));
+    }
+
+    #[test]
+    fn julian_dates_never_count_february_29() {
+        assert_eq!(day_timestamp(2023, &RuleDate::Julian(1)), date(2023, 1, 1));
+        assert_eq!(day_timestamp(2023, &RuleDate::Julian(59)), date(2023, 2, 28));
+        assert_eq!(day_timestamp(2024, &RuleDate::Julian(59)), date(2024, 2, 28));
+        assert_eq!(day_timestamp(2023, &RuleDate::Julian(60)), date(2023, 3, 1));
+        assert_eq!(day_timestamp(2024, &RuleDate::Julian(60)), date(2024, 3, 1));
+        assert_eq!(day_timestamp(2024, &RuleDate::Julian(365)), date(2024, 12, 31));
+    }
+
+    #[test]
+    fn day_of_year_dates_count_february_29() {
+        assert_eq!(day_timestamp(2023, &RuleDate::DayOfYear(0)), date(2023, 1, 1));
+        assert_eq!(day_timestamp(2023, &RuleDate::DayOfYear(59)), date(2023, 3, 1));
+        assert_eq!(day_timestamp(2024, &RuleDate::DayOfYear(59)), date(2024, 2, 29));
+        assert_eq!(day_timestamp(2024, &RuleDate::DayOfYear(365)), date(2024, 12, 31));
+    }
+
+    #[test]
+    fn rule_dates_with_times() {
+        assert_eq!(parse_rule_date("M3.5.0").unwrap().1, 2 * 3600);
+        assert_eq!(parse_rule_date("M10.5.0/3").unwrap().1, 3 * 3600);
+        assert_eq!(parse_rule_date("J60/1:30").unwrap().1, 3600 + 1800);
+        assert_eq!(parse_rule_date("59/-1").unwrap().1, -3600);
+
+        assert!(parse_rule_date("M13.1.0").is_none());
+        assert!(parse_rule_date("M3.6.0").is_none());
+        assert!(parse_rule_date("M3.1.7").is_none());
+        assert!(parse_rule_date("J0").is_none());
+        assert!(parse_rule_date("J366").is_none());
+        assert!(parse_rule_date("366").is_none());
+        assert!(parse_rule_date("M3.5.0/x").is_none());
+    }
+
+    #[test]
+    fn rules() {
+        let berlin = parse_rule("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        assert_eq!(berlin.offset_at(utc(2016, 1, 1, 0, 0)), 3600);
+        assert_eq!(berlin.offset_at(utc(2016, 3, 27, 1, 0)), 7200);
+        assert_eq!(berlin.offset_at(utc(2016, 10, 30, 1, 0)), 3600);
+
+        let sydney = parse_rule("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset_at(utc(2016, 1, 1, 0, 0)), 11 * 3600);
+        assert_eq!(sydney.offset_at(utc(2016, 7, 1, 0, 0)), 10 * 3600);
+
+        let julian = parse_rule("<+03>-3<+04>,J60/0,J300/0").unwrap();
+        assert_eq!(julian.offset_at(utc(2024, 2, 29, 20, 59)), 3 * 3600);
+        assert_eq!(julian.offset_at(utc(2024, 2, 29, 21, 0)), 4 * 3600);
+
+        let fixed = parse_rule("EST5").unwrap();
+        assert_eq!(fixed.std_offset, -5 * 3600);
+        assert!(fixed.dst.is_none());
+
+        assert!(parse_rule("EST").is_none());
+        assert!(parse_rule("E5").is_none());
+        assert!(parse_rule("CET-1CEST,M3.5.0").is_none());
+    }
+}
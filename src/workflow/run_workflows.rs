@@ -0,0 +1,440 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 AT&T
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use chrono::UTC;
+use config::{Config, Workflow};
+use state::{StateManager, TaskState};
+use utils::{Reference, substitute_references};
+
+const POLL_INTERVAL_IN_SECONDS: u64 = 5;
+
+lazy_static! {
+    static ref WORKFLOWS: Arc<Mutex<Workflows>> = {
+        Arc::new(Mutex::new(Workflows {
+            defined: vec![],
+            runs: vec![],
+            run_numbers: BTreeMap::new(),
+        }))
+    };
+}
+
+struct Workflows {
+    /// Defined through the api, the others come from the config.
+    defined: Vec<Workflow>,
+    runs: Vec<ActiveRun>,
+    run_numbers: BTreeMap<String, u64>,
+}
+
+// a run keeps the definition it was started with, reloads don't change it
+struct ActiveRun {
+    workflow: Workflow,
+    run: WorkflowRun,
+}
+
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+pub enum RunState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+pub enum StepState {
+    /// for the steps it comes after
+    Waiting,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct WorkflowRun {
+    pub id: String,
+    pub workflow: String,
+    pub state: RunState,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub steps: Vec<StepRun>,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct StepRun {
+    pub step: String,
+    pub after: Vec<String>,
+    pub state: StepState,
+    /// The task of the latest attempt, empty until the step started.
+    pub task: String,
+    /// Counts the retries through the api, the job's own `max_retries` happen within one attempt.
+    pub attempt: i64,
+    pub exit_status: Option<i32>,
+    pub ip: String,
+    pub node: String,
+    pub message: String,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct WorkflowSummary {
+    pub name: String,
+    /// config or api
+    pub source: String,
+    pub steps: Vec<StepSummary>,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct StepSummary {
+    pub name: String,
+    pub after: Vec<String>,
+}
+
+/// Starts the steps of running workflows once the steps they come after
+/// succeeded and records how they ended.
+pub fn run_workflows(state_manager: &StateManager) {
+    log_info!("workflows starting");
+    state_manager.send_ping();
+
+    loop {
+        thread::sleep(Duration::from_secs(POLL_INTERVAL_IN_SECONDS));
+
+        let config = state_manager.get_config();
+        let now = UTC::now().timestamp();
+        let mut workflows = WORKFLOWS.lock().unwrap();
+
+        for active in workflows.runs.iter_mut() {
+            if active.run.state == RunState::Running {
+                advance(state_manager, active, now);
+            }
+        }
+
+        // completed runs are kept like job runs, the oldest go first
+        let mut completed = workflows.runs.iter().filter(|active| active.run.state != RunState::Running).count();
+        while completed > config.jobs.history_size as usize {
+            match workflows.runs.iter().position(|active| active.run.state != RunState::Running) {
+                Some(idx) => {
+                    workflows.runs.remove(idx);
+                    completed -= 1;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// The workflows of the config followed by the ones defined through the api.
+pub fn list_workflows(config: &Config) -> Vec<WorkflowSummary> {
+    let workflows = WORKFLOWS.lock().unwrap();
+
+    config.workflows
+        .iter()
+        .map(|workflow| summary(workflow, "config"))
+        .chain(workflows.defined
+            .iter()
+            .filter(|workflow| config.get_workflow(&workflow.name).is_none())
+            .map(|workflow| summary(workflow, "api")))
+        .collect()
+}
+
+/// Adds or replaces a workflow defined through the api, the ones in the config
+/// can only be changed there.
+pub fn define_workflow(config: &Config, workflow: Workflow) -> Result<(), &'static str> {
+    if config.get_workflow(&workflow.name).is_some() {
+        return Err("defined_in_config");
+    }
+
+    let mut workflows = WORKFLOWS.lock().unwrap();
+    workflows.defined.retain(|defined| defined.name != workflow.name);
+    log_info!("workflow defined"; "workflow" => workflow.name, "steps" => workflow.steps.len());
+    workflows.defined.push(workflow);
+    Ok(())
+}
+
+/// Removes a workflow defined through the api, its runs carry on.
+pub fn remove_workflow(name: &str) -> bool {
+    let mut workflows = WORKFLOWS.lock().unwrap();
+    let before = workflows.defined.len();
+    workflows.defined.retain(|defined| defined.name != name);
+    workflows.defined.len() != before
+}
+
+/// Starts a run of the workflow and the steps that don't come after any other,
+/// None if there's no such workflow.
+pub fn start_workflow(state_manager: &StateManager, config: &Config, name: &str) -> Option<WorkflowRun> {
+    let mut workflows = WORKFLOWS.lock().unwrap();
+
+    let workflow = match config.get_workflow(name) {
+        Some(workflow) => workflow.clone(),
+        None => {
+            match workflows.defined.iter().find(|defined| defined.name == name) {
+                Some(workflow) => workflow.clone(),
+                None => return None,
+            }
+        }
+    };
+
+    let number = {
+        let number = workflows.run_numbers.entry(name.to_string()).or_insert(0);
+        *number += 1;
+        *number
+    };
+
+    let now = UTC::now().timestamp();
+    let run = WorkflowRun {
+        id: format!("{}-{}", name, number),
+        workflow: name.to_string(),
+        state: RunState::Running,
+        started_at: now,
+        finished_at: None,
+        steps: workflow.steps
+            .iter()
+            .map(|step| {
+                StepRun {
+                    step: step.name.clone(),
+                    after: step.after.clone(),
+                    state: StepState::Waiting,
+                    task: "".to_string(),
+                    attempt: 0,
+                    exit_status: None,
+                    ip: "".to_string(),
+                    node: "".to_string(),
+                    message: "".to_string(),
+                    started_at: None,
+                    finished_at: None,
+                }
+            })
+            .collect(),
+    };
+
+    log_info!("starting workflow"; "workflow" => name, "run" => run.id);
+    let mut active = ActiveRun {
+        workflow: workflow,
+        run: run,
+    };
+    advance(state_manager, &mut active, now);
+
+    let result = active.run.clone();
+    workflows.runs.push(active);
+    Some(result)
+}
+
+/// The latest runs first, optionally of a single workflow.
+pub fn list_runs(workflow: Option<String>, limit: usize) -> Vec<WorkflowRun> {
+    WORKFLOWS.lock()
+        .unwrap()
+        .runs
+        .iter()
+        .rev()
+        .filter(|active| workflow.as_ref().map(|workflow| active.run.workflow == *workflow).unwrap_or(true))
+        .take(limit)
+        .map(|active| active.run.clone())
+        .collect()
+}
+
+pub fn get_run(id: &str) -> Option<WorkflowRun> {
+    WORKFLOWS.lock().unwrap().runs.iter().find(|active| active.run.id == id).map(|active| active.run.clone())
+}
+
+/// Starts a failed step again, the steps after it follow once it succeeds.
+/// Steps that already succeeded aren't run again.
+pub fn retry_step(state_manager: &StateManager, id: &str, step: &str) -> Result<WorkflowRun, &'static str> {
+    let mut workflows = WORKFLOWS.lock().unwrap();
+    let active = match workflows.runs.iter_mut().find(|active| active.run.id == id) {
+        Some(active) => active,
+        None => return Err("unknown_run"),
+    };
+
+    match active.run.steps.iter_mut().find(|step_run| step_run.step == step) {
+        Some(step_run) => {
+            if step_run.state != StepState::Failed {
+                return Err("step_not_failed");
+            }
+            step_run.state = StepState::Waiting;
+        }
+        None => return Err("unknown_step"),
+    }
+
+    log_info!("retrying workflow step"; "run" => id, "step" => step);
+    active.run.state = RunState::Running;
+    active.run.finished_at = None;
+    advance(state_manager, active, UTC::now().timestamp());
+    Ok(active.run.clone())
+}
+
+fn summary(workflow: &Workflow, source: &str) -> WorkflowSummary {
+    WorkflowSummary {
+        name: workflow.name.clone(),
+        source: source.to_string(),
+        steps: workflow.steps
+            .iter()
+            .map(|step| {
+                StepSummary {
+                    name: step.name.clone(),
+                    after: step.after.clone(),
+                }
+            })
+            .collect(),
+    }
+}
+
+fn advance(state_manager: &StateManager, active: &mut ActiveRun, now: i64) {
+    for step_run in active.run.steps.iter_mut() {
+        if step_run.state == StepState::Running {
+            check_step(state_manager, step_run, now);
+        }
+    }
+
+    for idx in 0..active.run.steps.len() {
+        let ready = {
+            let steps = &active.run.steps;
+            steps[idx].state == StepState::Waiting &&
+            steps[idx].after.iter().all(|after| {
+                steps.iter().any(|other| other.step == *after && other.state == StepState::Succeeded)
+            })
+        };
+        if ready {
+            start_step(state_manager, active, idx, now);
+        }
+    }
+
+    let steps = &active.run.steps;
+    if steps.iter().all(|step_run| step_run.state == StepState::Succeeded) {
+        log_info!("workflow succeeded"; "run" => active.run.id);
+        active.run.state = RunState::Succeeded;
+        active.run.finished_at = Some(now);
+    } else if !steps.iter().any(|step_run| step_run.state == StepState::Running) {
+        // whatever is still waiting comes after a failed step
+        log_warn!("workflow failed"; "run" => active.run.id);
+        active.run.state = RunState::Failed;
+        active.run.finished_at = Some(now);
+    }
+}
+
+fn check_step(state_manager: &StateManager, step_run: &mut StepRun, now: i64) {
+    let task = match state_manager.request_task(step_run.task.clone()) {
+        Some(task) => task,
+        None => {
+            step_run.state = StepState::Failed;
+            step_run.message = "the task was killed".to_string();
+            step_run.finished_at = Some(now);
+            return;
+        }
+    };
+
+    let succeeded = match task.state {
+        TaskState::Finished => true,
+        TaskState::Failed => false,
+        _ => return,
+    };
+
+    let job_run = state_manager.request_job_runs(Some(step_run.task.clone()), 1).into_iter().next();
+    step_run.exit_status = job_run.as_ref().and_then(|job_run| job_run.exit_status);
+    step_run.message = job_run.map(|job_run| job_run.message).unwrap_or("".to_string());
+    step_run.ip = task.ip.clone();
+    step_run.node = task.node_name.clone();
+    step_run.finished_at = Some(now);
+    step_run.state = match succeeded {
+        true => StepState::Succeeded,
+        false => StepState::Failed,
+    };
+    log_info!("workflow step completed";
+              "task" => step_run.task,
+              "state" => format!("{:?}", step_run.state));
+}
+
+fn start_step(state_manager: &StateManager, active: &mut ActiveRun, idx: usize, now: i64) {
+    let task = match active.workflow.get_step(&active.run.steps[idx].step) {
+        Some(step) => step.task.clone(),
+        None => return,
+    };
+
+    // earlier steps' outputs go in now, the other references when the task gets placed
+    let arguments = substitute_references(&task.arguments,
+                                          |reference| step_output(&active.run.steps, reference));
+    let parameters = substitute_references(&task.parameters,
+                                           |reference| step_output(&active.run.steps, reference));
+
+    let step_run = &mut active.run.steps[idx];
+    step_run.attempt += 1;
+    step_run.task = match step_run.attempt {
+        1 => format!("{}-{}", active.run.id, step_run.step),
+        attempt => format!("{}-{}-{}", active.run.id, step_run.step, attempt),
+    };
+    step_run.state = StepState::Running;
+    step_run.exit_status = None;
+    step_run.ip = "".to_string();
+    step_run.node = "".to_string();
+    step_run.message = "".to_string();
+    step_run.started_at = Some(now);
+    step_run.finished_at = None;
+
+    log_info!("starting workflow step"; "run" => active.run.id, "task" => step_run.task);
+    state_manager.send_start_task(&step_run.task,
+                                  &0,
+                                  &task.image,
+                                  &task.node_name,
+                                  &task.node_type,
+                                  &task.node_function,
+                                  &task.dependent_service,
+                                  &task.dependencies,
+                                  &task.health_check,
+                                  &arguments,
+                                  &parameters,
+                                  &task.memory,
+                                  &task.cpu,
+                                  &task.volumes,
+                                  &task.privileged,
+                                  &task.sla,
+                                  &task.is_metered,
+                                  &false,
+                                  &task.is_job,
+                                  &task.max_retries,
+                                  &task.network_type,
+                                  &task.constraints);
+}
+
+// `${step:NAME:OUTPUT}` of a step that succeeded
+fn step_output(steps: &Vec<StepRun>, reference: &Reference) -> Option<String> {
+    let (name, output) = match *reference {
+        Reference::StepOutput(ref name, ref output) => (name, output),
+        _ => return None,
+    };
+
+    let step_run = match steps.iter().find(|step_run| step_run.step == *name) {
+        Some(step_run) if step_run.state == StepState::Succeeded => step_run,
+        _ => return None,
+    };
+
+    let value = match output.as_str() {
+        "exit_status" => step_run.exit_status.map(|exit_status| exit_status.to_string()).unwrap_or("".to_string()),
+        "ip" => step_run.ip.clone(),
+        "node" => step_run.node.clone(),
+        "task" => step_run.task.clone(),
+        _ => "".to_string(),
+    };
+    match value.is_empty() {
+        true => None,
+        false => Some(value),
+    }
+}